@@ -0,0 +1,385 @@
+//! Arena allocation for deferred closures. A `ThunkArena` bump-allocates the
+//! environments of the closures given to `ThunkArena::defer`, so creating an
+//! `ArenaThunk` never touches the global allocator once the arena has warmed
+//! up. All of the arena's memory is reclaimed at once by `ThunkArena::reset`,
+//! which requires that every thunk borrowing the arena has been dropped.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+
+use crate::{LazyRef, LazyMut, Lazy};
+use crate::unsync::Thunk;
+
+
+/// The default size, in bytes, of the chunks a `ThunkArena` allocates.
+const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+
+/// A bump arena for the closures of `ArenaThunk`s.
+pub struct ThunkArena {
+    chunks: RefCell<Vec<Chunk>>,
+
+    /// The offset, in bytes, of the first free byte of the last chunk.
+    offset: Cell<usize>,
+
+    chunk_size: usize,
+}
+
+
+/// A chunk of arena memory. It is owned through a raw pointer rather than a
+/// `Box`, because moving or borrowing a `Box` would invalidate the pointers
+/// already handed out into it.
+struct Chunk(*mut [MaybeUninit<usize>]);
+
+
+// A `Chunk` is just memory; what lives in it is borrowed from the arena.
+unsafe impl Send for Chunk {}
+
+
+/// A non-thread-safe thunk whose closure lives in a `ThunkArena`. Apart from
+/// where its closure is stored, an `ArenaThunk` behaves exactly like a
+/// `Thunk`.
+pub struct ArenaThunk<'a, T> {
+    thunk: Thunk<T>,
+    _arena: PhantomData<&'a ThunkArena>,
+}
+
+
+/// An owning pointer to a closure stored in a `ThunkArena`. Dropping the slot
+/// drops the closure in place; the memory itself belongs to the arena.
+struct ArenaSlot<'a, F>(&'a mut ManuallyDrop<F>);
+
+
+impl<'a, F> Drop for ArenaSlot<'a, F> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(self.0) }
+    }
+}
+
+
+impl<'a, T, F: FnOnce() -> T> ArenaSlot<'a, F> {
+    #[inline]
+    fn call(self) -> T {
+        let f = unsafe { ptr::read(&**self.0) };
+        mem::forget(self);
+        f()
+    }
+}
+
+
+impl Chunk {
+    #[inline]
+    fn new(words: usize) -> Chunk {
+        let chunk: Box<[MaybeUninit<usize>]> = (0..words).map(|_| MaybeUninit::uninit()).collect();
+        Chunk(Box::into_raw(chunk))
+    }
+
+
+    #[inline]
+    fn base(&self) -> *mut u8 {
+        self.0 as *mut u8
+    }
+
+
+    /// The size of the chunk in bytes.
+    #[inline]
+    fn len(&self) -> usize {
+        self.0.len() * mem::size_of::<usize>()
+    }
+}
+
+
+impl Drop for Chunk {
+    #[inline]
+    fn drop(&mut self) {
+        mem::drop(unsafe { Box::from_raw(self.0) });
+    }
+}
+
+
+impl Default for ThunkArena {
+    #[inline]
+    fn default() -> ThunkArena {
+        ThunkArena::new()
+    }
+}
+
+
+impl fmt::Debug for ThunkArena {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThunkArena")
+            .field("chunks", &self.chunks.borrow().len())
+            .field("offset", &self.offset.get())
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+
+impl ThunkArena {
+    /// Create an empty arena. No memory is allocated until the first closure
+    /// is deferred.
+    #[inline]
+    pub fn new() -> ThunkArena {
+        ThunkArena::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+
+    /// Create an empty arena which allocates memory in chunks of at least
+    /// `chunk_size` bytes. Closures larger than `chunk_size` get a chunk of
+    /// their own.
+    #[inline]
+    pub fn with_chunk_size(chunk_size: usize) -> ThunkArena {
+        ThunkArena {
+            chunks: RefCell::new(Vec::new()),
+            offset: Cell::new(0),
+            chunk_size,
+        }
+    }
+
+
+    /// Defer a computation, storing its closure in the arena.
+    #[inline]
+    pub fn defer<'a, T, F: FnOnce() -> T + 'a>(&'a self, f: F) -> ArenaThunk<'a, T>
+        where T: 'a
+    {
+        let slot = ArenaSlot(self.alloc(f));
+
+        // The slot is a single pointer, so `Thunk` stores it inline rather
+        // than boxing it.
+        ArenaThunk {
+            thunk: Thunk::defer(move || slot.call()),
+            _arena: PhantomData,
+        }
+    }
+
+
+    /// Create an already-evaluated thunk tied to the arena. This does not
+    /// allocate any arena memory.
+    #[inline]
+    pub fn computed<T>(&self, t: T) -> ArenaThunk<'_, T> {
+        ArenaThunk {
+            thunk: Thunk::computed(t),
+            _arena: PhantomData,
+        }
+    }
+
+
+    /// Free every closure allocated in the arena at once. The most recently
+    /// allocated chunk is kept around to be reused.
+    pub fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        let len = chunks.len();
+
+        if len > 1 {
+            chunks.drain(..len - 1);
+        }
+
+        self.offset.set(0);
+    }
+
+
+    /// The number of bytes of memory currently reserved by the arena.
+    pub fn capacity(&self) -> usize {
+        self.chunks.borrow().iter().map(Chunk::len).sum()
+    }
+
+
+    // Each allocation is a fresh region of a chunk, so handing out a unique
+    // reference to it through a shared reference to the arena is fine.
+    #[allow(clippy::mut_from_ref)]
+    fn alloc<F>(&self, f: F) -> &mut ManuallyDrop<F> {
+        let size = mem::size_of::<F>();
+        let align = mem::align_of::<F>();
+
+        let mut chunks = self.chunks.borrow_mut();
+
+        let fits = chunks.last().and_then(|chunk| {
+            let base = chunk.base();
+            let offset = self.offset.get();
+            let start = offset + unsafe { base.add(offset) }.align_offset(align);
+
+            if start + size <= chunk.len() {
+                Some((base, start))
+            } else {
+                None
+            }
+        });
+
+        let (base, start) = match fits {
+            Some(found) => found,
+            None => {
+                let bytes = self.chunk_size.max(size + align);
+                let words = bytes.div_ceil(mem::size_of::<usize>());
+                let chunk = Chunk::new(words);
+                let base = chunk.base();
+                let start = base.align_offset(align);
+                chunks.push(chunk);
+                (base, start)
+            }
+        };
+
+        self.offset.set(start + size);
+
+        unsafe {
+            let slot = base.add(start) as *mut ManuallyDrop<F>;
+            ptr::write(slot, ManuallyDrop::new(f));
+            &mut *slot
+        }
+    }
+}
+
+
+impl<'a, T> ArenaThunk<'a, T> {
+    /// Force evaluation of the thunk and return a reference to its value.
+    #[inline]
+    pub fn force(&self) -> &T {
+        self.thunk.force()
+    }
+
+
+    /// Force evaluation of the thunk and return a mutable reference to its
+    /// value.
+    #[inline]
+    pub fn force_mut(&mut self) -> &mut T {
+        self.thunk.force_mut()
+    }
+
+
+    /// Check whether the thunk has been evaluated yet.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.thunk.is_evaluated()
+    }
+
+
+    /// Get a reference to the computed value if the thunk has already been
+    /// evaluated, without forcing it.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.thunk.try_get()
+    }
+
+
+    /// Force the thunk and take ownership of its value.
+    #[inline]
+    pub fn unwrap(self) -> T {
+        self.thunk.unwrap()
+    }
+}
+
+
+impl<'a, T> AsRef<T> for ArenaThunk<'a, T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<'a, T> AsMut<T> for ArenaThunk<'a, T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<'a, T> Deref for ArenaThunk<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<'a, T> DerefMut for ArenaThunk<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use std::hint::black_box;
+    #[cfg(feature = "nightly")]
+    use test::Bencher;
+
+    #[test]
+    fn arena_thunk_deferred() {
+        let arena = ThunkArena::new();
+        let thunk = arena.defer(|| black_box(1) + 1);
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 2);
+        assert!(thunk.is_evaluated());
+    }
+
+
+    #[test]
+    fn arena_thunk_large_closure() {
+        let arena = ThunkArena::with_chunk_size(16);
+        let big = [3usize; 16];
+        let thunk = arena.defer(move || big.iter().sum::<usize>());
+        assert_eq!(thunk.unwrap(), 48);
+    }
+
+
+    #[test]
+    fn arena_thunk_drops_unforced() {
+        let counter = Rc::new(());
+        let arena = ThunkArena::new();
+
+        {
+            let captured = counter.clone();
+            let _thunk = arena.defer(move || Rc::strong_count(&captured));
+            assert_eq!(Rc::strong_count(&counter), 2);
+        }
+
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+
+    #[test]
+    fn arena_reset_reuses_memory() {
+        let mut arena = ThunkArena::with_chunk_size(64);
+
+        for round in 0..4 {
+            let thunks: Vec<_> = (0..32usize).map(|i| arena.defer(move || i + round)).collect();
+            assert_eq!(thunks.iter().map(|t| **t).sum::<usize>(), 496 + 32 * round);
+            mem::drop(thunks);
+
+            arena.reset();
+            assert_eq!(arena.capacity(), 64);
+        }
+    }
+
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn defer_arena_closures(b: &mut Bencher) {
+        let mut arena = ThunkArena::new();
+
+        b.iter(|| {
+                   {
+                       let things: Vec<_> = (0..1000usize)
+                           .map(|i| arena.defer(move || black_box([i; 4]).len()))
+                           .collect();
+                       black_box(things);
+                   }
+                   arena.reset();
+               })
+    }
+}
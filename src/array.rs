@@ -0,0 +1,319 @@
+//! Lazily computed sequences. A `LazyArray` computes each of its elements on
+//! first access with a single function shared by all of them, so that no
+//! element needs a closure of its own.
+
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ops::Index;
+use std::ptr;
+
+
+/// A non-thread-safe sequence whose `i`th element is computed by `f(i)` the
+/// first time it is accessed. Formatting a `LazyArray` with `Debug` does not
+/// force it; unevaluated elements are shown as `None`.
+pub struct LazyArray<T, F> {
+    f: F,
+    slots: Vec<Slot<T>>,
+}
+
+
+struct Slot<T> {
+    state: Cell<SlotState>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SlotState {
+    Deferred,
+    Evaluating,
+    Evaluated,
+}
+
+
+/// An iterator over the elements of a `LazyArray`, forcing each element as it
+/// is reached.
+pub struct Iter<'a, T: 'a, F: 'a> {
+    array: &'a LazyArray<T, F>,
+    idx: usize,
+}
+
+
+impl<T> Slot<T> {
+    #[inline]
+    fn deferred() -> Slot<T> {
+        Slot {
+            state: Cell::new(SlotState::Deferred),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+
+    #[inline]
+    fn try_get(&self) -> Option<&T> {
+        match self.state.get() {
+            SlotState::Evaluated => Some(unsafe { &*(*self.value.get()).as_ptr() }),
+            SlotState::Deferred | SlotState::Evaluating => None,
+        }
+    }
+}
+
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        if self.state.get() == SlotState::Evaluated {
+            unsafe { ptr::drop_in_place(self.value.get_mut().as_mut_ptr()) }
+        }
+    }
+}
+
+
+impl<T, F: Fn(usize) -> T> LazyArray<T, F> {
+    /// Create a `LazyArray` of `len` elements, none of which are computed yet.
+    #[inline]
+    pub fn new(len: usize, f: F) -> LazyArray<T, F> {
+        LazyArray {
+            f,
+            slots: (0..len).map(|_| Slot::deferred()).collect(),
+        }
+    }
+
+
+    /// Get a reference to the element at `idx`, computing it if it has not
+    /// been computed yet. Returns `None` if `idx` is out of bounds.
+    ///
+    /// Panics if `f` tries to access the element it is computing.
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        let slot = self.slots.get(idx)?;
+
+        match slot.state.get() {
+            SlotState::Evaluated => {}
+            SlotState::Evaluating => panic!("element {} of a `LazyArray` depends on itself", idx),
+            SlotState::Deferred => {
+                slot.state.set(SlotState::Evaluating);
+
+                // If `f` panics, the slot is left `Evaluating`, and accessing
+                // it again will panic rather than recompute it.
+                let value = (self.f)(idx);
+
+                unsafe {
+                    (*slot.value.get()).as_mut_ptr().write(value);
+                }
+
+                slot.state.set(SlotState::Evaluated);
+            }
+        }
+
+        slot.try_get()
+    }
+
+
+    /// Get a mutable reference to the element at `idx`, computing it if it has
+    /// not been computed yet. Returns `None` if `idx` is out of bounds.
+    #[inline]
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        self.get(idx)?;
+        Some(unsafe { &mut *self.slots[idx].value.get_mut().as_mut_ptr() })
+    }
+
+
+    /// Get a reference to the element at `idx` only if it has already been
+    /// computed.
+    #[inline]
+    pub fn try_get(&self, idx: usize) -> Option<&T> {
+        self.slots.get(idx).and_then(Slot::try_get)
+    }
+
+
+    /// Check whether the element at `idx` has been computed. Returns `false`
+    /// if `idx` is out of bounds.
+    #[inline]
+    pub fn is_evaluated(&self, idx: usize) -> bool {
+        self.try_get(idx).is_some()
+    }
+
+
+    /// The number of elements in the array, computed or not.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+
+    /// Check whether the array has no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+
+    /// Grow or shrink the array to `len` elements. New elements are not
+    /// computed until they are accessed; removed elements are dropped.
+    #[inline]
+    pub fn resize(&mut self, len: usize) {
+        self.slots.resize_with(len, Slot::deferred);
+    }
+
+
+    /// Iterate over the elements of the array, computing each one as it is
+    /// reached.
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, T, F> {
+        Iter { array: self, idx: 0 }
+    }
+
+
+    /// Compute every element, and collect them into a `Vec`.
+    pub fn into_vec(self) -> Vec<T> {
+        for idx in 0..self.len() {
+            self.get(idx);
+        }
+
+        self.slots
+            .into_iter()
+            .map(|mut slot| {
+                slot.state.set(SlotState::Deferred);
+                unsafe { ptr::read(slot.value.get_mut().as_ptr()) }
+            })
+            .collect()
+    }
+}
+
+
+impl<T, F: Fn(usize) -> T> Index<usize> for LazyArray<T, F> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, idx: usize) -> &T {
+        match self.get(idx) {
+            Some(value) => value,
+            None => {
+                panic!("index {} out of bounds for a `LazyArray` of length {}", idx, self.len())
+            }
+        }
+    }
+}
+
+
+impl<'a, T, F: Fn(usize) -> T> Iterator for Iter<'a, T, F> {
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        let value = self.array.get(self.idx)?;
+        self.idx += 1;
+        Some(value)
+    }
+
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.array.len() - self.idx;
+        (remaining, Some(remaining))
+    }
+}
+
+
+impl<'a, T, F: Fn(usize) -> T> IntoIterator for &'a LazyArray<T, F> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, F>;
+
+    #[inline]
+    fn into_iter(self) -> Iter<'a, T, F> {
+        self.iter()
+    }
+}
+
+
+impl<T: fmt::Debug, F> fmt::Debug for LazyArray<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.slots.iter().map(Slot::try_get)).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::rc::Rc;
+
+    #[cfg(feature = "nightly")]
+    use std::hint::black_box;
+    #[cfg(feature = "nightly")]
+    use test::Bencher;
+
+    #[cfg(feature = "nightly")]
+    use crate::LazyRef;
+    #[cfg(feature = "nightly")]
+    use crate::unsync::Thunk;
+
+    #[test]
+    fn lazy_array_computes_on_access() {
+        let calls = Cell::new(0);
+        let squares = LazyArray::new(10, |i| {
+            calls.set(calls.get() + 1);
+            i * i
+        });
+
+        assert_eq!(squares.try_get(3), None);
+        assert_eq!(squares[3], 9);
+        assert_eq!(squares[3], 9);
+        assert!(squares.is_evaluated(3));
+        assert!(!squares.is_evaluated(4));
+        assert_eq!(squares.get(10), None);
+        assert_eq!(calls.get(), 1);
+    }
+
+
+    #[test]
+    fn lazy_array_resize_and_collect() {
+        let mut array = LazyArray::new(2, |i| i + 1);
+        array.get(0);
+        array.resize(4);
+
+        assert_eq!(array.iter().cloned().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+        array.resize(3);
+        *array.get_mut(2).unwrap() += 10;
+
+        assert_eq!(array.into_vec(), vec![1, 2, 13]);
+    }
+
+
+    #[test]
+    fn lazy_array_drops_computed_elements() {
+        let counter = Rc::new(());
+        let array = LazyArray::new(4, |_| counter.clone());
+
+        array.get(1);
+        array.get(3);
+        assert_eq!(Rc::strong_count(&counter), 3);
+
+        drop(array);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn lazy_array_ten_thousand(b: &mut Bencher) {
+        b.iter(|| {
+                   let array = LazyArray::new(10000, |i| black_box(i) ^ 0xff);
+                   black_box(array.iter().fold(0, |acc, x| acc ^ x))
+               })
+    }
+
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn thunk_vec_ten_thousand(b: &mut Bencher) {
+        b.iter(|| {
+                   let thunks: Vec<_> = (0..10000usize)
+                       .map(|i| Thunk::defer(move || black_box(i) ^ 0xff))
+                       .collect();
+                   black_box(thunks.iter().fold(0, |acc, x| acc ^ **x))
+               })
+    }
+}
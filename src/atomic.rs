@@ -0,0 +1,179 @@
+//! The atomic flags behind the thread-safe thunks. On targets without threads,
+//! such as `wasm32-unknown-unknown` without the `atomics` target feature, the
+//! flags are plain cells instead. `AtomicThunk` and `ArcThunk` keep their API
+//! there, so portable crates can depend on them, but they cost no more than
+//! the unsynchronized thunks.
+//!
+//! With the `portable_atomic` feature, the atomics come from `portable-atomic`
+//! instead of `core`, so that they exist even on targets without native
+//! atomic instructions.
+//!
+//! Under `cfg(loom)`, the flags, and the threads which park on them, come from
+//! `loom`, which explores every interleaving of the operations on them. Only
+//! the atomic protocol is modeled; the cells holding the value are not.
+
+pub(crate) use core::sync::atomic::Ordering;
+
+#[cfg(all(not(loom), not(feature = "portable_atomic"), not(all(target_arch = "wasm32", not(target_feature = "atomics")))))]
+pub(crate) use core::sync::atomic::AtomicUsize;
+#[cfg(all(not(loom), feature = "std", not(feature = "portable_atomic"), not(all(target_arch = "wasm32", not(target_feature = "atomics")))))]
+pub(crate) use core::sync::atomic::AtomicBool;
+#[cfg(all(not(feature = "portable_atomic"), target_has_atomic = "64"))]
+pub(crate) use core::sync::atomic::{AtomicU64, AtomicU8};
+
+#[cfg(all(not(loom), feature = "portable_atomic", not(all(target_arch = "wasm32", not(target_feature = "atomics")))))]
+pub(crate) use portable_atomic::AtomicUsize;
+#[cfg(all(not(loom), feature = "std", feature = "portable_atomic", not(all(target_arch = "wasm32", not(target_feature = "atomics")))))]
+pub(crate) use portable_atomic::AtomicBool;
+#[cfg(feature = "portable_atomic")]
+pub(crate) use portable_atomic::{AtomicU64, AtomicU8};
+
+#[cfg(all(not(loom), target_arch = "wasm32", not(target_feature = "atomics")))]
+pub(crate) use self::single_threaded::AtomicUsize;
+#[cfg(all(not(loom), feature = "std", target_arch = "wasm32", not(target_feature = "atomics")))]
+pub(crate) use self::single_threaded::AtomicBool;
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicUsize};
+
+#[cfg(not(loom))]
+pub(crate) use core::hint;
+#[cfg(loom)]
+pub(crate) use loom::hint;
+
+#[cfg(all(feature = "std", not(loom)))]
+pub(crate) use std::thread;
+#[cfg(loom)]
+pub(crate) use loom::thread;
+
+
+/// Read an atomic through a unique reference, without synchronization.
+#[cfg(not(loom))]
+#[inline]
+pub(crate) fn get_unique(atomic: &mut AtomicUsize) -> usize {
+    *atomic.get_mut()
+}
+
+
+/// Write an atomic through a unique reference, without synchronization.
+#[cfg(not(loom))]
+#[inline]
+pub(crate) fn set_unique(atomic: &mut AtomicUsize, value: usize) {
+    *atomic.get_mut() = value;
+}
+
+
+#[cfg(loom)]
+#[inline]
+pub(crate) fn get_unique(atomic: &mut AtomicUsize) -> usize {
+    atomic.with_mut(|value| *value)
+}
+
+
+#[cfg(loom)]
+#[inline]
+pub(crate) fn set_unique(atomic: &mut AtomicUsize, value: usize) {
+    atomic.with_mut(|slot| *slot = value);
+}
+
+
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+mod single_threaded {
+    use core::cell::Cell;
+
+    use super::Ordering;
+
+
+    macro_rules! unsynced {
+        ($name:ident, $t:ty) => {
+            /// A stand-in for the atomic of the same name, for targets where
+            /// only one thread can ever exist. Orderings are ignored.
+            #[derive(Debug, Default)]
+            pub(crate) struct $name(Cell<$t>);
+
+
+            // There is no other thread to share it with.
+            unsafe impl Sync for $name {}
+
+
+            impl $name {
+                #[inline]
+                pub const fn new(value: $t) -> $name {
+                    $name(Cell::new(value))
+                }
+
+
+                #[inline]
+                pub fn load(&self, _: Ordering) -> $t {
+                    self.0.get()
+                }
+
+
+                #[inline]
+                pub fn store(&self, value: $t, _: Ordering) {
+                    self.0.set(value)
+                }
+
+
+                #[inline]
+                pub fn swap(&self, value: $t, _: Ordering) -> $t {
+                    self.0.replace(value)
+                }
+
+
+                #[inline]
+                pub fn compare_exchange(&self, current: $t, new: $t, _: Ordering, _: Ordering) -> Result<$t, $t> {
+                    let old = self.0.get();
+
+                    if old == current {
+                        self.0.set(new);
+                        Ok(old)
+                    } else {
+                        Err(old)
+                    }
+                }
+
+
+                #[inline]
+                pub fn compare_exchange_weak(&self, current: $t, new: $t, success: Ordering, failure: Ordering) -> Result<$t, $t> {
+                    self.compare_exchange(current, new, success, failure)
+                }
+
+
+                #[inline]
+                pub fn get_mut(&mut self) -> &mut $t {
+                    self.0.get_mut()
+                }
+
+
+                #[inline]
+                pub fn into_inner(self) -> $t {
+                    self.0.into_inner()
+                }
+            }
+        };
+    }
+
+
+    #[cfg(feature = "std")]
+    unsynced!(AtomicBool, bool);
+    unsynced!(AtomicUsize, usize);
+
+
+    impl AtomicUsize {
+        #[inline]
+        pub fn fetch_add(&self, value: usize, _: Ordering) -> usize {
+            let old = self.0.get();
+            self.0.set(old.wrapping_add(value));
+            old
+        }
+
+
+        #[inline]
+        pub fn fetch_sub(&self, value: usize, _: Ordering) -> usize {
+            let old = self.0.get();
+            self.0.set(old.wrapping_sub(value));
+            old
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Cooperative cancellation for deferred computations. A `CancellableThunk`
+//! hands its closure a `CancelToken`, which long-running computations should
+//! check periodically; cancelling the thunk before it is forced prevents the
+//! computation from running at all.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::fallible::AtomicTryThunk;
+
+
+/// The error produced by forcing a thunk which has been cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the deferred computation was cancelled")
+    }
+}
+
+
+impl Error for Cancelled {}
+
+
+/// A shared flag used to request cancellation of one or more deferred
+/// computations. Cloning a `CancelToken` produces another handle to the same
+/// flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+
+impl CancelToken {
+    /// Construct a new, uncancelled token.
+    #[inline]
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+
+    /// Request cancellation. This cannot be undone.
+    #[inline]
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+
+    /// Check whether cancellation has been requested.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+
+    /// Return `Err(Cancelled)` if cancellation has been requested. This is
+    /// intended to be used with `?` inside of cancellable computations.
+    #[inline]
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+
+/// A thread-safe thunk whose computation can be cooperatively cancelled. If
+/// the thunk is cancelled before it is forced, forcing it returns
+/// `Err(Cancelled)` without running the closure; if it is cancelled while it
+/// is being forced, it is up to the closure to notice via its `CancelToken`.
+/// Either way, the outcome is cached like any other value.
+pub struct CancellableThunk<T> {
+    token: CancelToken,
+    thunk: AtomicTryThunk<T, Cancelled>,
+}
+
+
+impl<T> CancellableThunk<T> {
+    /// Defer a cancellable computation with a fresh `CancelToken`.
+    #[inline]
+    pub fn defer<'a, F>(f: F) -> CancellableThunk<T>
+        where T: 'a, F: FnOnce(&CancelToken) -> Result<T, Cancelled> + Send + 'a
+    {
+        CancellableThunk::with_token(CancelToken::new(), f)
+    }
+
+
+    /// Defer a cancellable computation controlled by an existing token. A
+    /// single token may be shared between many thunks in order to cancel them
+    /// all at once.
+    pub fn with_token<'a, F>(token: CancelToken, f: F) -> CancellableThunk<T>
+        where T: 'a, F: FnOnce(&CancelToken) -> Result<T, Cancelled> + Send + 'a
+    {
+        let closure_token = token.clone();
+
+        CancellableThunk {
+            token,
+            thunk: AtomicTryThunk::defer(move || {
+                closure_token.check()?;
+                f(&closure_token)
+            }),
+        }
+    }
+
+
+    /// Request cancellation of the thunk's computation. If the thunk has
+    /// already been evaluated, this has no effect on its value.
+    #[inline]
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+
+    /// Check whether cancellation of the thunk has been requested.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+
+    /// The token controlling this thunk's cancellation.
+    #[inline]
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+
+
+    /// Force the thunk, returning a reference to the computed value, or
+    /// `Err(Cancelled)` if the computation was cancelled.
+    #[inline]
+    pub fn force(&self) -> Result<&T, Cancelled> {
+        self.thunk.force().map_err(|&cancelled| cancelled)
+    }
+
+
+    /// Check whether the thunk has already been evaluated - or cancelled -
+    /// without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.thunk.is_evaluated()
+    }
+
+
+    /// Unwrap the thunk into its value, forcing it if necessary.
+    #[inline]
+    pub fn unwrap(self) -> Result<T, Cancelled> {
+        self.thunk.unwrap()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn cancelled_before_force() {
+        let ran = AtomicBool::new(false);
+        let thunk = CancellableThunk::defer(|_| {
+            ran.store(true, Ordering::SeqCst);
+            Ok(1)
+        });
+
+        thunk.cancel();
+        assert_eq!(thunk.force(), Err(Cancelled));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cancelled_while_forcing() {
+        let token = CancelToken::new();
+        let thunk = CancellableThunk::with_token(token.clone(), |token| {
+            for i in 0.. {
+                if i == 10 {
+                    token.cancel();
+                }
+
+                token.check()?;
+            }
+
+            Ok(())
+        });
+
+        assert_eq!(thunk.unwrap(), Err(Cancelled));
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn not_cancelled() {
+        let thunk = CancellableThunk::defer(|token| {
+            token.check()?;
+            Ok(2)
+        });
+
+        assert_eq!(thunk.force(), Ok(&2));
+        thunk.cancel();
+        assert_eq!(thunk.force(), Ok(&2));
+    }
+}
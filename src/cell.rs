@@ -0,0 +1,302 @@
+//! Lock-free thunks for small `Copy` values. An `AtomicCellThunk` keeps the
+//! bits of its computed value in an atomic word, next to a state byte and its
+//! closure, so it never queues or parks threads.
+
+use core::fmt;
+use core::hint;
+use core::marker::PhantomData;
+use crate::atomic::{AtomicU64, AtomicU8, Ordering};
+use crate::erased::never_called;
+
+
+/// A `Copy` value which fits in an atomic word, by way of its bits.
+pub trait CellValue: Copy {
+    /// Convert the value to the bits stored in the atomic word.
+    fn into_bits(self) -> u64;
+
+    /// Convert bits produced by `into_bits` back into the value.
+    fn from_bits(bits: u64) -> Self;
+}
+
+
+macro_rules! cell_value_int {
+    ($($t:ty),*) => {
+        $(
+            impl CellValue for $t {
+                #[inline]
+                fn into_bits(self) -> u64 {
+                    self as u64
+                }
+
+
+                #[inline]
+                fn from_bits(bits: u64) -> $t {
+                    bits as $t
+                }
+            }
+        )*
+    };
+}
+
+
+cell_value_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+
+impl CellValue for bool {
+    #[inline]
+    fn into_bits(self) -> u64 {
+        self as u64
+    }
+
+
+    #[inline]
+    fn from_bits(bits: u64) -> bool {
+        bits != 0
+    }
+}
+
+
+impl CellValue for char {
+    #[inline]
+    fn into_bits(self) -> u64 {
+        self as u64
+    }
+
+
+    #[inline]
+    fn from_bits(bits: u64) -> char {
+        char::from_u32(bits as u32).expect("invalid bits for a `char`")
+    }
+}
+
+
+impl CellValue for f32 {
+    #[inline]
+    fn into_bits(self) -> u64 {
+        self.to_bits() as u64
+    }
+
+
+    #[inline]
+    fn from_bits(bits: u64) -> f32 {
+        f32::from_bits(bits as u32)
+    }
+}
+
+
+impl CellValue for f64 {
+    #[inline]
+    fn into_bits(self) -> u64 {
+        self.to_bits()
+    }
+
+
+    #[inline]
+    fn from_bits(bits: u64) -> f64 {
+        f64::from_bits(bits)
+    }
+}
+
+
+/// No value has been stored yet.
+const CELL_EMPTY: u8 = 0;
+
+/// Some thread is storing its value.
+const CELL_WRITING: u8 = 1;
+
+/// The value has been stored, and can be loaded.
+const CELL_READY: u8 = 2;
+
+
+/// A thread-safe, lock-free thunk holding a small `Copy` value.
+///
+/// Rather than making other threads wait while one thread computes the value,
+/// every thread which finds the thunk unevaluated runs the computation itself,
+/// and the first result to be stored wins. The computation is therefore an
+/// `Fn` rather than an `FnOnce`, and should give the same result every time
+/// it is run. Since the value is stored as the bits of an atomic word, it is
+/// returned by value rather than by reference.
+///
+/// With the default closure type of a function pointer, an `AtomicCellThunk`
+/// can be constructed in a `const` context:
+///
+/// ```
+/// use thunk::AtomicCellThunk;
+///
+/// fn compute() -> u32 {
+///     (1..11).product()
+/// }
+///
+/// static FACTORIAL: AtomicCellThunk<u32> = AtomicCellThunk::new(compute);
+///
+/// assert_eq!(FACTORIAL.force(), 3628800);
+/// ```
+pub struct AtomicCellThunk<T: CellValue, F: Fn() -> T = fn() -> T> {
+    state: AtomicU8,
+    bits: AtomicU64,
+    f: F,
+    _marker: PhantomData<T>,
+}
+
+
+impl<T: CellValue, F: Fn() -> T> AtomicCellThunk<T, F> {
+    /// Defer the computation `f`, which may be run by more than one thread.
+    #[inline]
+    pub const fn new(f: F) -> AtomicCellThunk<T, F> {
+        AtomicCellThunk {
+            state: AtomicU8::new(CELL_EMPTY),
+            bits: AtomicU64::new(0),
+            f,
+            _marker: PhantomData,
+        }
+    }
+
+
+    /// Force the thunk, and return its value. If the thunk is not yet
+    /// evaluated, this runs the computation on this thread, even if another
+    /// thread is running it too.
+    #[inline]
+    pub fn force(&self) -> T {
+        match self.try_get() {
+            Some(value) => value,
+            None => self.force_slow(),
+        }
+    }
+
+
+    #[cold]
+    fn force_slow(&self) -> T {
+        let value = (self.f)();
+
+        // Only the thread which gets to store its value writes `bits`. If some
+        // other thread beat us to it, we return the winner's value instead, so
+        // that every caller sees the same one; the winner is at most a store
+        // away from publishing it.
+        if self.state.compare_exchange(CELL_EMPTY, CELL_WRITING, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            self.bits.store(value.into_bits(), Ordering::Relaxed);
+            self.state.store(CELL_READY, Ordering::Release);
+            return value;
+        }
+
+        loop {
+            if let Some(value) = self.try_get() {
+                return value;
+            }
+
+            hint::spin_loop();
+        }
+    }
+
+
+    /// Get the value if the thunk has already been evaluated. This never runs
+    /// the computation.
+    #[inline]
+    pub fn try_get(&self) -> Option<T> {
+        match self.state.load(Ordering::Acquire) {
+            CELL_READY => Some(T::from_bits(self.bits.load(Ordering::Relaxed))),
+            _ => None,
+        }
+    }
+
+
+    /// Check whether the thunk has been evaluated.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.state.load(Ordering::Acquire) == CELL_READY
+    }
+}
+
+
+impl<T: CellValue> AtomicCellThunk<T> {
+    /// Construct an already evaluated `AtomicCellThunk`.
+    #[inline]
+    pub fn computed(t: T) -> AtomicCellThunk<T> {
+        AtomicCellThunk {
+            state: AtomicU8::new(CELL_READY),
+            bits: AtomicU64::new(t.into_bits()),
+            f: never_called::<T>,
+            _marker: PhantomData,
+        }
+    }
+}
+
+
+impl<T: CellValue> From<T> for AtomicCellThunk<T> {
+    #[inline]
+    fn from(t: T) -> AtomicCellThunk<T> {
+        AtomicCellThunk::computed(t)
+    }
+}
+
+
+impl<T: CellValue + fmt::Debug, F: Fn() -> T> fmt::Debug for AtomicCellThunk<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_get() {
+            Some(value) => f.debug_tuple("AtomicCellThunk").field(&value).finish(),
+            None => f.write_str("AtomicCellThunk(<deferred>)"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(not(feature = "std"))]
+    use std::vec::Vec;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn atomic_cell_thunk_deferred() {
+        let thunk = AtomicCellThunk::new(|| -1.5f32);
+
+        assert!(!thunk.is_evaluated());
+        assert_eq!(thunk.try_get(), None);
+        assert_eq!(thunk.force(), -1.5);
+        assert_eq!(thunk.try_get(), Some(-1.5));
+        assert_eq!(AtomicCellThunk::computed('x').force(), 'x');
+    }
+
+    #[test]
+    fn atomic_cell_thunk_shared() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let thunks: Arc<Vec<_>> = Arc::new((0..1000i64).map(|i| {
+            let runs = runs.clone();
+            AtomicCellThunk::new(move || {
+                runs.fetch_add(1, Ordering::Relaxed);
+                -i
+            })
+        }).collect());
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let thunks = thunks.clone();
+            thread::spawn(move || thunks.iter().map(AtomicCellThunk::force).sum::<i64>())
+        }).collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), -499500);
+        }
+
+        assert!(runs.load(Ordering::Relaxed) >= 1000);
+    }
+
+    #[test]
+    fn atomic_cell_thunk_first_store_wins() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let thunk = {
+            let runs = runs.clone();
+            Arc::new(AtomicCellThunk::new(move || runs.fetch_add(1, Ordering::Relaxed) as u64))
+        };
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let thunk = thunk.clone();
+            thread::spawn(move || thunk.force())
+        }).collect();
+        let values: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+
+        assert!(values.iter().all(|&value| Some(value) == thunk.try_get()));
+    }
+}
@@ -0,0 +1,315 @@
+//! Free-standing combinators for working with collections of thunks. These are
+//! generic over the thunk types involved, so they work equally well with
+//! `Thunk`, `AtomicThunk`, and `Strict`.
+//!
+//! `sequence` and `traverse` may produce any thunk which implements
+//! `LazyLocal`; `sequence_send` and `traverse_send` may also produce
+//! thread-safe thunks, as long as the thunks they collect are `Send`.
+//!
+//! `force_both` and `zip_parallel` force two independent thunks at once, one
+//! of them on a scoped thread, so that at least that one must be thread-safe.
+//!
+//! `force_all`, `force_both`, `zip_parallel`, and the `ForceAll` trait are
+//! re-exported from the crate root.
+//! `ForceAll` is implemented for slices and arrays of thunks, as well as for
+//! tuples of up to twelve references to thunks of differing types.
+
+use std::panic;
+use std::thread;
+
+use crate::{LazyRef, Lazy, LazyLocal, LazySend};
+
+
+/// Turn a collection of thunks into a single thunk of a `Vec` of their values.
+/// None of the thunks are forced until the resulting thunk is; forcing it then
+/// forces every element, in order.
+///
+/// The type of the resulting thunk is picked by the caller, so a collection of
+/// `Thunk`s may just as well be sequenced into an `RcThunk`.
+pub fn sequence<'a, I, L, M>(thunks: I) -> M
+    where I: IntoIterator<Item = L>,
+          L: Lazy + 'a,
+          L::Target: Into<L> + 'a,
+          M: LazyLocal<Target = Vec<L::Target>>,
+          M::Target: Into<M> + 'a
+{
+    let thunks: Vec<L> = thunks.into_iter().collect();
+
+    M::defer(move || thunks.into_iter().map(Lazy::unwrap).collect())
+}
+
+
+/// Like `sequence`, but the resulting thunk may be thread-safe, such as an
+/// `AtomicThunk` or an `ArcThunk`, since the thunks are moved into a `Send`
+/// closure.
+pub fn sequence_send<'a, I, L, M>(thunks: I) -> M
+    where I: IntoIterator<Item = L>,
+          L: Lazy + Send + 'a,
+          L::Target: Into<L> + 'a,
+          M: LazySend<Target = Vec<L::Target>>,
+          M::Target: Into<M> + 'a
+{
+    let thunks: Vec<L> = thunks.into_iter().collect();
+
+    M::defer_send(move || thunks.into_iter().map(Lazy::unwrap).collect())
+}
+
+
+/// Map each element of a collection to a thunk with `f`, and then `sequence`
+/// the results into a single thunk of a `Vec`. `f` itself is run immediately,
+/// but none of the thunks it produces are forced until the resulting thunk is.
+pub fn traverse<'a, I, F, L, M>(items: I, f: F) -> M
+    where I: IntoIterator,
+          F: FnMut(I::Item) -> L,
+          L: Lazy + 'a,
+          L::Target: Into<L> + 'a,
+          M: LazyLocal<Target = Vec<L::Target>>,
+          M::Target: Into<M> + 'a
+{
+    sequence(items.into_iter().map(f))
+}
+
+
+/// Like `traverse`, but the resulting thunk may be thread-safe, as with
+/// `sequence_send`.
+pub fn traverse_send<'a, I, F, L, M>(items: I, f: F) -> M
+    where I: IntoIterator,
+          F: FnMut(I::Item) -> L,
+          L: Lazy + Send + 'a,
+          L::Target: Into<L> + 'a,
+          M: LazySend<Target = Vec<L::Target>>,
+          M::Target: Into<M> + 'a
+{
+    sequence_send(items.into_iter().map(f))
+}
+
+
+/// Force every thunk yielded by an iterator, in order. This is useful for
+/// making sure a batch of thunks is evaluated before handing them off to code
+/// which must not block or perform expensive computations.
+pub fn force_all<'a, I, L>(thunks: I)
+    where I: IntoIterator<Item = &'a L>,
+          L: LazyRef + 'a,
+          L::Target: Into<L>
+{
+    for thunk in thunks {
+        thunk.force();
+    }
+}
+
+
+/// Force two independent thunks at the same time, forcing `a` on a scoped
+/// thread while `b` is forced on this one, and return references to both
+/// values once both are done. If either thunk has already been evaluated, no
+/// thread is spawned. A panic while forcing either thunk is propagated.
+///
+/// `a` may be forced on another thread, so an `AtomicThunk` deferred with a
+/// closure which is not `Send` panics when passed as `a`.
+pub fn force_both<'a, A, B>(a: &'a A, b: &'a B) -> (&'a A::Target, &'a B::Target)
+    where A: LazyRef + Sync,
+          A::Target: Into<A> + Sync,
+          B: LazyRef,
+          B::Target: Into<B>
+{
+    if a.is_evaluated() || b.is_evaluated() {
+        return (a.force(), b.force());
+    }
+
+    thread::scope(|scope| {
+        let forcing_a = scope.spawn(move || a.force());
+        let b = b.force();
+
+        match forcing_a.join() {
+            Ok(a) => (a, b),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    })
+}
+
+
+/// Unwrap two independent thunks at the same time, unwrapping `a` on a scoped
+/// thread while `b` is unwrapped on this one. As with `force_both`, a panic
+/// while forcing either thunk is propagated.
+pub fn zip_parallel<A, B>(a: A, b: B) -> (A::Target, B::Target)
+    where A: Lazy + Send,
+          A::Target: Sized + Send,
+          B: Lazy,
+          B::Target: Sized
+{
+    if a.is_evaluated() || b.is_evaluated() {
+        return (a.unwrap(), b.unwrap());
+    }
+
+    thread::scope(|scope| {
+        let unwrapping_a = scope.spawn(move || a.unwrap());
+        let b = b.unwrap();
+
+        match unwrapping_a.join() {
+            Ok(a) => (a, b),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    })
+}
+
+
+/// The `ForceAll` trait abstracts collections of thunks which can be forced in
+/// a single call.
+pub trait ForceAll {
+    /// Force every thunk in the collection.
+    fn force_all(&self);
+}
+
+
+impl<L: LazyRef> ForceAll for [L]
+    where L::Target: Into<L>
+{
+    #[inline]
+    fn force_all(&self) {
+        force_all(self);
+    }
+}
+
+
+impl<L: LazyRef, const N: usize> ForceAll for [L; N]
+    where L::Target: Into<L>
+{
+    #[inline]
+    fn force_all(&self) {
+        force_all(self);
+    }
+}
+
+
+macro_rules! tuple_force_all {
+    ($($name:ident),+) => {
+        impl<'a, $($name),+> ForceAll for ($(&'a $name,)+)
+            where $($name: LazyRef, $name::Target: Into<$name>),+
+        {
+            #[inline]
+            #[allow(non_snake_case)]
+            fn force_all(&self) {
+                let ($($name,)+) = *self;
+                $($name.force();)+
+            }
+        }
+    };
+}
+
+
+tuple_force_all!(A);
+tuple_force_all!(A, B);
+tuple_force_all!(A, B, C);
+tuple_force_all!(A, B, C, D);
+tuple_force_all!(A, B, C, D, E);
+tuple_force_all!(A, B, C, D, E, F);
+tuple_force_all!(A, B, C, D, E, F, G);
+tuple_force_all!(A, B, C, D, E, F, G, H);
+tuple_force_all!(A, B, C, D, E, F, G, H, I);
+tuple_force_all!(A, B, C, D, E, F, G, H, I, J);
+tuple_force_all!(A, B, C, D, E, F, G, H, I, J, K);
+tuple_force_all!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::hint::black_box;
+
+    use crate::{Thunk, RcThunk, AtomicThunk, ArcThunk, Strict};
+
+    #[test]
+    fn sequence_thunks() {
+        let thunks = vec![Thunk::defer(|| black_box(1)), Thunk::computed(2)];
+        let sequenced: Thunk<Vec<i32>> = sequence(thunks);
+
+        assert!(!sequenced.is_evaluated());
+        assert_eq!(*sequenced, vec![1, 2]);
+
+        let strict: RcThunk<Vec<i32>> = sequence(vec![Strict::computed(1), Strict::computed(2)]);
+
+        assert_eq!(*strict, vec![1, 2]);
+    }
+
+    #[test]
+    fn traverse_thunks() {
+        let traversed: RcThunk<Vec<i32>> =
+            traverse(1..4, |i| Thunk::defer(move || black_box(i) * 10));
+
+        assert!(!traversed.is_evaluated());
+        assert_eq!(*traversed, vec![10, 20, 30]);
+
+        let traversed: AtomicThunk<Vec<i32>> =
+            traverse_send(1..4, |i| AtomicThunk::defer(move || black_box(i) * 10));
+
+        assert!(!traversed.is_evaluated());
+        assert_eq!(*traversed, vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn sequence_send_stays_deferred() {
+        let thunks: Vec<_> = (1..4).map(|i| AtomicThunk::defer(move || black_box(i))).collect();
+        let sequenced: ArcThunk<Vec<i32>> = sequence_send(thunks);
+
+        assert!(!sequenced.is_evaluated());
+        assert_eq!(*sequenced, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn force_all_thunks() {
+        let thunks: Vec<_> = (0..4).map(|i| Thunk::defer(move || black_box(i))).collect();
+
+        thunks[..2].force_all();
+        assert!(thunks[1].is_evaluated());
+        assert!(!thunks[2].is_evaluated());
+        force_all(&thunks);
+        assert!(thunks.iter().all(LazyRef::is_evaluated));
+    }
+
+    #[test]
+    fn force_all_tuples_and_arrays() {
+        let a = Thunk::defer(|| black_box(1));
+        let b = AtomicThunk::defer(|| "two");
+        let c = Strict::computed(3.0);
+
+        (&a, &b, &c).force_all();
+        assert!(a.is_evaluated() && b.is_evaluated());
+
+        let array = [RcThunk::defer(|| black_box(1)), RcThunk::defer(|| 2)];
+
+        ForceAll::force_all(&array);
+        assert!(array.iter().all(LazyRef::is_evaluated));
+    }
+
+    #[test]
+    fn force_both_in_parallel() {
+        let here = thread::current().id();
+        let a = AtomicThunk::defer_send(|| thread::current().id());
+        let b = Thunk::defer(|| thread::current().id());
+
+        let (&a_thread, &b_thread) = force_both(&a, &b);
+        assert_ne!(a_thread, here);
+        assert_eq!(b_thread, here);
+
+        let c = AtomicThunk::computed(1);
+        let d = Strict::computed(2);
+        assert_eq!(force_both(&c, &d), (&1, &2));
+    }
+
+    #[test]
+    fn zip_parallel_thunks() {
+        let a = AtomicThunk::defer_send(|| vec![black_box(1); 3]);
+        let b = Thunk::defer(|| "b");
+
+        assert_eq!(zip_parallel(a, b), (vec![1, 1, 1], "b"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn force_both_propagates_panic() {
+        let a: AtomicThunk<()> = AtomicThunk::defer_send(|| panic!("left failed"));
+        let b = Thunk::defer(|| ());
+
+        force_both(&a, &b);
+    }
+}
@@ -0,0 +1,339 @@
+//! Thunks packed into two words. A `CompactThunk` keeps its state in the
+//! pointer to its boxed closure, so that a thunk of a value no larger than a
+//! word - a `Box`, an `Rc`, or an integer - is just two words long, half the
+//! size of the corresponding `Thunk`.
+
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+use core::borrow::{Borrow, BorrowMut};
+use core::cell::{Cell, UnsafeCell};
+use core::mem::{self, ManuallyDrop, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+use crate::{LazyRef, LazyMut, Lazy, LazyLocal, LazySend};
+use crate::erased::ErasedClosure;
+
+
+/// The thunk has been evaluated, and its value is initialized.
+const EVALUATED: usize = 1;
+
+/// The closure is running. It has been moved out of its box, and the value is
+/// not initialized yet.
+const EVALUATING: usize = 2;
+
+/// The closure panicked, leaving neither a closure nor a value.
+const POISONED: usize = 3;
+
+
+// A boxed closure is aligned to at least a word, so its address can never be
+// mistaken for one of the states above.
+const _: () = assert!(mem::align_of::<ErasedClosure<()>>() > POISONED);
+
+
+/// A non-thread-safe thunk which stores its closure in a box, and its state in
+/// the pointer to that box. It takes one word more than its value, where a
+/// `Thunk` takes a flag and room for an inline closure as well. In exchange,
+/// deferring a computation always allocates, and forcing it frees the box
+/// again.
+pub struct CompactThunk<T> {
+    /// While the thunk is deferred, a pointer to its boxed closure; otherwise,
+    /// one of `EVALUATED`, `EVALUATING`, or `POISONED`.
+    state: Cell<*mut ErasedClosure<T>>,
+
+    /// The computed value, which is initialized only in the `EVALUATED`
+    /// state.
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+
+/// Poisons a compact thunk if its computation unwinds. On success, the guard
+/// is forgotten and the thunk marked evaluated instead.
+struct PoisonOnUnwind<'a, T: 'a>(&'a Cell<*mut ErasedClosure<T>>);
+
+
+impl<'a, T> Drop for PoisonOnUnwind<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.set(ptr::without_provenance_mut(POISONED));
+    }
+}
+
+
+impl<T> Drop for CompactThunk<T> {
+    fn drop(&mut self) {
+        match self.state() {
+            EVALUATED => unsafe { ptr::drop_in_place(self.value.get_mut().as_mut_ptr()) },
+            EVALUATING | POISONED => {}
+            _ => mem::drop(unsafe { Box::from_raw(self.state.get()) }),
+        }
+    }
+}
+
+
+impl<T> CompactThunk<T> {
+    #[inline]
+    fn state(&self) -> usize {
+        self.state.get().addr()
+    }
+
+
+    /// Get a reference to the computed value if the thunk has already been
+    /// evaluated. This never forces the thunk; if it is still deferred, `None`
+    /// is returned.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        match self.state() {
+            EVALUATED => Some(unsafe { &*(*self.value.get()).as_ptr() }),
+            _ => None,
+        }
+    }
+
+
+    #[cold]
+    fn evaluate(&self) {
+        match self.state() {
+            EVALUATED => {}
+            EVALUATING => panic!("<<loop>>: attempted to force a `CompactThunk` from within its own computation"),
+            POISONED => panic!("attempted to force a `CompactThunk` whose computation panicked"),
+            _ => {
+                let closure = *unsafe { Box::from_raw(self.state.get()) };
+                self.state.set(ptr::without_provenance_mut(EVALUATING));
+                let poison = PoisonOnUnwind(&self.state);
+
+                let value = closure.call();
+
+                unsafe {
+                    (*self.value.get()).as_mut_ptr().write(value);
+                }
+
+                mem::forget(poison);
+                self.state.set(ptr::without_provenance_mut(EVALUATED));
+            }
+        }
+    }
+}
+
+
+impl<T> Borrow<T> for CompactThunk<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T> BorrowMut<T> for CompactThunk<T> {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<T> AsRef<T> for CompactThunk<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T> AsMut<T> for CompactThunk<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<T> Deref for CompactThunk<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T> DerefMut for CompactThunk<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+impl<T> From<T> for CompactThunk<T> {
+    #[inline]
+    fn from(t: T) -> CompactThunk<T> {
+        CompactThunk {
+            state: Cell::new(ptr::without_provenance_mut(EVALUATED)),
+            value: UnsafeCell::new(MaybeUninit::new(t)),
+        }
+    }
+}
+
+
+impl<T> LazyRef for CompactThunk<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        if self.state() != EVALUATED {
+            self.evaluate();
+        }
+
+        unsafe { &*(*self.value.get()).as_ptr() }
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.state() == EVALUATED
+    }
+}
+
+
+impl<T> LazyLocal for CompactThunk<T> {
+    #[inline]
+    fn defer_local<'a, F: FnOnce() -> T + 'a>(f: F) -> CompactThunk<T>
+        where T: 'a
+    {
+        CompactThunk {
+            state: Cell::new(Box::into_raw(Box::new(ErasedClosure::new(f)))),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+
+impl<T> LazySend for CompactThunk<T> {
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> CompactThunk<T>
+        where T: 'a
+    {
+        CompactThunk::defer(f)
+    }
+}
+
+
+impl<T> LazyMut for CompactThunk<T> {
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        self.force();
+
+        unsafe { &mut *self.value.get_mut().as_mut_ptr() }
+    }
+}
+
+
+impl<T> Lazy for CompactThunk<T> {
+    #[inline]
+    fn unwrap(self) -> T {
+        self.force();
+
+        let this = ManuallyDrop::new(self);
+        unsafe { ptr::read((*this.value.get()).as_ptr()) }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::rc::Rc;
+    #[cfg(all(feature = "nightly", not(feature = "std")))]
+    use std::vec::Vec;
+
+    use std::hint::black_box;
+    #[cfg(feature = "nightly")]
+    use test::Bencher;
+
+    use crate::unsync::Thunk;
+
+    #[test]
+    fn compact_thunk_size() {
+        let word = mem::size_of::<usize>();
+
+        assert_eq!(mem::size_of::<Thunk<Box<u32>>>(), 4 * word);
+        assert_eq!(mem::size_of::<CompactThunk<Box<u32>>>(), 2 * word);
+        assert_eq!(mem::size_of::<CompactThunk<Rc<str>>>(), 3 * word);
+        assert_eq!(mem::size_of::<CompactThunk<u8>>(), 2 * word);
+    }
+
+
+    #[test]
+    fn compact_thunk_deferred() {
+        let thunk = CompactThunk::defer(|| Box::new(black_box(1) + 1));
+
+        assert!(!thunk.is_evaluated());
+        assert_eq!(thunk.try_get(), None);
+        assert_eq!(**thunk, 2);
+        assert!(thunk.is_evaluated());
+        assert_eq!(thunk.try_get().map(|b| **b), Some(2));
+    }
+
+
+    #[test]
+    fn compact_thunk_mutate_and_unwrap() {
+        let mut thunk = CompactThunk::defer(|| vec![1, 2]);
+        thunk.push(3);
+
+        assert_eq!(thunk.unwrap(), [1, 2, 3]);
+        assert_eq!(CompactThunk::computed(5).unwrap(), 5);
+    }
+
+
+    #[test]
+    fn compact_thunk_drops_in_every_state() {
+        let counter = Rc::new(());
+
+        let deferred = {
+            let captured = counter.clone();
+            CompactThunk::defer(move || captured)
+        };
+        let evaluated = {
+            let captured = counter.clone();
+            CompactThunk::defer(move || captured)
+        };
+        evaluated.force();
+        let computed = CompactThunk::computed(counter.clone());
+
+        assert_eq!(Rc::strong_count(&counter), 4);
+
+        mem::drop((deferred, evaluated, computed));
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+
+    #[test]
+    fn compact_thunk_panic_poisons() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let thunk = CompactThunk::<Box<u32>>::defer(|| panic!("boom"));
+
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| thunk.force().clone())).is_err());
+        assert!(!thunk.is_evaluated());
+
+        let again = panic::catch_unwind(AssertUnwindSafe(|| thunk.force().clone())).unwrap_err();
+        assert_eq!(again.downcast_ref::<&str>(),
+                   Some(&"attempted to force a `CompactThunk` whose computation panicked"));
+    }
+
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn compact_thunk_deref(b: &mut Bencher) {
+        let thunks: Vec<_> = (0..1000usize).map(|i| CompactThunk::defer(move || Box::new(black_box(i)))).collect();
+        b.iter(|| thunks.iter().map(|thunk| ***thunk).sum::<usize>())
+    }
+
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn thunk_deref(b: &mut Bencher) {
+        let thunks: Vec<_> = (0..1000usize).map(|i| Thunk::defer(move || Box::new(black_box(i)))).collect();
+        b.iter(|| thunks.iter().map(|thunk| ***thunk).sum::<usize>())
+    }
+}
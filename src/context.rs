@@ -0,0 +1,344 @@
+//! Thunks whose computations need a context which is only available when they
+//! are forced, such as a database handle or an arena. The context is passed to
+//! `force_with`, and the deferred closure is run with it exactly once; later
+//! calls ignore their context and return the cached value.
+
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use crate::{Lazy, LazyRef};
+use crate::sync::AtomicLateThunk;
+use crate::unsync::LateThunk;
+
+
+/// The deferred computation of a `LazyWith`.
+type Init<Ctx, T> = Box<dyn FnOnce(&Ctx) -> T>;
+
+/// The deferred computation of an `AtomicLazyWith`.
+type SendInit<Ctx, T> = Box<dyn FnOnce(&Ctx) -> T + Send>;
+
+
+/// A non-thread-safe thunk whose computation is supplied a context of type
+/// `Ctx` when it is forced.
+pub struct LazyWith<Ctx: ?Sized, T> {
+    value: LateThunk<T>,
+    init: Cell<Option<Init<Ctx, T>>>,
+}
+
+
+/// A thread-safe thunk whose computation is supplied a context of type `Ctx`
+/// when it is forced. If several threads force it at once, only one of their
+/// contexts is used, and the others wait for the result.
+pub struct AtomicLazyWith<Ctx: ?Sized, T> {
+    value: AtomicLateThunk<T>,
+    init: Mutex<Option<SendInit<Ctx, T>>>,
+}
+
+
+/// A reference-counted, non-thread-safe `LazyWith`.
+pub struct RcLazyWith<Ctx: ?Sized, T>(Rc<LazyWith<Ctx, T>>);
+
+
+/// An atomically reference-counted, thread-safe `AtomicLazyWith`.
+pub struct ArcLazyWith<Ctx: ?Sized, T>(Arc<AtomicLazyWith<Ctx, T>>);
+
+
+impl<Ctx: ?Sized, T> LazyWith<Ctx, T> {
+    /// Defer a computation which needs a context.
+    #[inline]
+    pub fn defer<F: FnOnce(&Ctx) -> T + 'static>(f: F) -> LazyWith<Ctx, T> {
+        LazyWith {
+            value: LateThunk::new(),
+            init: Cell::new(Some(Box::new(f))),
+        }
+    }
+
+
+    /// Construct a `LazyWith` from an already computed value. Forcing it
+    /// ignores the context.
+    #[inline]
+    pub fn computed(t: T) -> LazyWith<Ctx, T> {
+        LazyWith {
+            value: LateThunk::computed(t),
+            init: Cell::new(None),
+        }
+    }
+
+
+    /// Force the computation with the given context, returning a reference to
+    /// the value. If the value has already been computed, `ctx` is unused.
+    #[inline]
+    pub fn force_with(&self, ctx: &Ctx) -> &T {
+        self.value.get_or_init(|| {
+            let init = self.init
+                .take()
+                .expect("a previous attempt to force a `LazyWith` panicked");
+            init(ctx)
+        })
+    }
+
+
+    /// Get a reference to the value if it has already been computed.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+
+    /// Check whether the value has been computed yet.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.value.get().is_some()
+    }
+
+
+    /// Force the computation with the given context and take ownership of the
+    /// value.
+    #[inline]
+    pub fn unwrap_with(self, ctx: &Ctx) -> T {
+        self.force_with(ctx);
+        self.value.unwrap()
+    }
+}
+
+
+impl<Ctx: ?Sized, T> From<T> for LazyWith<Ctx, T> {
+    #[inline]
+    fn from(t: T) -> LazyWith<Ctx, T> {
+        LazyWith::computed(t)
+    }
+}
+
+
+impl<Ctx: ?Sized, T: fmt::Debug> fmt::Debug for LazyWith<Ctx, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("LazyWith").field(&self.try_get()).finish()
+    }
+}
+
+
+impl<Ctx: ?Sized, T> AtomicLazyWith<Ctx, T> {
+    /// Defer a computation which needs a context.
+    #[inline]
+    pub fn defer<F: FnOnce(&Ctx) -> T + Send + 'static>(f: F) -> AtomicLazyWith<Ctx, T> {
+        AtomicLazyWith {
+            value: AtomicLateThunk::new(),
+            init: Mutex::new(Some(Box::new(f))),
+        }
+    }
+
+
+    /// Construct an `AtomicLazyWith` from an already computed value. Forcing
+    /// it ignores the context.
+    #[inline]
+    pub fn computed(t: T) -> AtomicLazyWith<Ctx, T> {
+        AtomicLazyWith {
+            value: AtomicLateThunk::computed(t),
+            init: Mutex::new(None),
+        }
+    }
+
+
+    /// Force the computation with the given context, returning a reference to
+    /// the value. If the value has already been computed, or another thread is
+    /// computing it, `ctx` is unused.
+    #[inline]
+    pub fn force_with(&self, ctx: &Ctx) -> &T {
+        self.value.get_or_init(|| {
+            let init = self.init
+                .lock()
+                .unwrap()
+                .take()
+                .expect("a previous attempt to force an `AtomicLazyWith` panicked");
+            init(ctx)
+        })
+    }
+
+
+    /// Get a reference to the value if it has already been computed. This
+    /// never blocks.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+
+    /// Check whether the value has been computed yet.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.value.get().is_some()
+    }
+
+
+    /// Force the computation with the given context and take ownership of the
+    /// value.
+    #[inline]
+    pub fn unwrap_with(self, ctx: &Ctx) -> T {
+        self.force_with(ctx);
+        self.value.unwrap()
+    }
+}
+
+
+impl<Ctx: ?Sized, T> From<T> for AtomicLazyWith<Ctx, T> {
+    #[inline]
+    fn from(t: T) -> AtomicLazyWith<Ctx, T> {
+        AtomicLazyWith::computed(t)
+    }
+}
+
+
+impl<Ctx: ?Sized, T: fmt::Debug> fmt::Debug for AtomicLazyWith<Ctx, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("AtomicLazyWith").field(&self.try_get()).finish()
+    }
+}
+
+
+impl<Ctx: ?Sized, T> Clone for RcLazyWith<Ctx, T> {
+    #[inline]
+    fn clone(&self) -> RcLazyWith<Ctx, T> {
+        RcLazyWith(self.0.clone())
+    }
+}
+
+
+impl<Ctx: ?Sized, T> RcLazyWith<Ctx, T> {
+    /// Defer a computation which needs a context.
+    #[inline]
+    pub fn defer<F: FnOnce(&Ctx) -> T + 'static>(f: F) -> RcLazyWith<Ctx, T> {
+        RcLazyWith(Rc::new(LazyWith::defer(f)))
+    }
+
+
+    /// Construct an `RcLazyWith` from an already computed value.
+    #[inline]
+    pub fn computed(t: T) -> RcLazyWith<Ctx, T> {
+        RcLazyWith(Rc::new(LazyWith::computed(t)))
+    }
+
+
+    /// Force the computation with the given context, returning a reference to
+    /// the value. The computation is shared between all clones.
+    #[inline]
+    pub fn force_with(&self, ctx: &Ctx) -> &T {
+        self.0.force_with(ctx)
+    }
+
+
+    /// Get a reference to the value if it has already been computed.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.0.try_get()
+    }
+
+
+    /// Check whether the value has been computed yet.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<Ctx: ?Sized, T> Clone for ArcLazyWith<Ctx, T> {
+    #[inline]
+    fn clone(&self) -> ArcLazyWith<Ctx, T> {
+        ArcLazyWith(self.0.clone())
+    }
+}
+
+
+impl<Ctx: ?Sized, T> ArcLazyWith<Ctx, T> {
+    /// Defer a computation which needs a context.
+    #[inline]
+    pub fn defer<F: FnOnce(&Ctx) -> T + Send + 'static>(f: F) -> ArcLazyWith<Ctx, T> {
+        ArcLazyWith(Arc::new(AtomicLazyWith::defer(f)))
+    }
+
+
+    /// Construct an `ArcLazyWith` from an already computed value.
+    #[inline]
+    pub fn computed(t: T) -> ArcLazyWith<Ctx, T> {
+        ArcLazyWith(Arc::new(AtomicLazyWith::computed(t)))
+    }
+
+
+    /// Force the computation with the given context, returning a reference to
+    /// the value. The computation is shared between all clones.
+    #[inline]
+    pub fn force_with(&self, ctx: &Ctx) -> &T {
+        self.0.force_with(ctx)
+    }
+
+
+    /// Get a reference to the value if it has already been computed. This
+    /// never blocks.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.0.try_get()
+    }
+
+
+    /// Check whether the value has been computed yet.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+
+    struct Database {
+        answer: usize,
+    }
+
+
+    #[test]
+    fn lazy_with_runs_once() {
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let lazy = LazyWith::defer(move |db: &Database| {
+            counter.set(counter.get() + 1);
+            db.answer * 2
+        });
+
+        assert!(!lazy.is_evaluated());
+        assert_eq!(*lazy.force_with(&Database { answer: 21 }), 42);
+        assert_eq!(*lazy.force_with(&Database { answer: 0 }), 42);
+        assert_eq!(runs.get(), 1);
+        assert_eq!(lazy.unwrap_with(&Database { answer: 0 }), 42);
+    }
+
+
+    #[test]
+    fn rc_lazy_with_shared() {
+        let lazy = RcLazyWith::defer(|s: &str| s.len());
+        let other = lazy.clone();
+
+        assert_eq!(*lazy.force_with("four"), 4);
+        assert_eq!(other.try_get(), Some(&4));
+    }
+
+
+    #[test]
+    fn arc_lazy_with_threads() {
+        let lazy = ArcLazyWith::defer(|db: &Database| db.answer + 1);
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let lazy = lazy.clone();
+            thread::spawn(move || *lazy.force_with(&Database { answer: 41 }))
+        }).collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 42);
+        }
+    }
+}
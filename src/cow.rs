@@ -0,0 +1,382 @@
+//! Lazy clone-on-write. A `LazyCow` dereferences to borrowed data for as long
+//! as it is only read, and defers producing an owned copy - by cloning, or by
+//! any other closure which turns the borrowed data into an owned value - until
+//! it is mutated or unwrapped. A `LazyClone` does the same for a borrowed
+//! value which is simply cloned, and implements the `Lazy` traits.
+
+use std::borrow::{Borrow, Cow};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use crate::{LazyRef, LazyMut, Lazy, LazyLocal, LazySend};
+use crate::unsync::Thunk;
+
+
+/// A clone-on-write smart pointer which lazily materializes its owned value.
+///
+/// Until the owned value is demanded, a `LazyCow` dereferences to the borrowed
+/// `&'a B` it was constructed from. The closure `F` is only run the first time
+/// the `LazyCow` is mutated through `to_mut`, or unwrapped through
+/// `into_owned`; it should produce an owned value which borrows as the same `B`
+/// it was given, so that materializing does not change what the `LazyCow`
+/// dereferences to.
+pub struct LazyCow<'a, B: ?Sized + ToOwned + 'a, F = fn(&'a B) -> <B as ToOwned>::Owned> {
+    borrowed: &'a B,
+
+    /// Exactly one of `owned` and `materialize` is `Some`, except after
+    /// `materialize` has panicked.
+    owned: Option<B::Owned>,
+    materialize: Option<F>,
+}
+
+
+impl<'a, B: ?Sized + ToOwned + 'a> LazyCow<'a, B> {
+    /// Borrow `b`, cloning it with `ToOwned::to_owned` if an owned value is
+    /// ever needed.
+    #[inline]
+    pub fn borrowed(b: &'a B) -> LazyCow<'a, B> {
+        LazyCow::with(b, B::to_owned as fn(&'a B) -> B::Owned)
+    }
+}
+
+
+impl<'a, B: ?Sized + ToOwned + 'a, F: FnOnce(&'a B) -> B::Owned> LazyCow<'a, B, F> {
+    /// Borrow `b`, deferring `f` to produce an owned value if one is ever
+    /// needed.
+    #[inline]
+    pub fn with(b: &'a B, f: F) -> LazyCow<'a, B, F> {
+        LazyCow {
+            borrowed: b,
+            owned: None,
+            materialize: Some(f),
+        }
+    }
+
+
+    /// Check whether the owned value has been materialized.
+    #[inline]
+    pub fn is_owned(&self) -> bool {
+        self.owned.is_some()
+    }
+
+
+    /// Materialize the owned value if necessary, and get a mutable reference
+    /// to it.
+    pub fn to_mut(&mut self) -> &mut B::Owned {
+        if self.owned.is_none() {
+            let f = self.materialize
+                .take()
+                .expect("a previous attempt to materialize a `LazyCow` panicked");
+            self.owned = Some(f(self.borrowed));
+        }
+
+        match self.owned {
+            Some(ref mut owned) => owned,
+            None => unreachable!(),
+        }
+    }
+
+
+    /// Materialize the owned value if necessary, and take ownership of it.
+    #[inline]
+    pub fn into_owned(mut self) -> B::Owned {
+        self.to_mut();
+        self.owned.take().unwrap()
+    }
+
+
+    /// Convert to a `Cow` without materializing the owned value.
+    #[inline]
+    pub fn into_cow(self) -> Cow<'a, B> {
+        match self.owned {
+            Some(owned) => Cow::Owned(owned),
+            None => Cow::Borrowed(self.borrowed),
+        }
+    }
+}
+
+
+impl<'a, B: ?Sized + ToOwned + 'a, F> AsRef<B> for LazyCow<'a, B, F> {
+    #[inline]
+    fn as_ref(&self) -> &B {
+        self
+    }
+}
+
+
+impl<'a, B: ?Sized + ToOwned + 'a, F> Borrow<B> for LazyCow<'a, B, F> {
+    #[inline]
+    fn borrow(&self) -> &B {
+        self
+    }
+}
+
+
+impl<'a, B: ?Sized + ToOwned + 'a, F> Deref for LazyCow<'a, B, F> {
+    type Target = B;
+
+    #[inline]
+    fn deref(&self) -> &B {
+        match self.owned {
+            Some(ref owned) => owned.borrow(),
+            None => self.borrowed,
+        }
+    }
+}
+
+
+impl<'a, B: ?Sized + ToOwned + 'a, F: Clone> Clone for LazyCow<'a, B, F>
+    where B::Owned: Clone
+{
+    #[inline]
+    fn clone(&self) -> LazyCow<'a, B, F> {
+        LazyCow {
+            borrowed: self.borrowed,
+            owned: self.owned.clone(),
+            materialize: self.materialize.clone(),
+        }
+    }
+}
+
+
+impl<'a, B: ?Sized + ToOwned + fmt::Debug + 'a, F> fmt::Debug for LazyCow<'a, B, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LazyCow")
+            .field("value", &&**self)
+            .field("owned", &self.owned.is_some())
+            .finish()
+    }
+}
+
+
+impl<'a, B: ?Sized + ToOwned + 'a> From<&'a B> for LazyCow<'a, B> {
+    #[inline]
+    fn from(b: &'a B) -> LazyCow<'a, B> {
+        LazyCow::borrowed(b)
+    }
+}
+
+
+/// A borrowed value which is cloned into an owned one only when it is mutated
+/// or unwrapped. Unlike `LazyCow`, a `LazyClone` implements the `Lazy` traits:
+/// it may also hold an owned value, computed or deferred, from the start.
+pub struct LazyClone<'a, T: 'a>(LazyCloneInner<'a, T>);
+
+
+enum LazyCloneInner<'a, T: 'a> {
+    Borrowed(&'a T),
+    Owned(Thunk<T>),
+}
+
+
+impl<'a, T: 'a> LazyClone<'a, T> {
+    /// Borrow `t`, cloning it only if an owned value is needed.
+    #[inline]
+    pub fn borrowed(t: &'a T) -> LazyClone<'a, T> {
+        LazyClone(LazyCloneInner::Borrowed(t))
+    }
+
+
+    /// Check whether the `LazyClone` holds an owned value, either because it
+    /// was constructed with one or because the borrowed value was cloned.
+    #[inline]
+    pub fn is_owned(&self) -> bool {
+        match self.0 {
+            LazyCloneInner::Borrowed(_) => false,
+            LazyCloneInner::Owned(_) => true,
+        }
+    }
+}
+
+
+impl<'a, T: 'a> AsRef<T> for LazyClone<'a, T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<'a, T: Clone + 'a> AsMut<T> for LazyClone<'a, T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<'a, T: 'a> Deref for LazyClone<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<'a, T: Clone + 'a> DerefMut for LazyClone<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+impl<'a, T: 'a> From<T> for LazyClone<'a, T> {
+    #[inline]
+    fn from(t: T) -> LazyClone<'a, T> {
+        LazyClone(LazyCloneInner::Owned(Thunk::computed(t)))
+    }
+}
+
+
+impl<'a, T: 'a> From<&'a T> for LazyClone<'a, T> {
+    #[inline]
+    fn from(t: &'a T) -> LazyClone<'a, T> {
+        LazyClone::borrowed(t)
+    }
+}
+
+
+impl<'a, T: 'a> LazyRef for LazyClone<'a, T> {
+    #[inline]
+    fn force(&self) -> &T {
+        match self.0 {
+            LazyCloneInner::Borrowed(t) => t,
+            LazyCloneInner::Owned(ref thunk) => thunk.force(),
+        }
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        match self.0 {
+            LazyCloneInner::Borrowed(_) => true,
+            LazyCloneInner::Owned(ref thunk) => thunk.is_evaluated(),
+        }
+    }
+}
+
+
+impl<'a, T: 'a> LazyLocal for LazyClone<'a, T> {
+    #[inline]
+    fn defer_local<'b, F: FnOnce() -> T + 'b>(f: F) -> LazyClone<'a, T>
+        where T: 'b
+    {
+        LazyClone(LazyCloneInner::Owned(Thunk::defer(f)))
+    }
+}
+
+
+impl<'a, T: 'a> LazySend for LazyClone<'a, T> {
+    #[inline]
+    fn defer_send<'b, F: FnOnce() -> T + Send + 'b>(f: F) -> LazyClone<'a, T>
+        where T: 'b
+    {
+        LazyClone::defer(f)
+    }
+}
+
+
+impl<'a, T: Clone + 'a> LazyMut for LazyClone<'a, T> {
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        if let LazyCloneInner::Borrowed(t) = self.0 {
+            self.0 = LazyCloneInner::Owned(Thunk::computed(t.clone()));
+        }
+
+        match self.0 {
+            LazyCloneInner::Owned(ref mut thunk) => thunk.force_mut(),
+            LazyCloneInner::Borrowed(_) => unreachable!(),
+        }
+    }
+}
+
+
+impl<'a, T: Clone + 'a> Lazy for LazyClone<'a, T> {
+    #[inline]
+    fn unwrap(self) -> T {
+        match self.0 {
+            LazyCloneInner::Borrowed(t) => t.clone(),
+            LazyCloneInner::Owned(thunk) => thunk.unwrap(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+
+    #[test]
+    fn lazy_cow_reads_borrowed() {
+        let cloned = Cell::new(false);
+        let text = String::from("hello");
+        let cow = LazyCow::with(&text[..], |s: &str| {
+            cloned.set(true);
+            s.to_owned()
+        });
+
+        assert_eq!(&*cow, "hello");
+        assert_eq!(cow.len(), 5);
+        assert!(!cow.is_owned());
+        assert!(!cloned.get());
+    }
+
+
+    #[test]
+    fn lazy_cow_to_mut() {
+        let data = vec![1, 2, 3];
+        let mut cow = LazyCow::borrowed(&data[..]);
+
+        cow.to_mut().push(4);
+
+        assert!(cow.is_owned());
+        assert_eq!(&*cow, &[1, 2, 3, 4]);
+        assert_eq!(data, vec![1, 2, 3]);
+        assert_eq!(cow.into_owned(), vec![1, 2, 3, 4]);
+    }
+
+
+    #[test]
+    fn lazy_cow_into_cow() {
+        let text = "borrowed";
+
+        match LazyCow::borrowed(text).into_cow() {
+            Cow::Borrowed(s) => assert_eq!(s, "borrowed"),
+            Cow::Owned(_) => panic!("`into_cow` should not materialize"),
+        }
+    }
+
+
+
+    #[test]
+    fn lazy_clone_borrowed_until_mutated() {
+        let data = vec![1, 2, 3];
+        let mut lazy = LazyClone::borrowed(&data);
+
+        assert!(!lazy.is_owned());
+        assert_eq!(lazy.len(), 3);
+
+        lazy.push(4);
+
+        assert!(lazy.is_owned());
+        assert_eq!(*lazy, vec![1, 2, 3, 4]);
+        assert_eq!(data, vec![1, 2, 3]);
+    }
+
+
+    #[test]
+    fn lazy_clone_generic() {
+        fn total<L: Lazy<Target = Vec<u32>>>(lazy: L) -> u32 {
+            lazy.unwrap().into_iter().sum()
+        }
+
+        let data = vec![1, 2, 3];
+        assert_eq!(total(LazyClone::borrowed(&data)), 6);
+        assert_eq!(total(LazyClone::defer(|| vec![4, 5])), 9);
+    }
+}
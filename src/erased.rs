@@ -0,0 +1,221 @@
+//! Type-erased storage for deferred closures, shared by `Thunk` and
+//! `AtomicThunk`, and the in-place closures of `RcThunk` and `ArcThunk`.
+
+#[cfg(feature = "allocator_api")]
+use alloc::alloc::{handle_alloc_error, Allocator, Layout};
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use core::mem::{self, ManuallyDrop, MaybeUninit};
+#[cfg(feature = "allocator_api")]
+use core::ptr::NonNull;
+use core::ptr;
+
+
+/// The number of words of closure environment which a thunk will store inline
+/// rather than boxing. Two words covers the common cases of capturing a single
+/// `Rc`, `Box`, reference, or slice/`str` reference, at the cost of making
+/// every thunk one word larger than a lone boxed closure would.
+const INLINE_CLOSURE_WORDS: usize = 2;
+
+
+/// A type-erased `FnOnce() -> T` closure. Closures whose environment fits in
+/// `INLINE_CLOSURE_WORDS` words are stored directly in `env`; larger ones are
+/// boxed and the (thin) `Box` pointer is stored in `env` instead. Either way,
+/// `vtable` knows how to call or drop whatever lives in `env`.
+///
+/// Only the closure type is erased: the return type `T` stays in the type, so
+/// the vtable is always called with an `out` pointer of the type it writes.
+/// (The vtable itself cannot mention `T`, or it could only be promoted to a
+/// `'static` reference for `T: 'static`.) Since nothing is known about the
+/// closure's environment, an `ErasedClosure` is neither `Send` nor `Sync`.
+pub(crate) struct ErasedClosure<T> {
+    env: MaybeUninit<[usize; INLINE_CLOSURE_WORDS]>,
+    vtable: &'static ClosureVTable,
+    _marker: PhantomData<*const T>,
+}
+
+
+struct ClosureVTable {
+    /// Move the closure out of `env`, call it, and write the result to `out`.
+    call: unsafe fn(env: *mut u8, out: *mut u8),
+
+    /// Drop the closure in `env` in place.
+    drop: unsafe fn(env: *mut u8),
+}
+
+
+struct VTableOf<F, T>(PhantomData<(F, fn() -> T)>);
+
+
+impl<T, F: FnOnce() -> T> VTableOf<F, T> {
+    const VTABLE: ClosureVTable = ClosureVTable {
+        call: Self::call,
+        drop: Self::drop,
+    };
+
+
+    unsafe fn call(env: *mut u8, out: *mut u8) {
+        ptr::write(out as *mut T, ptr::read(env as *const F)());
+    }
+
+
+    unsafe fn drop(env: *mut u8) {
+        ptr::drop_in_place(env as *mut F);
+    }
+}
+
+
+impl<T> Drop for ErasedClosure<T> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { (self.vtable.drop)(self.env.as_mut_ptr() as *mut u8) }
+    }
+}
+
+
+impl<T> ErasedClosure<T> {
+    #[inline]
+    pub fn new<'a, F: FnOnce() -> T + 'a>(f: F) -> ErasedClosure<T> {
+        if Self::fits::<F>() {
+            unsafe { Self::new_unchecked(f) }
+        } else {
+            unsafe { Self::new_unchecked(Box::new(f)) }
+        }
+    }
+
+
+    /// Like `new`, but if the closure has to be boxed, its environment is
+    /// allocated with `alloc` rather than the global allocator.
+    #[cfg(feature = "allocator_api")]
+    #[inline]
+    pub fn new_in<'a, F, A>(f: F, alloc: A) -> ErasedClosure<T>
+        where F: FnOnce() -> T + 'a, A: Allocator + 'a
+    {
+        if Self::fits::<F>() {
+            unsafe { Self::new_unchecked(f) }
+        } else {
+            let allocated = AllocatedClosure::new(f, alloc);
+            unsafe { Self::new_unchecked(move || allocated.into_inner()()) }
+        }
+    }
+
+
+    #[inline]
+    fn fits<F>() -> bool {
+        mem::size_of::<F>() <= mem::size_of::<[usize; INLINE_CLOSURE_WORDS]>()
+            && mem::align_of::<F>() <= mem::align_of::<[usize; INLINE_CLOSURE_WORDS]>()
+    }
+
+
+    /// PRECONDITION: `F` must fit in `env`, as checked by `fits`.
+    #[inline]
+    unsafe fn new_unchecked<F: FnOnce() -> T>(f: F) -> ErasedClosure<T> {
+        let mut env = MaybeUninit::<[usize; INLINE_CLOSURE_WORDS]>::uninit();
+        ptr::write(env.as_mut_ptr() as *mut F, f);
+
+        ErasedClosure {
+            env,
+            vtable: &VTableOf::<F, T>::VTABLE,
+            _marker: PhantomData,
+        }
+    }
+
+
+    #[inline]
+    pub fn call(self) -> T {
+        // If the closure panics, it has already been moved out of `env`, so
+        // `self` must not be dropped either way.
+        let mut this = ManuallyDrop::new(self);
+        let mut out = MaybeUninit::<T>::uninit();
+
+        unsafe {
+            (this.vtable.call)(this.env.as_mut_ptr() as *mut u8, out.as_mut_ptr() as *mut u8);
+            out.assume_init()
+        }
+    }
+}
+
+
+/// A closure and the allocator it was allocated with, behind a single thin
+/// pointer so that it fits inline in an `ErasedClosure`. The allocator is
+/// stored alongside the closure so that it can be used to free the memory.
+#[cfg(feature = "allocator_api")]
+struct AllocatedClosure<F, A: Allocator> {
+    ptr: NonNull<(F, A)>,
+}
+
+
+#[cfg(feature = "allocator_api")]
+impl<F, A: Allocator> AllocatedClosure<F, A> {
+    fn new(f: F, alloc: A) -> AllocatedClosure<F, A> {
+        let layout = Layout::new::<(F, A)>();
+        let ptr = match alloc.allocate(layout) {
+            Ok(ptr) => ptr.cast::<(F, A)>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+
+        unsafe {
+            ptr::write(ptr.as_ptr(), (f, alloc));
+        }
+
+        AllocatedClosure { ptr }
+    }
+
+
+    /// Move the closure out of its allocation and free the allocation.
+    fn into_inner(self) -> F {
+        let this = ManuallyDrop::new(self);
+
+        unsafe {
+            let (f, alloc) = ptr::read(this.ptr.as_ptr());
+            alloc.deallocate(this.ptr.cast(), Layout::new::<(F, A)>());
+            f
+        }
+    }
+}
+
+
+#[cfg(feature = "allocator_api")]
+impl<F, A: Allocator> Drop for AllocatedClosure<F, A> {
+    fn drop(&mut self) {
+        unsafe {
+            let (f, alloc) = ptr::read(self.ptr.as_ptr());
+            alloc.deallocate(self.ptr.cast(), Layout::new::<(F, A)>());
+            mem::drop(f);
+        }
+    }
+}
+
+
+/// A deferred closure stored in place, which can be called or dropped exactly
+/// once through a trait object.
+pub(crate) trait Deferred<T> {
+    /// PRECONDITION: must be called at most once, and never after
+    /// `drop_in_place`.
+    unsafe fn call_in_place(&mut self) -> T;
+
+    /// PRECONDITION: must be called at most once, and never after
+    /// `call_in_place`.
+    unsafe fn drop_in_place(&mut self);
+}
+
+
+impl<T, F: FnOnce() -> T> Deferred<T> for ManuallyDrop<F> {
+    #[inline]
+    unsafe fn call_in_place(&mut self) -> T {
+        ManuallyDrop::take(self)()
+    }
+
+
+    #[inline]
+    unsafe fn drop_in_place(&mut self) {
+        ManuallyDrop::drop(self)
+    }
+}
+
+
+/// The closure of a shared thunk which was computed from the start.
+pub(crate) fn never_called<T>() -> T {
+    unreachable!("the closure of a computed shared thunk was called")
+}
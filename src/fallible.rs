@@ -0,0 +1,351 @@
+//! Thunks for deferred computations which may fail. Storing a `Result` in a
+//! plain `Thunk` makes dereferencing awkward; the types in this module instead
+//! hand out a `Result<&T, &E>` on force, caching the error just like a value.
+//!
+//! Fallible thunks may also be constructed with a `RetryPolicy`, in which case
+//! a failing computation is retried on force before its error is cached, or
+//! with `defer_catching`, in which case a panic during evaluation is caught and
+//! cached as an error instead of unwinding through the caller.
+
+use std::any::Any;
+use std::panic::{self, UnwindSafe};
+use std::thread;
+use std::time::Duration;
+
+use crate::{LazyRef, LazyMut, Lazy};
+use crate::sync::AtomicThunk;
+use crate::unsync::Thunk;
+
+
+/// A policy describing how many times a fallible computation is attempted when
+/// it is forced, and how long to wait between attempts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    backoff: Duration,
+    backoff_factor: u32,
+}
+
+
+impl RetryPolicy {
+    /// Construct a policy which attempts a computation up to `max_attempts`
+    /// times, with no delay between attempts.
+    ///
+    /// Panics if `max_attempts` is zero.
+    #[inline]
+    pub fn new(max_attempts: usize) -> RetryPolicy {
+        assert!(max_attempts > 0, "a `RetryPolicy` must allow at least one attempt");
+
+        RetryPolicy {
+            max_attempts,
+            backoff: Duration::from_secs(0),
+            backoff_factor: 1,
+        }
+    }
+
+
+    /// Wait for `backoff` after the first failed attempt.
+    #[inline]
+    pub fn with_backoff(self, backoff: Duration) -> RetryPolicy {
+        RetryPolicy { backoff, ..self }
+    }
+
+
+    /// Multiply the delay by `factor` after every failed attempt, for
+    /// exponential backoff. The default factor is 1, keeping the delay
+    /// constant.
+    #[inline]
+    pub fn with_backoff_factor(self, factor: u32) -> RetryPolicy {
+        RetryPolicy { backoff_factor: factor, ..self }
+    }
+
+
+    /// The maximum number of times a computation will be attempted.
+    #[inline]
+    pub fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+
+    /// Wrap a repeatable, fallible computation into a single computation
+    /// which retries according to this policy and returns the first success,
+    /// or the last error.
+    fn retrying<T, E, F: FnMut() -> Result<T, E>>(self, mut f: F) -> impl FnOnce() -> Result<T, E> {
+        move || {
+            let mut backoff = self.backoff;
+
+            for _ in 1..self.max_attempts {
+                if let Ok(t) = f() {
+                    return Ok(t);
+                }
+
+                if backoff > Duration::from_secs(0) {
+                    thread::sleep(backoff);
+                    backoff = backoff.checked_mul(self.backoff_factor).unwrap_or(backoff);
+                }
+            }
+
+            f()
+        }
+    }
+}
+
+
+/// A non-thread-safe thunk representing a lazily computed, fallible value.
+pub struct TryThunk<T, E>(Thunk<Result<T, E>>);
+
+
+impl<T, E> TryThunk<T, E> {
+    /// Construct a `TryThunk` with a precomputed, successful value.
+    #[inline]
+    pub fn computed(t: T) -> TryThunk<T, E> {
+        TryThunk(Thunk::computed(Ok(t)))
+    }
+
+
+    /// Construct a `TryThunk` with a precomputed error.
+    #[inline]
+    pub fn failed(e: E) -> TryThunk<T, E> {
+        TryThunk(Thunk::computed(Err(e)))
+    }
+
+
+    /// Defer a fallible computation. Whatever the closure returns - value or
+    /// error - is cached, and the closure is never run again.
+    #[inline]
+    pub fn defer<'a, F: FnOnce() -> Result<T, E> + 'a>(f: F) -> TryThunk<T, E>
+        where T: 'a, E: 'a
+    {
+        TryThunk(Thunk::defer(f))
+    }
+
+
+    /// Defer a repeatable, fallible computation. When forced, the closure is
+    /// retried according to `policy` until it succeeds or runs out of
+    /// attempts; only then is the final result cached.
+    #[inline]
+    pub fn defer_retrying<'a, F>(policy: RetryPolicy, f: F) -> TryThunk<T, E>
+        where T: 'a, E: 'a, F: FnMut() -> Result<T, E> + 'a
+    {
+        TryThunk::defer(policy.retrying(f))
+    }
+
+
+    /// Force the computation, returning a reference to either the computed
+    /// value or the error.
+    #[inline]
+    pub fn force(&self) -> Result<&T, &E> {
+        self.0.force().as_ref()
+    }
+
+
+    /// Force the computation, returning a mutable reference to either the
+    /// computed value or the error.
+    #[inline]
+    pub fn force_mut(&mut self) -> Result<&mut T, &mut E> {
+        self.0.force_mut().as_mut()
+    }
+
+
+    /// Check whether the computation has already been run, without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+
+
+    /// Unwrap the thunk into the result of its computation. This forces the
+    /// thunk.
+    #[inline]
+    pub fn unwrap(self) -> Result<T, E> {
+        self.0.unwrap()
+    }
+}
+
+
+/// A `TryThunk` whose error is the payload of a panic caught while evaluating
+/// it. See `TryThunk::defer_catching`.
+pub type CatchThunk<T> = TryThunk<T, Box<dyn Any + Send + 'static>>;
+
+
+impl<T> TryThunk<T, Box<dyn Any + Send + 'static>> {
+    /// Defer a computation which may panic. If the closure panics while the
+    /// thunk is being forced, the panic is caught and its payload is cached as
+    /// the thunk's error, rather than unwinding through the caller.
+    #[inline]
+    pub fn defer_catching<'a, F: FnOnce() -> T + UnwindSafe + 'a>(f: F) -> CatchThunk<T>
+        where T: 'a
+    {
+        TryThunk::defer(move || panic::catch_unwind(f))
+    }
+}
+
+
+impl<T, E> From<Result<T, E>> for TryThunk<T, E> {
+    #[inline]
+    fn from(result: Result<T, E>) -> TryThunk<T, E> {
+        TryThunk(Thunk::computed(result))
+    }
+}
+
+
+/// A thread-safe thunk representing a lazily computed, fallible value.
+pub struct AtomicTryThunk<T, E>(AtomicThunk<Result<T, E>>);
+
+
+impl<T, E> AtomicTryThunk<T, E> {
+    /// Construct an `AtomicTryThunk` with a precomputed, successful value.
+    #[inline]
+    pub fn computed(t: T) -> AtomicTryThunk<T, E> {
+        AtomicTryThunk(AtomicThunk::computed(Ok(t)))
+    }
+
+
+    /// Construct an `AtomicTryThunk` with a precomputed error.
+    #[inline]
+    pub fn failed(e: E) -> AtomicTryThunk<T, E> {
+        AtomicTryThunk(AtomicThunk::computed(Err(e)))
+    }
+
+
+    /// Defer a fallible computation. Whatever the closure returns - value or
+    /// error - is cached, and the closure is never run again.
+    #[inline]
+    pub fn defer<'a, F: FnOnce() -> Result<T, E> + Send + 'a>(f: F) -> AtomicTryThunk<T, E>
+        where T: 'a, E: 'a
+    {
+        AtomicTryThunk(AtomicThunk::defer(f))
+    }
+
+
+    /// Defer a repeatable, fallible computation. When forced, the closure is
+    /// retried according to `policy` until it succeeds or runs out of
+    /// attempts; only then is the final result cached.
+    #[inline]
+    pub fn defer_retrying<'a, F>(policy: RetryPolicy, f: F) -> AtomicTryThunk<T, E>
+        where T: 'a, E: 'a, F: FnMut() -> Result<T, E> + Send + 'a
+    {
+        AtomicTryThunk::defer(policy.retrying(f))
+    }
+
+
+    /// Force the computation, returning a reference to either the computed
+    /// value or the error.
+    #[inline]
+    pub fn force(&self) -> Result<&T, &E> {
+        self.0.force().as_ref()
+    }
+
+
+    /// Force the computation, returning a mutable reference to either the
+    /// computed value or the error.
+    #[inline]
+    pub fn force_mut(&mut self) -> Result<&mut T, &mut E> {
+        self.0.force_mut().as_mut()
+    }
+
+
+    /// Check whether the computation has already been run, without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+
+
+    /// Unwrap the thunk into the result of its computation. This forces the
+    /// thunk.
+    #[inline]
+    pub fn unwrap(self) -> Result<T, E> {
+        self.0.unwrap()
+    }
+}
+
+
+/// A `AtomicTryThunk` whose error is the payload of a panic caught while evaluating
+/// it. See `AtomicTryThunk::defer_catching`.
+pub type AtomicCatchThunk<T> = AtomicTryThunk<T, Box<dyn Any + Send + 'static>>;
+
+
+impl<T> AtomicTryThunk<T, Box<dyn Any + Send + 'static>> {
+    /// Defer a computation which may panic. If the closure panics while the
+    /// thunk is being forced, the panic is caught and its payload is cached as
+    /// the thunk's error, rather than unwinding through the caller.
+    #[inline]
+    pub fn defer_catching<'a, F: FnOnce() -> T + UnwindSafe + Send + 'a>(f: F) -> AtomicCatchThunk<T>
+        where T: 'a
+    {
+        AtomicTryThunk::defer(move || panic::catch_unwind(f))
+    }
+}
+
+
+impl<T, E> From<Result<T, E>> for AtomicTryThunk<T, E> {
+    #[inline]
+    fn from(result: Result<T, E>) -> AtomicTryThunk<T, E> {
+        AtomicTryThunk(AtomicThunk::computed(result))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+
+    use std::hint::black_box;
+
+    #[test]
+    fn try_thunk_caches_error() {
+        let runs = Cell::new(0);
+        let thunk: TryThunk<i32, &str> = TryThunk::defer(|| {
+            runs.set(runs.get() + 1);
+            Err("failed")
+        });
+
+        assert!(!thunk.is_evaluated());
+        assert_eq!(thunk.force(), Err(&"failed"));
+        assert_eq!(thunk.force(), Err(&"failed"));
+        assert_eq!(runs.get(), 1);
+        assert_eq!(thunk.unwrap(), Err("failed"));
+    }
+
+    #[test]
+    fn atomic_try_thunk_deferred() {
+        let mut thunk: AtomicTryThunk<i32, ()> = AtomicTryThunk::defer(|| Ok(black_box(1) + 1));
+
+        *thunk.force_mut().unwrap() += 1;
+        assert_eq!(thunk.force(), Ok(&3));
+        assert_eq!(thunk.unwrap(), Ok(3));
+    }
+
+    #[test]
+    fn try_thunk_retries() {
+        let runs = Cell::new(0);
+        let policy = RetryPolicy::new(3).with_backoff(Duration::from_millis(1));
+        let thunk: TryThunk<i32, i32> = TryThunk::defer_retrying(policy, || {
+            runs.set(runs.get() + 1);
+            if runs.get() < 3 { Err(runs.get()) } else { Ok(runs.get()) }
+        });
+
+        assert_eq!(thunk.force(), Ok(&3));
+        assert_eq!(runs.get(), 3);
+
+        let failing: AtomicTryThunk<(), i32> =
+            AtomicTryThunk::defer_retrying(RetryPolicy::new(2), || Err(black_box(1)));
+
+        assert_eq!(failing.unwrap(), Err(1));
+    }
+
+    #[test]
+    fn catch_thunk_catches_panics() {
+        let thunk: CatchThunk<i32> = TryThunk::defer_catching(|| panic!("oh no"));
+        let payload = thunk.unwrap().err().unwrap();
+
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"oh no"));
+
+        let thunk = AtomicCatchThunk::defer_catching(|| black_box(1) + 1);
+
+        assert_eq!(thunk.force().ok(), Some(&2));
+    }
+}
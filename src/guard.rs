@@ -0,0 +1,161 @@
+//! Thunks which are forced when they are dropped. A `ForceOnDrop` is useful
+//! for deferred computations which carry side effects, such as logging or
+//! flushing, which must happen even if nobody ever reads the value.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::thread;
+
+use crate::{LazyRef, LazyMut};
+
+
+/// An adaptor which forces the thunk it wraps when it is dropped, if the
+/// thunk has not been forced already. The thunk is not forced if the
+/// `ForceOnDrop` is dropped while the thread is panicking, since a panic from
+/// the deferred computation would then abort the process.
+pub struct ForceOnDrop<L: LazyRef>(Option<L>)
+    where L::Target: Into<L>;
+
+
+impl<L: LazyRef> Drop for ForceOnDrop<L>
+    where L::Target: Into<L>
+{
+    fn drop(&mut self) {
+        if let Some(ref thunk) = self.0 {
+            if !thunk.is_evaluated() && !thread::panicking() {
+                thunk.force();
+            }
+        }
+    }
+}
+
+
+impl<L: LazyRef> ForceOnDrop<L>
+    where L::Target: Into<L>
+{
+    /// Wrap a thunk so that it is forced when the wrapper is dropped.
+    #[inline]
+    pub fn new(thunk: L) -> ForceOnDrop<L> {
+        ForceOnDrop(Some(thunk))
+    }
+
+
+    /// Force the inner thunk, returning a reference to its value.
+    #[inline]
+    pub fn force(&self) -> &L::Target {
+        self.get().force()
+    }
+
+
+    /// Check whether the inner thunk has already been evaluated, without
+    /// forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.get().is_evaluated()
+    }
+
+
+    /// Get a reference to the inner thunk, without forcing it.
+    #[inline]
+    pub fn get(&self) -> &L {
+        self.0.as_ref().unwrap()
+    }
+
+
+    /// Unwrap the inner thunk without forcing it. The thunk will no longer be
+    /// forced when it is dropped.
+    #[inline]
+    pub fn into_inner(mut self) -> L {
+        self.0.take().unwrap()
+    }
+}
+
+
+impl<L: LazyRef> From<L> for ForceOnDrop<L>
+    where L::Target: Into<L>
+{
+    #[inline]
+    fn from(thunk: L) -> ForceOnDrop<L> {
+        ForceOnDrop::new(thunk)
+    }
+}
+
+
+impl<L: LazyRef> Deref for ForceOnDrop<L>
+    where L::Target: Into<L>
+{
+    type Target = L::Target;
+
+    #[inline]
+    fn deref(&self) -> &L::Target {
+        self.force()
+    }
+}
+
+
+impl<L: LazyMut> DerefMut for ForceOnDrop<L>
+    where L::Target: Into<L>
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut L::Target {
+        self.0.as_mut().unwrap().force_mut()
+    }
+}
+
+
+impl<L: LazyRef + fmt::Debug> fmt::Debug for ForceOnDrop<L>
+    where L::Target: Into<L>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ForceOnDrop").field(self.get()).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+
+    use crate::unsync::Thunk;
+
+    #[test]
+    fn force_on_drop_forces() {
+        let flushed = Cell::new(false);
+
+        {
+            let thunk = ForceOnDrop::new(Thunk::defer(|| flushed.set(true)));
+            assert!(!thunk.is_evaluated());
+        }
+
+        assert!(flushed.get());
+    }
+
+
+    #[test]
+    fn force_on_drop_forces_once() {
+        let forced = Cell::new(0);
+
+        {
+            let thunk = ForceOnDrop::new(Thunk::defer(|| {
+                forced.set(forced.get() + 1);
+                forced.get()
+            }));
+            assert_eq!(*thunk, 1);
+        }
+
+        assert_eq!(forced.get(), 1);
+    }
+
+
+    #[test]
+    fn force_on_drop_into_inner_disarms() {
+        let forced = Cell::new(false);
+
+        let thunk = ForceOnDrop::new(Thunk::defer(|| forced.set(true)));
+        drop(thunk.into_inner());
+
+        assert!(!forced.get());
+    }
+}
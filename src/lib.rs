@@ -12,30 +12,145 @@
 //!
 //! In addition, two shared thunk types are provided, implementing `LazyRef`
 //! and `LazyShared`:
-//! * `RcThunk`: a reference-counted thunk type. This is a wrapper over `Thunk`.
-//! * `ArcThunk`: an atomically reference-counted thunk type. This is a wrapper
-//!   over `AtomicThunk`.
+//! * `RcThunk`: a reference-counted thunk type, which keeps its counts, state,
+//!   value, and closure together in a single heap block.
+//! * `ArcThunk`: an atomically reference-counted thunk type, which likewise
+//!   keeps everything in a single heap block.
+//!
+//! Everything but `Strict`, the `unsync` thunks, and the thread-safe thunks
+//! needs the default `std` feature.
+//! Without it, the crate is `no_std` and needs only `alloc`, and threads
+//! which wait for a thunk to be evaluated spin rather than park.
 
-#![cfg_attr(test, feature(test))]
-#![feature(unsized_locals)]
-#![feature(untagged_unions)]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(all(test, feature = "nightly"), feature(test))]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
+#[cfg(feature = "interrupt_safe")]
+extern crate critical_section;
+#[cfg(loom)]
+extern crate loom;
+#[cfg(feature = "portable_atomic")]
+extern crate portable_atomic;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "stable_deref")]
+extern crate stable_deref_trait;
 extern crate unreachable;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "nightly"))]
 extern crate test;
 
-use std::ops::{Deref, DerefMut};
+use core::ops::{Deref, DerefMut};
 
+#[cfg(feature = "std")]
+pub mod arena;
+#[cfg(feature = "std")]
+pub mod array;
+#[cfg(feature = "std")]
+pub mod cancel;
+#[cfg(any(feature = "portable_atomic", target_has_atomic = "64"))]
+pub mod cell;
+#[cfg(feature = "std")]
+pub mod combinators;
+pub mod compact;
+#[cfg(feature = "std")]
+pub mod context;
+#[cfg(feature = "std")]
+pub mod cow;
+#[cfg(feature = "std")]
+pub mod fallible;
+#[cfg(feature = "std")]
+pub mod guard;
+#[cfg(feature = "std")]
+pub mod map;
+#[cfg(feature = "std")]
+pub mod memo;
+#[cfg(feature = "std")]
+pub mod owned;
+#[cfg(feature = "rayon")]
+pub mod par;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod refresh;
+#[cfg(feature = "std")]
+pub mod registry;
+#[cfg(feature = "std")]
+pub mod resumable;
+#[cfg(feature = "std")]
+pub mod rope;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(feature = "std")]
+pub mod speculative;
 pub mod strict;
 pub mod sync;
 pub mod unsync;
+#[cfg(feature = "std")]
+pub mod vec;
 
+mod atomic;
+mod erased;
 
+#[cfg(feature = "std")]
+pub use crate::arena::{ArenaThunk, ThunkArena};
+#[cfg(feature = "std")]
+pub use crate::array::LazyArray;
+#[cfg(feature = "std")]
+pub use crate::cancel::{CancellableThunk, CancelToken, Cancelled};
+#[cfg(any(feature = "portable_atomic", target_has_atomic = "64"))]
+pub use crate::cell::{AtomicCellThunk, CellValue};
+#[cfg(feature = "std")]
+pub use crate::combinators::{force_all, force_both, zip_parallel, ForceAll};
+pub use crate::compact::CompactThunk;
+#[cfg(feature = "std")]
+pub use crate::context::{ArcLazyWith, AtomicLazyWith, LazyWith, RcLazyWith};
+#[cfg(feature = "std")]
+pub use crate::cow::{LazyClone, LazyCow};
+#[cfg(feature = "std")]
+pub use crate::fallible::{AtomicCatchThunk, AtomicTryThunk, CatchThunk, RetryPolicy, TryThunk};
+#[cfg(feature = "std")]
+pub use crate::guard::ForceOnDrop;
+#[cfg(feature = "std")]
+pub use crate::map::LazyMap;
+#[cfg(feature = "std")]
+pub use crate::memo::{AtomicMemo, LruMemo, Memo};
+#[cfg(feature = "std")]
+pub use crate::owned::{OwnedLazy, RefView, View};
+#[cfg(feature = "rayon")]
+pub use crate::par::{par_force, par_unwrap, ParForce};
+#[cfg(feature = "std")]
+pub use crate::pool::{EagerPool, ThunkGroup};
+#[cfg(feature = "std")]
+pub use crate::progress::{Progress, ProgressThunk};
+#[cfg(feature = "std")]
+pub use crate::refresh::{AtomicExpiringThunk, ExpiringThunk, RefreshableThunk, SoftThunk};
+#[cfg(feature = "std")]
+pub use crate::registry::{AtomicThunkRegistry, ThunkRegistry};
+#[cfg(feature = "std")]
+pub use crate::resumable::{GenThunk, Step};
+#[cfg(feature = "std")]
+pub use crate::rope::{LazyConcat, Segment};
+#[cfg(feature = "std")]
+pub use crate::shared::{ArcPointer, ArcShared, RcPointer, RcShared, Shared, SharedPointer};
+#[cfg(feature = "std")]
+pub use crate::speculative::SpeculativeThunk;
 pub use crate::strict::Strict;
-pub use crate::sync::{AtomicThunk, ArcThunk};
-pub use crate::unsync::{Thunk, RcThunk};
-
+pub use crate::sync::{AtomicLateThunk, AtomicThunk, AtomicThunkRef, AtomicUnsizedThunk, CachePaddedThunk, FnPtrThunk, Poisoned, ThunkState, Timeout, WouldBlock};
+#[cfg(target_has_atomic = "ptr")]
+pub use crate::sync::{ArcThunk, ArcThunkRef, WeakArcThunk};
+pub use crate::unsync::{InlineThunk, LateThunk, Thunk, ThunkCell, ThunkRef, RcThunk, RcThunkRef, UnsizedThunk, WeakRcThunk};
+#[cfg(feature = "std")]
+pub use crate::vec::ThunkVec;
 
 /// The `Lazy` trait abstracts thunks which have exactly the same lifetimes
 /// as the types they defer computation of.
@@ -53,10 +168,23 @@ pub trait LazyRef
     /// Defer a computation stored as a `FnOnce` closure. Unwrapping/dereferencing
     /// will force the computation of the closure. The supplied closure must live
     /// as long as the type which the thunk computes.
-    fn defer<'a, F: FnOnce() -> Self::Target + 'a>(closure: F) -> Self where Self::Target: 'a;
+    ///
+    /// The closure need not be `Send`, so only the thunks which implement
+    /// `LazyLocal` can defer it; thread-safe thunks defer `Send` closures with
+    /// `LazySend::defer_send` instead.
+    #[inline]
+    fn defer<'a, F: FnOnce() -> Self::Target + 'a>(closure: F) -> Self
+        where Self: LazyLocal, Self::Target: 'a
+    {
+        LazyLocal::defer_local(closure)
+    }
+
+    /// Manually force a thunk's computation, returning a reference to the
+    /// computed value.
+    fn force(&self) -> &Self::Target;
 
-    /// Manually force a thunk's computation.
-    fn force(&self);
+    /// Check whether a thunk has already been evaluated, without forcing it.
+    fn is_evaluated(&self) -> bool;
 }
 
 
@@ -64,6 +192,9 @@ pub trait LazyRef
 pub trait LazyMut: From<<Self as Deref>::Target> + LazyRef + DerefMut
     where Self::Target: Sized
 {
+    /// Manually force a thunk's computation, returning a mutable reference to
+    /// the computed value.
+    fn force_mut(&mut self) -> &mut Self::Target;
 }
 
 
@@ -73,4 +204,155 @@ pub trait Lazy: LazyMut
 {
     /// Unwrap a thunk into its inner value. This forces the thunk.
     fn unwrap(self) -> Self::Target;
+
+    /// Unwrap a thunk into its inner value only if it has already been
+    /// evaluated. This never forces the thunk; if it is still deferred, the
+    /// thunk is handed back intact in the `Err` variant.
+    #[inline]
+    fn try_into_inner(self) -> Result<Self::Target, Self> {
+        if self.is_evaluated() {
+            Ok(self.unwrap())
+        } else {
+            Err(self)
+        }
+    }
 }
+
+
+/// The `LazyShared` trait abstracts reference-counted handles to lazily
+/// computed values, such as `RcThunk` and `ArcThunk`.
+pub trait LazyShared: LazyRef + Clone
+    where Self::Target: Into<Self> + Sized
+{
+    /// If the thunk is unevaluated, this will force it. If this is the sole
+    /// handle to the underlying thunk, this will return the forced value;
+    /// otherwise, it will return an `Err` containing the original handle.
+    fn try_unwrap(this: Self) -> Result<Self::Target, Self>;
+
+    /// If the thunk is unevaluated, this will force it. If this is the sole
+    /// handle to the underlying thunk, this will return a mutable reference to
+    /// the forced value; otherwise, it will return `None`.
+    fn get_mut(this: &mut Self) -> Option<&mut Self::Target>;
+
+    /// If the thunk is unevaluated, this will force it. If this is the sole
+    /// handle to the underlying thunk, this will return a mutable reference to
+    /// the forced value; otherwise, the forced value is cloned into a new,
+    /// unshared thunk which this handle is updated to point to.
+    fn make_mut(this: &mut Self) -> &mut Self::Target where Self::Target: Clone;
+
+    /// Check whether two handles point to the same underlying thunk, without
+    /// forcing either.
+    fn ptr_eq(this: &Self, other: &Self) -> bool;
+}
+
+
+/// The `LazyLocal` trait abstracts thunks whose deferred computation only
+/// ever runs on the thread which created them, so that they can defer closures
+/// which are not `Send` through `LazyRef::defer`. Every thunk type which is not
+/// thread-safe implements it; the thread-safe ones do not, since any thread
+/// which forces them may run the closure.
+///
+/// ```compile_fail
+/// use std::rc::Rc;
+/// use thunk::{AtomicThunk, LazyRef};
+///
+/// let rc = Rc::new(1);
+/// let thunk: AtomicThunk<i32> = LazyRef::defer(move || *rc + 1);
+/// ```
+pub trait LazyLocal: LazyRef
+    where Self::Target: Into<Self> + Sized
+{
+    /// Defer a computation stored as a `FnOnce` closure, which need not be
+    /// `Send`. This is what `LazyRef::defer` calls.
+    fn defer_local<'a, F: FnOnce() -> Self::Target + 'a>(closure: F) -> Self
+        where Self::Target: 'a;
+}
+
+
+/// The `LazySend` trait abstracts thunks which can defer a computation that
+/// may be run on another thread. Every thunk type implements it: the
+/// thread-safe ones defer the `Send` closure as they would with their own
+/// `defer`, and the others just as they would with `LazyRef::defer`. Generic
+/// code which may produce a thread-safe thunk should defer through
+/// `defer_send`.
+pub trait LazySend: LazyRef
+    where Self::Target: Into<Self> + Sized
+{
+    /// Defer a computation stored as a `Send` closure, which may be run on
+    /// whichever thread forces the thunk.
+    fn defer_send<'a, F: FnOnce() -> Self::Target + Send + 'a>(closure: F) -> Self
+        where Self::Target: 'a;
+}
+
+
+/// The `LazyExt` trait provides small ergonomic combinators for every thunk
+/// type. It is implemented for all types implementing `LazyRef`.
+pub trait LazyExt: LazyRef
+    where Self::Target: Into<Self> + Sized
+{
+    /// Produce a thunk which runs `f` on the value when, and only when, it is
+    /// forced.
+    fn inspect<'a, F: FnOnce(&Self::Target) + 'a>(self, f: F) -> Self
+        where Self: Lazy + LazyLocal + 'a, Self::Target: 'a
+    {
+        Self::defer(move || {
+            let value = self.unwrap();
+            f(&value);
+            value
+        })
+    }
+
+    /// Like `inspect`, but for any thunk type, including the thread-safe ones,
+    /// as long as the thunk and `f` can be sent to another thread.
+    fn inspect_send<'a, F: FnOnce(&Self::Target) + Send + 'a>(self, f: F) -> Self
+        where Self: Lazy + LazySend + Send + 'a, Self::Target: 'a
+    {
+        Self::defer_send(move || {
+            let value = self.unwrap();
+            f(&value);
+            value
+        })
+    }
+
+    /// Run `f` on the value immediately if the thunk has already been
+    /// evaluated. The thunk is never forced.
+    #[inline]
+    fn tap_forced<F: FnOnce(&Self::Target)>(&self, f: F) -> &Self {
+        if self.is_evaluated() {
+            f(self.force());
+        }
+
+        self
+    }
+
+    /// Move the (possibly deferred) value into a different thunk type. If the
+    /// thunk has already been evaluated, the new thunk will be evaluated as
+    /// well; otherwise, the new thunk defers forcing the old one.
+    fn map_into<'a, L>(self) -> L
+        where Self: Lazy + 'a,
+              L: LazyLocal<Target = Self::Target>,
+              Self::Target: Into<L> + 'a
+    {
+        match self.try_into_inner() {
+            Ok(value) => L::computed(value),
+            Err(thunk) => L::defer(move || thunk.unwrap()),
+        }
+    }
+
+    /// Like `map_into`, but into any thunk type, including the thread-safe
+    /// ones, as long as this thunk can be sent to another thread.
+    fn map_into_send<'a, L>(self) -> L
+        where Self: Lazy + Send + 'a,
+              L: LazySend<Target = Self::Target>,
+              Self::Target: Into<L> + 'a
+    {
+        match self.try_into_inner() {
+            Ok(value) => L::computed(value),
+            Err(thunk) => L::defer_send(move || thunk.unwrap()),
+        }
+    }
+}
+
+
+impl<L: LazyRef> LazyExt for L where L::Target: Into<L> {}
+
@@ -0,0 +1,240 @@
+//! A hash map whose values are computed lazily. Each entry is a `Thunk`, which
+//! is forced the first time the entry is looked up with `get`; iterating over
+//! the map never forces anything.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::fmt;
+use std::hash::Hash;
+
+use crate::{LazyRef, LazyMut};
+use crate::unsync::Thunk;
+
+
+/// A `HashMap`-like container of lazily computed values. Formatting a
+/// `LazyMap` with `Debug` does not force it; unevaluated entries are shown as
+/// `None`.
+pub struct LazyMap<K, V> {
+    entries: HashMap<K, Thunk<V>>,
+}
+
+
+/// An iterator over the entries of a `LazyMap`, which does not force them.
+pub struct Iter<'a, K: 'a, V: 'a>(hash_map::Iter<'a, K, Thunk<V>>);
+
+
+impl<K: Hash + Eq, V> Default for LazyMap<K, V> {
+    #[inline]
+    fn default() -> LazyMap<K, V> {
+        LazyMap::new()
+    }
+}
+
+
+impl<K: Hash + Eq, V> LazyMap<K, V> {
+    /// Create an empty map.
+    #[inline]
+    pub fn new() -> LazyMap<K, V> {
+        LazyMap { entries: HashMap::new() }
+    }
+
+
+    /// Create an empty map with space for at least `capacity` entries.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> LazyMap<K, V> {
+        LazyMap { entries: HashMap::with_capacity(capacity) }
+    }
+
+
+    /// Insert an already computed value, returning the previous entry for the
+    /// key, if any, without forcing it.
+    #[inline]
+    pub fn insert(&mut self, k: K, v: V) -> Option<Thunk<V>> {
+        self.entries.insert(k, Thunk::computed(v))
+    }
+
+
+    /// Insert a deferred computation, returning the previous entry for the
+    /// key, if any, without forcing it.
+    #[inline]
+    pub fn insert_deferred<'a, F: FnOnce() -> V + 'a>(&mut self, k: K, f: F) -> Option<Thunk<V>>
+        where V: 'a
+    {
+        self.entries.insert(k, Thunk::defer(f))
+    }
+
+
+    /// Get the value for a key, forcing it if it has not been computed yet.
+    #[inline]
+    pub fn get<Q: ?Sized + Hash + Eq>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>
+    {
+        self.entries.get(k).map(Thunk::force)
+    }
+
+
+    /// Get a mutable reference to the value for a key, forcing it if it has
+    /// not been computed yet.
+    #[inline]
+    pub fn get_mut<Q: ?Sized + Hash + Eq>(&mut self, k: &Q) -> Option<&mut V>
+        where K: Borrow<Q>
+    {
+        self.entries.get_mut(k).map(Thunk::force_mut)
+    }
+
+
+    /// Get the value for a key only if it has already been computed. This
+    /// never forces the entry.
+    #[inline]
+    pub fn get_unforced<Q: ?Sized + Hash + Eq>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>
+    {
+        self.entries.get(k).and_then(Thunk::try_get)
+    }
+
+
+    /// Get the thunk for a key, without forcing it.
+    #[inline]
+    pub fn get_thunk<Q: ?Sized + Hash + Eq>(&self, k: &Q) -> Option<&Thunk<V>>
+        where K: Borrow<Q>
+    {
+        self.entries.get(k)
+    }
+
+
+    /// Remove the entry for a key, returning it without forcing it.
+    #[inline]
+    pub fn remove<Q: ?Sized + Hash + Eq>(&mut self, k: &Q) -> Option<Thunk<V>>
+        where K: Borrow<Q>
+    {
+        self.entries.remove(k)
+    }
+
+
+    /// Check whether the map has an entry for a key, without forcing it.
+    #[inline]
+    pub fn contains_key<Q: ?Sized + Hash + Eq>(&self, k: &Q) -> bool
+        where K: Borrow<Q>
+    {
+        self.entries.contains_key(k)
+    }
+
+
+    /// The number of entries in the map, forced or not.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+
+    /// Check whether the map has no entries.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+
+    /// Iterate over the entries of the map without forcing them.
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter(self.entries.iter())
+    }
+
+
+    /// Force every entry in the map.
+    #[inline]
+    pub fn force_all(&self) {
+        for thunk in self.entries.values() {
+            thunk.force();
+        }
+    }
+}
+
+
+impl<'a, K: 'a, V: 'a> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a Thunk<V>);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a K, &'a Thunk<V>)> {
+        self.0.next()
+    }
+
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+
+impl<'a, K: Hash + Eq, V> IntoIterator for &'a LazyMap<K, V> {
+    type Item = (&'a K, &'a Thunk<V>);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+
+impl<K: Hash + Eq, V> Extend<(K, V)> for LazyMap<K, V> {
+    #[inline]
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.entries.extend(iter.into_iter().map(|(k, v)| (k, Thunk::computed(v))));
+    }
+}
+
+
+impl<K: Hash + Eq + fmt::Debug, V: fmt::Debug> fmt::Debug for LazyMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.entries.iter().map(|(k, v)| (k, v.try_get()))).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+
+    #[test]
+    fn lazy_map_forces_on_get() {
+        let forced = Cell::new(0);
+        let mut map = LazyMap::new();
+
+        map.insert_deferred("one", || {
+            forced.set(forced.get() + 1);
+            1
+        });
+        map.insert_deferred("two", || {
+            forced.set(forced.get() + 1);
+            2
+        });
+
+        assert_eq!(map.get_unforced("one"), None);
+        assert_eq!(map.get("one"), Some(&1));
+        assert_eq!(map.get_unforced("one"), Some(&1));
+        assert_eq!(forced.get(), 1);
+        assert_eq!(map.get("three"), None);
+    }
+
+
+    #[test]
+    fn lazy_map_iter_does_not_force() {
+        let mut map = LazyMap::new();
+
+        for i in 0..10 {
+            map.insert_deferred(i, move || i * i);
+        }
+
+        assert_eq!(map.iter().filter(|&(_, thunk)| thunk.is_evaluated()).count(), 0);
+
+        map.force_all();
+
+        assert_eq!(map.iter().filter(|&(_, thunk)| thunk.is_evaluated()).count(), 10);
+        assert_eq!(map.get_mut(&3).map(|v| { *v += 1; *v }), Some(10));
+    }
+}
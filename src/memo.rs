@@ -0,0 +1,434 @@
+//! Memoized functions. A `Memo` wraps a function and lazily computes and
+//! caches its result for each distinct argument; each cached result lives in
+//! its own thunk, so that a `Memo` may be called recursively from within its
+//! own function for different arguments, and so that concurrent callers of an
+//! `AtomicMemo` with the same argument share one evaluation. An `LruMemo`
+//! bounds the number of results it keeps.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use crate::sync::AtomicLateThunk;
+use crate::LazyRef;
+use crate::unsync::{LateThunk, RcThunk};
+
+
+/// A non-thread-safe memoized function.
+pub struct Memo<A, R, F> {
+    /// Slots are boxed so that references to their values stay valid when the
+    /// map grows. Slots are never removed while the `Memo` is borrowed.
+    slots: RefCell<HashMap<A, Box<LateThunk<R>>>>,
+    f: F,
+}
+
+
+/// A thread-safe memoized function. If several threads call it with the same
+/// argument at once, the function is run only once and the other threads wait
+/// for its result.
+///
+/// Since every caller gets a reference to the shared result, an `AtomicMemo`
+/// is only `Sync` if its results are:
+///
+/// ```compile_fail
+/// use std::cell::Cell;
+/// use thunk::AtomicMemo;
+///
+/// fn assert_sync<T: Sync>(_: &T) {}
+/// assert_sync(&AtomicMemo::new(|x: u32| Cell::new(x)));
+/// ```
+pub struct AtomicMemo<A, R, F> {
+    slots: Mutex<HashMap<A, Box<AtomicLateThunk<R>>>>,
+    f: F,
+}
+
+
+// Results are handed out by reference to every thread calling the function,
+// which the `Mutex` around the slots does not account for.
+unsafe impl<A: Send, R: Send + Sync, F: Sync> Sync for AtomicMemo<A, R, F> {}
+
+
+impl<A: Hash + Eq + Clone, R, F: Fn(A) -> R> Memo<A, R, F> {
+    /// Memoize `f`.
+    #[inline]
+    pub fn new(f: F) -> Memo<A, R, F> {
+        Memo {
+            slots: RefCell::new(HashMap::new()),
+            f,
+        }
+    }
+
+
+    /// Call the function, or get the cached result of a previous call with
+    /// the same argument.
+    pub fn call(&self, a: A) -> &R {
+        let slot = {
+            let mut slots = self.slots.borrow_mut();
+            let slot = match slots.get(&a) {
+                Some(slot) => &**slot as *const LateThunk<R>,
+                None => {
+                    let slot = slots.entry(a.clone()).or_insert_with(|| Box::new(LateThunk::new()));
+                    &**slot as *const LateThunk<R>
+                }
+            };
+
+            // The slot is boxed and is not removed until the `Memo` is
+            // mutably borrowed, so it outlives `&self`.
+            unsafe { &*slot }
+        };
+
+        slot.get_or_init(|| (self.f)(a))
+    }
+
+
+    /// Get the cached result for `a`, if the function has been called with it
+    /// before, without calling the function.
+    pub fn get(&self, a: &A) -> Option<&R> {
+        let slots = self.slots.borrow();
+        let slot = &**slots.get(a)? as *const LateThunk<R>;
+        unsafe { &*slot }.get()
+    }
+
+
+    /// The number of arguments for which a result is cached or being
+    /// computed.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slots.borrow().len()
+    }
+
+
+    /// Check whether no results are cached.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.slots.borrow().is_empty()
+    }
+
+
+    /// Forget every cached result.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.slots.get_mut().clear();
+    }
+}
+
+
+impl<A: Hash + Eq, R, F> fmt::Debug for Memo<A, R, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Memo").field("len", &self.slots.borrow().len()).finish()
+    }
+}
+
+
+impl<A: Hash + Eq + Clone, R, F: Fn(A) -> R> AtomicMemo<A, R, F> {
+    /// Memoize `f`.
+    #[inline]
+    pub fn new(f: F) -> AtomicMemo<A, R, F> {
+        AtomicMemo {
+            slots: Mutex::new(HashMap::new()),
+            f,
+        }
+    }
+
+
+    /// Call the function, or get the cached result of a previous call with
+    /// the same argument. The `AtomicMemo` is not locked while the function
+    /// runs.
+    pub fn call(&self, a: A) -> &R {
+        let slot = {
+            let mut slots = self.slots.lock().unwrap();
+            let slot = match slots.get(&a) {
+                Some(slot) => &**slot as *const AtomicLateThunk<R>,
+                None => {
+                    let slot = slots.entry(a.clone()).or_insert_with(|| Box::new(AtomicLateThunk::new()));
+                    &**slot as *const AtomicLateThunk<R>
+                }
+            };
+
+            // The slot is boxed and is not removed until the `AtomicMemo` is
+            // mutably borrowed, so it outlives `&self`.
+            unsafe { &*slot }
+        };
+
+        slot.get_or_init(|| (self.f)(a))
+    }
+
+
+    /// Get the cached result for `a`, if the function has finished running
+    /// with it, without calling the function or blocking on it.
+    pub fn get(&self, a: &A) -> Option<&R> {
+        let slots = self.slots.lock().unwrap();
+        let slot = &**slots.get(a)? as *const AtomicLateThunk<R>;
+        unsafe { &*slot }.get()
+    }
+
+
+    /// The number of arguments for which a result is cached or being
+    /// computed.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slots.lock().unwrap().len()
+    }
+
+
+    /// Check whether no results are cached.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.slots.lock().unwrap().is_empty()
+    }
+
+
+    /// Forget every cached result.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.slots.get_mut().unwrap().clear();
+    }
+}
+
+
+impl<A: Hash + Eq, R, F> fmt::Debug for AtomicMemo<A, R, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AtomicMemo").field("len", &self.slots.lock().unwrap().len()).finish()
+    }
+}
+
+
+/// A non-thread-safe memoized function which caches at most a fixed number of
+/// results, evicting the least recently used result when it is full. Evicted
+/// results are recomputed if the function is called with their argument
+/// again.
+///
+/// Results are handed out as `RcThunk`s, so a result which has been evicted
+/// stays alive for as long as a caller holds on to it.
+pub struct LruMemo<K, V, F> {
+    f: Rc<F>,
+    capacity: usize,
+    state: RefCell<LruState<K, V>>,
+}
+
+
+struct LruState<K, V> {
+    /// Every cached result, along with the tick at which it was last used.
+    entries: HashMap<K, (RcThunk<V>, u64)>,
+
+    /// The keys of the cached results, ordered from least to most recently
+    /// used.
+    order: BTreeMap<u64, K>,
+
+    tick: u64,
+}
+
+
+impl<K: Hash + Eq + Clone, V, F: Fn(K) -> V> LruMemo<K, V, F> {
+    /// Memoize `f`, caching at most `capacity` results.
+    ///
+    /// Panics if `capacity` is zero.
+    #[inline]
+    pub fn new(capacity: usize, f: F) -> LruMemo<K, V, F> {
+        assert!(capacity > 0, "an `LruMemo` must have a nonzero capacity");
+
+        LruMemo {
+            f: Rc::new(f),
+            capacity,
+            state: RefCell::new(LruState {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+            }),
+        }
+    }
+
+
+    /// Call the function, or get the cached result of a previous call with
+    /// the same argument, marking the result as the most recently used.
+    pub fn call(&self, k: K) -> RcThunk<V> {
+        let thunk = {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+
+            state.tick += 1;
+            let tick = state.tick;
+
+            match state.entries.get_mut(&k) {
+                Some(entry) => {
+                    state.order.remove(&entry.1);
+                    entry.1 = tick;
+                    state.order.insert(tick, k);
+                    entry.0.clone()
+                }
+                None => {
+                    let f = self.f.clone();
+                    let arg = k.clone();
+                    let thunk = RcThunk::defer(move || f(arg));
+
+                    state.entries.insert(k.clone(), (thunk.clone(), tick));
+                    state.order.insert(tick, k);
+
+                    while state.entries.len() > self.capacity {
+                        let oldest = *state.order.keys().next().unwrap();
+                        let key = state.order.remove(&oldest).unwrap();
+                        state.entries.remove(&key);
+                    }
+
+                    thunk
+                }
+            }
+        };
+
+        // The memo table is not borrowed while the function runs, so that it
+        // may call back into the `LruMemo`.
+        thunk.force();
+        thunk
+    }
+
+
+    /// Get the cached result for `k`, if there is one, without calling the
+    /// function or marking the result as used.
+    #[inline]
+    pub fn get(&self, k: &K) -> Option<RcThunk<V>> {
+        self.state.borrow().entries.get(k).map(|entry| entry.0.clone())
+    }
+
+
+    /// Evict the cached result for `k`, returning it.
+    pub fn remove(&self, k: &K) -> Option<RcThunk<V>> {
+        let mut state = self.state.borrow_mut();
+        let (thunk, tick) = state.entries.remove(k)?;
+        state.order.remove(&tick);
+        Some(thunk)
+    }
+
+
+    /// The maximum number of results the `LruMemo` caches.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+
+    /// The number of results currently cached.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.state.borrow().entries.len()
+    }
+
+
+    /// Check whether no results are cached.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.state.borrow().entries.is_empty()
+    }
+
+
+    /// Evict every cached result.
+    #[inline]
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        state.entries.clear();
+        state.order.clear();
+    }
+}
+
+
+impl<K, V, F> fmt::Debug for LruMemo<K, V, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LruMemo")
+            .field("capacity", &self.capacity)
+            .field("len", &self.state.borrow().entries.len())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn memo_caches() {
+        let calls = Cell::new(0);
+        let square = Memo::new(|x: u64| {
+            calls.set(calls.get() + 1);
+            x * x
+        });
+
+        assert_eq!(square.get(&3), None);
+        assert_eq!(*square.call(3), 9);
+        assert_eq!(*square.call(3), 9);
+        assert_eq!(*square.call(4), 16);
+        assert_eq!(square.get(&3), Some(&9));
+        assert_eq!(calls.get(), 2);
+        assert_eq!(square.len(), 2);
+    }
+
+
+    #[test]
+    fn memo_references_stay_valid() {
+        let identity = Memo::new(|x: usize| x);
+        let first = identity.call(0);
+
+        for i in 1..1000 {
+            identity.call(i);
+        }
+
+        assert_eq!(*first, 0);
+    }
+
+
+    #[test]
+    fn atomic_memo_deduplicates() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let memo = Arc::new(AtomicMemo::new(move |x: u64| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::yield_now();
+            x + 1
+        }));
+
+        let handles: Vec<_> = (0..8).map(|_| {
+            let memo = memo.clone();
+            thread::spawn(move || *memo.call(41))
+        }).collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 42);
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+
+
+    #[test]
+    fn lru_memo_evicts_least_recently_used() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let memo = LruMemo::new(2, move |x: u64| {
+            counter.set(counter.get() + 1);
+            x * 10
+        });
+
+        assert_eq!(*memo.call(1), 10);
+        assert_eq!(*memo.call(2), 20);
+        assert_eq!(*memo.call(1), 10);
+        assert_eq!(*memo.call(3), 30);
+        assert_eq!(calls.get(), 3);
+
+        assert!(memo.get(&2).is_none());
+        assert!(memo.get(&1).is_some());
+        assert_eq!(memo.len(), 2);
+
+        assert_eq!(*memo.call(2), 20);
+        assert_eq!(calls.get(), 4);
+        assert!(memo.get(&1).is_none());
+        assert!(memo.get(&3).is_some());
+    }
+}
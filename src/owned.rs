@@ -0,0 +1,177 @@
+//! Lazily computed views which borrow from data owned by the same value. An
+//! `OwnedLazy` holds an owner, such as a `String` or `Vec<u8>`, together with a
+//! deferred projection closure; forcing it runs the projection once and hands
+//! out the resulting view, which may borrow from the owner.
+//!
+//! Since Rust has no way of naming "a type with a lifetime parameter", the
+//! shape of the view is described by a type implementing `View`, mapping each
+//! lifetime `'a` to the view type borrowing for `'a`.
+
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+
+
+/// A family of view types, one for each lifetime of the borrow they take from
+/// their owner. For example, `RefView<str>` maps `'a` to `&'a str`.
+///
+/// # Safety
+///
+/// `Output` must be covariant in `'a`: an `OwnedLazy` stores its view with the
+/// borrow's lifetime erased, and shortens it to the lifetime of the borrow of
+/// the `OwnedLazy` when handing it out.
+pub unsafe trait View<'a> {
+    type Output: 'a;
+}
+
+
+/// The `View` of a plain reference into the owner.
+pub struct RefView<T: ?Sized>(PhantomData<fn() -> Box<T>>);
+
+
+unsafe impl<'a, T: ?Sized + 'a> View<'a> for RefView<T> {
+    type Output = &'a T;
+}
+
+
+/// The deferred projection of an `OwnedLazy`.
+type Project<O, V> = Box<dyn for<'a> FnOnce(&'a O) -> <V as View<'a>>::Output>;
+
+
+/// A value which owns `O` and lazily computes a view of type `V` borrowing
+/// from it. The owner is boxed so that its address does not change when the
+/// `OwnedLazy` is moved.
+pub struct OwnedLazy<O, V: for<'a> View<'a>> {
+    /// Declared first so that the view is dropped before the owner it
+    /// borrows from.
+    view: UnsafeCell<Option<<V as View<'static>>::Output>>,
+    project: Cell<Option<Project<O, V>>>,
+    owner: Box<O>,
+}
+
+
+impl<O, V: for<'a> View<'a>> OwnedLazy<O, V> {
+    /// Take ownership of `owner`, deferring the computation of a view of it.
+    #[inline]
+    pub fn new<F>(owner: O, f: F) -> OwnedLazy<O, V>
+        where F: for<'a> FnOnce(&'a O) -> <V as View<'a>>::Output + 'static
+    {
+        OwnedLazy {
+            view: UnsafeCell::new(None),
+            project: Cell::new(Some(Box::new(f))),
+            owner: Box::new(owner),
+        }
+    }
+
+
+    /// Force the projection, returning a reference to the view.
+    pub fn force(&self) -> &<V as View<'_>>::Output {
+        unsafe {
+            if (*self.view.get()).is_none() {
+                let project = self.project
+                    .take()
+                    .expect("a previous attempt to force an `OwnedLazy` panicked");
+
+                // The owner is boxed and never mutably borrowed or moved out
+                // while the view exists, so it outlives the view, and the
+                // view's lifetime may be erased.
+                let view = project(&self.owner);
+                let erased = mem::transmute_copy::<_, <V as View<'static>>::Output>(&view);
+                mem::forget(view);
+
+                *self.view.get() = Some(erased);
+            }
+
+            let view = (*self.view.get()).as_ref().unwrap();
+            &*(view as *const <V as View<'static>>::Output as *const <V as View<'_>>::Output)
+        }
+    }
+
+
+    /// Check whether the view has been computed, without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        unsafe { (*self.view.get()).is_some() }
+    }
+
+
+    /// Get a reference to the owner.
+    #[inline]
+    pub fn owner(&self) -> &O {
+        &self.owner
+    }
+
+
+    /// Discard the view, if it was computed, and take back the owner.
+    #[inline]
+    pub fn into_owner(self) -> O {
+        let OwnedLazy { view, owner, .. } = self;
+        drop(view);
+        *owner
+    }
+}
+
+
+impl<O: fmt::Debug, V: for<'a> View<'a>> fmt::Debug for OwnedLazy<O, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnedLazy")
+            .field("owner", &self.owner)
+            .field("is_evaluated", &self.is_evaluated())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Pair<'a> {
+        key: &'a str,
+        value: &'a str,
+    }
+
+
+    struct PairView;
+
+
+    unsafe impl<'a> View<'a> for PairView {
+        type Output = Pair<'a>;
+    }
+
+
+    // Takes the owner itself, as `OwnedLazy::new` requires.
+    #[allow(clippy::ptr_arg)]
+    fn parse_pair(s: &String) -> Pair<'_> {
+        let mut parts = s.splitn(2, '=');
+        Pair {
+            key: parts.next().unwrap(),
+            value: parts.next().unwrap_or(""),
+        }
+    }
+
+
+    #[test]
+    fn owned_lazy_ref_view() {
+        let lazy = OwnedLazy::<String, RefView<str>>::new(String::from("  hello  "), |s| s.trim());
+
+        assert!(!lazy.is_evaluated());
+        assert_eq!(*lazy.force(), "hello");
+        assert!(lazy.is_evaluated());
+        assert_eq!(lazy.owner(), "  hello  ");
+    }
+
+
+    #[test]
+    fn owned_lazy_struct_view() {
+        let lazy = OwnedLazy::<String, PairView>::new(String::from("answer=42"), parse_pair);
+
+        let moved = lazy;
+        let pair = moved.force();
+        assert_eq!(pair.key, "answer");
+        assert_eq!(pair.value, "42");
+
+        assert_eq!(moved.into_owner(), "answer=42");
+    }
+}
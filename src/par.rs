@@ -0,0 +1,107 @@
+//! Parallel forcing of collections of thread-safe thunks, on the `rayon`
+//! thread pool. These mirror `force_all` and `ForceAll`, but force the
+//! elements in parallel rather than in order.
+//!
+//! The thunks must be `Sync`, which rules out `Thunk` and `RcThunk`, and their
+//! closures must be allowed to run on any thread: an `AtomicThunk` deferred
+//! with a closure which is not `Send` panics when a worker thread forces it.
+//! Use `LazySend::defer_send` to construct thunks meant for these functions.
+//!
+//! `par_force`, `par_unwrap`, and the `ParForce` trait are re-exported from
+//! the crate root.
+
+use rayon::prelude::*;
+
+use crate::{LazyRef, Lazy};
+
+
+/// Force every thunk yielded by a parallel iterator, in parallel. Any type
+/// which can be turned into a parallel iterator of references to thunks will
+/// do, such as a `&Vec` of `AtomicThunk`s or `ArcThunk`s.
+pub fn par_force<'a, I, L>(thunks: I)
+    where I: IntoParallelIterator<Item = &'a L>,
+          L: LazyRef + Sync + 'a,
+          L::Target: Into<L>
+{
+    thunks.into_par_iter().for_each(|thunk| {
+        thunk.force();
+    });
+}
+
+
+/// Unwrap every thunk yielded by a parallel iterator, forcing them in
+/// parallel, and collect their values in the original order.
+pub fn par_unwrap<I, L>(thunks: I) -> Vec<L::Target>
+    where I: IntoParallelIterator<Item = L>,
+          L: Lazy + Send,
+          L::Target: Sized + Send
+{
+    thunks.into_par_iter().map(Lazy::unwrap).collect()
+}
+
+
+/// The `ParForce` trait abstracts collections of thread-safe thunks which can
+/// be forced in parallel in a single call.
+pub trait ParForce {
+    /// Force every thunk in the collection, in parallel.
+    fn par_force(&self);
+}
+
+
+impl<L: LazyRef + Sync> ParForce for [L]
+    where L::Target: Into<L>
+{
+    #[inline]
+    fn par_force(&self) {
+        par_force(self);
+    }
+}
+
+
+impl<L: LazyRef + Sync, const N: usize> ParForce for [L; N]
+    where L::Target: Into<L>
+{
+    #[inline]
+    fn par_force(&self) {
+        par_force(&self[..]);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{ArcThunk, AtomicThunk, LazySend};
+
+    #[test]
+    fn par_force_atomic_thunks() {
+        let runs = AtomicUsize::new(0);
+        let thunks: Vec<_> = (0..100).map(|i| {
+            let runs = &runs;
+            AtomicThunk::defer_send(move || {
+                runs.fetch_add(1, Ordering::Relaxed);
+                i * i
+            })
+        }).collect();
+
+        thunks.par_force();
+
+        assert!(thunks.iter().all(|thunk| thunk.is_evaluated()));
+        assert_eq!(runs.load(Ordering::Relaxed), 100);
+        assert_eq!(par_unwrap(thunks), (0..100).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn par_force_arc_thunks() {
+        let thunks = [ArcThunk::defer_send(|| 1), ArcThunk::computed(2), ArcThunk::defer_send(|| 3)];
+
+        thunks.par_force();
+        par_force(&thunks[..2]);
+
+        assert!(thunks.iter().all(|thunk| thunk.is_evaluated()));
+        assert_eq!(thunks.iter().map(|thunk| **thunk).sum::<i32>(), 6);
+    }
+}
@@ -0,0 +1,698 @@
+//! Eager evaluation of shared thunks on a pool of background threads. An
+//! `EagerPool` lets a program prefetch `ArcThunk`s which it expects to need
+//! soon, such as assets or the results of upcoming requests, while forcing
+//! them keeps working exactly as before. Speculative prefetches can be
+//! collected in a `ThunkGroup`, and cancelled together once they are no longer
+//! wanted.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::{LazyRef, LazySend};
+use crate::cancel::{CancelToken, Cancelled};
+use crate::sync::ArcThunk;
+
+
+/// The priority of prefetches queued by `EagerPool::prefetch`.
+pub const DEFAULT_PRIORITY: u32 = 0;
+
+/// The priority of prefetches which some thread is waiting on. Nothing is
+/// queued ahead of them.
+pub const URGENT_PRIORITY: u32 = u32::MAX;
+
+
+/// A prefetch waiting for a worker. Jobs with higher priorities run first,
+/// and jobs with the same priority run in the order they were queued.
+struct Job {
+    priority: u32,
+    seq: u64,
+    thunk: usize,
+    group: u64,
+
+    /// Whether some thread is blocked in `EagerPool::wait` until the job
+    /// runs, in which case cancelling its group leaves it queued.
+    waited: bool,
+
+    run: Box<dyn FnOnce() + Send>,
+}
+
+
+impl Ord for Job {
+    fn cmp(&self, other: &Job) -> Ordering {
+        self.priority.cmp(&other.priority).then(other.seq.cmp(&self.seq))
+    }
+}
+
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Job) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Job) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+
+impl Eq for Job {}
+
+
+struct PoolState {
+    queue: BinaryHeap<Job>,
+    next_seq: u64,
+    next_group: u64,
+    shutdown: bool,
+}
+
+
+struct Shared {
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+
+/// Prefetches which belong to no group.
+const NO_GROUP: u64 = 0;
+
+
+impl Shared {
+    /// Queue a job to force `thunk`, and return whether it was queued: it is
+    /// not if the thunk has already been evaluated or the pool is shutting
+    /// down.
+    fn prefetch<T: Send + Sync + 'static>(&self, thunk: &ArcThunk<T>, group: u64, priority: u32, waited: bool) -> bool {
+        if thunk.is_evaluated() {
+            return false;
+        }
+
+        let weak = ArcThunk::downgrade(thunk);
+        let run = Box::new(move || {
+            if let Some(thunk) = weak.upgrade() {
+                thunk.force();
+            }
+        });
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            // Nothing would ever run it.
+            if state.shutdown {
+                return false;
+            }
+
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.queue.push(Job { priority, seq, thunk: ArcThunk::addr(thunk), group, waited, run });
+        }
+
+        self.available.notify_one();
+        true
+    }
+
+
+    /// Rebuild the queue after changing or removing the jobs for which
+    /// `pred` returns `true`, and return whether there were any. A job is
+    /// removed if `update` returns `false`.
+    fn update<P, U>(&self, mut pred: P, mut update: U) -> bool
+        where P: FnMut(&Job) -> bool, U: FnMut(&mut Job) -> bool
+    {
+        let mut state = self.state.lock().unwrap();
+
+        if !state.queue.iter().any(&mut pred) {
+            return false;
+        }
+
+        let jobs = mem::take(&mut state.queue).into_vec();
+        state.queue = jobs.into_iter().filter_map(|mut job| {
+            if !pred(&job) {
+                return Some(job);
+            }
+
+            if update(&mut job) { Some(job) } else { None }
+        }).collect();
+
+        true
+    }
+}
+
+
+/// A fixed pool of worker threads which force `ArcThunk`s in the background.
+///
+/// Prefetching a thunk never changes what forcing it does: the value is still
+/// computed exactly once. A thread which forces the thunk while a worker is
+/// evaluating it waits for the worker, just as it would wait for any other
+/// thread; if it gets there before any worker does, it evaluates the thunk
+/// itself, and the queued prefetch finds nothing left to do.
+///
+/// Workers take prefetches in order of priority, so cheap speculative work
+/// can be queued below the thunks which are needed soonest, and a thread which
+/// comes to depend on a queued thunk can `bump` it or `wait` for it.
+///
+/// The pool only keeps weak handles to the thunks it is given, so a thunk
+/// which is dropped before a worker gets to it is never evaluated at all.
+/// Dropping the pool discards the prefetches which have not started, and
+/// waits for the ones which have.
+///
+/// ```
+/// use thunk::{ArcThunk, EagerPool, LazySend};
+///
+/// let pool = EagerPool::new(2);
+/// let thunk = ArcThunk::defer_send(|| (1..11).product::<u64>());
+///
+/// pool.prefetch(&thunk);
+/// assert_eq!(*thunk, 3628800);
+/// ```
+pub struct EagerPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+
+impl EagerPool {
+    /// Start a pool with `threads` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero, or if a worker thread cannot be spawned.
+    pub fn new(threads: usize) -> EagerPool {
+        assert!(threads > 0, "an `EagerPool` needs at least one thread");
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(PoolState {
+                queue: BinaryHeap::new(),
+                next_seq: 0,
+                next_group: NO_GROUP + 1,
+                shutdown: false,
+            }),
+            available: Condvar::new(),
+        });
+
+        let workers = (0..threads).map(|i| {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("thunk-eager-pool-{}", i))
+                .spawn(move || work(&shared))
+                .expect("failed to spawn an `EagerPool` worker")
+        }).collect();
+
+        EagerPool { shared, workers }
+    }
+
+
+    /// Queue `thunk` to be forced by a worker, with `DEFAULT_PRIORITY`. This
+    /// does nothing if the thunk has already been evaluated.
+    ///
+    /// The thunk must not have been deferred with `ArcThunk::defer_local`,
+    /// whose closure may only run on the thread which created it.
+    #[inline]
+    pub fn prefetch<T: Send + Sync + 'static>(&self, thunk: &ArcThunk<T>) {
+        self.prefetch_with_priority(thunk, DEFAULT_PRIORITY);
+    }
+
+
+    /// Queue `thunk` to be forced by a worker once every prefetch with a
+    /// higher priority has started. This does nothing if the thunk has
+    /// already been evaluated.
+    #[inline]
+    pub fn prefetch_with_priority<T: Send + Sync + 'static>(&self, thunk: &ArcThunk<T>, priority: u32) {
+        self.shared.prefetch(thunk, NO_GROUP, priority, false);
+    }
+
+
+    /// Move any queued prefetches of `thunk` to the front of the queue, and
+    /// return whether there were any. A prefetch which a worker has already
+    /// started is unaffected.
+    pub fn bump<T>(&self, thunk: &ArcThunk<T>) -> bool {
+        let key = ArcThunk::addr(thunk);
+        self.shared.update(|job| job.thunk == key, |job| {
+            job.priority = URGENT_PRIORITY;
+            true
+        })
+    }
+
+
+    /// Block until a worker has forced `thunk`, bumping its prefetch to the
+    /// front of the queue, or queueing it with `URGENT_PRIORITY` if it was not
+    /// queued at all. The prefetch stays queued even if its group is
+    /// cancelled while this thread waits for it.
+    ///
+    /// Unlike forcing the thunk, this never runs the deferred computation on
+    /// this thread, unless the pool is shutting down and no worker ever would.
+    pub fn wait<'a, T: Send + Sync + 'static>(&self, thunk: &'a ArcThunk<T>) -> &'a T {
+        let key = ArcThunk::addr(thunk);
+        let queued = thunk.is_evaluated()
+            || self.shared.update(|job| job.thunk == key, |job| {
+                job.priority = URGENT_PRIORITY;
+                job.waited = true;
+                true
+            })
+            || self.shared.prefetch(thunk, NO_GROUP, URGENT_PRIORITY, true);
+
+        if !queued {
+            return thunk.force();
+        }
+
+        ArcThunk::wait(thunk)
+    }
+
+
+    /// Start a new, empty group of prefetches, which can be cancelled all at
+    /// once.
+    pub fn group(&self) -> ThunkGroup {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_group;
+        state.next_group += 1;
+
+        ThunkGroup {
+            shared: self.shared.clone(),
+            id,
+            token: CancelToken::new(),
+        }
+    }
+
+
+    /// The number of prefetches which no worker has started yet.
+    pub fn pending(&self) -> usize {
+        self.shared.state.lock().unwrap().queue.len()
+    }
+
+
+    /// The number of worker threads in the pool.
+    #[inline]
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+
+}
+
+
+impl Drop for EagerPool {
+    fn drop(&mut self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.shutdown = true;
+            state.queue.clear();
+        }
+
+        self.shared.available.notify_all();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+impl fmt::Debug for EagerPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EagerPool")
+            .field("threads", &self.threads())
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+
+/// A set of prefetches queued on an `EagerPool`, which can be cancelled
+/// together, for instance once the view which would have shown their results
+/// is dismissed. Cancelling the group removes its prefetches which no worker
+/// has started yet from the queue, and cancels the group's `CancelToken`, so
+/// that running computations which check it can stop early. Values which
+/// have already been computed stay usable. A prefetch which some thread is
+/// waiting for with `EagerPool::wait` stays queued, so that the thread is not
+/// left waiting forever; a computation from `defer` then finds the token
+/// cancelled and returns `Err(Cancelled)` straight away.
+///
+/// ```
+/// use thunk::EagerPool;
+///
+/// let pool = EagerPool::new(1);
+/// let group = pool.group();
+/// let thunk = group.defer(|token| {
+///     token.check()?;
+///     Ok(2 + 2)
+/// });
+///
+/// group.cancel();
+///
+/// // The thunk either finished before it was cancelled, or never will.
+/// assert!(match *thunk {
+///     Ok(value) => value == 4,
+///     Err(_) => true,
+/// });
+/// ```
+pub struct ThunkGroup {
+    shared: Arc<Shared>,
+    id: u64,
+    token: CancelToken,
+}
+
+
+impl ThunkGroup {
+    /// Defer a cancellable computation and queue it with `DEFAULT_PRIORITY`.
+    /// Once the group is cancelled, forcing the thunk returns `Err(Cancelled)`
+    /// without running `f`, unless `f` has already started.
+    #[inline]
+    pub fn defer<T, F>(&self, f: F) -> ArcThunk<Result<T, Cancelled>>
+        where T: Send + Sync + 'static, F: FnOnce(&CancelToken) -> Result<T, Cancelled> + Send + 'static
+    {
+        self.defer_with_priority(DEFAULT_PRIORITY, f)
+    }
+
+
+    /// Defer a cancellable computation and queue it with `priority`.
+    pub fn defer_with_priority<T, F>(&self, priority: u32, f: F) -> ArcThunk<Result<T, Cancelled>>
+        where T: Send + Sync + 'static, F: FnOnce(&CancelToken) -> Result<T, Cancelled> + Send + 'static
+    {
+        let token = self.token.clone();
+        let thunk = ArcThunk::defer_send(move || {
+            token.check()?;
+            f(&token)
+        });
+
+        self.prefetch_with_priority(&thunk, priority);
+        thunk
+    }
+
+
+    /// Queue an existing thunk as part of the group, with `DEFAULT_PRIORITY`.
+    /// Cancelling the group only removes the prefetch; to stop the
+    /// computation itself, it should check the group's `token`.
+    #[inline]
+    pub fn prefetch<T: Send + Sync + 'static>(&self, thunk: &ArcThunk<T>) {
+        self.prefetch_with_priority(thunk, DEFAULT_PRIORITY);
+    }
+
+
+    /// Queue an existing thunk as part of the group, with `priority`. This
+    /// does nothing if the group has been cancelled.
+    pub fn prefetch_with_priority<T: Send + Sync + 'static>(&self, thunk: &ArcThunk<T>, priority: u32) {
+        if !self.token.is_cancelled() {
+            self.shared.prefetch(thunk, self.id, priority, false);
+        }
+    }
+
+
+    /// Cancel every computation in the group. This cannot be undone.
+    pub fn cancel(&self) {
+        self.token.cancel();
+
+        let id = self.id;
+        self.shared.update(|job| job.group == id, |job| job.waited);
+    }
+
+
+    /// Check whether the group has been cancelled.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+
+    /// The token which is cancelled along with the group.
+    #[inline]
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+}
+
+
+impl fmt::Debug for ThunkGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThunkGroup")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+
+/// Run prefetches until the pool shuts down.
+fn work(shared: &Shared) {
+    loop {
+        let job = {
+            let mut state = shared.state.lock().unwrap();
+
+            loop {
+                if state.shutdown {
+                    return;
+                }
+
+                match state.queue.pop() {
+                    Some(job) => break job,
+                    None => state = shared.available.wait(state).unwrap(),
+                }
+            }
+        };
+
+        // A computation which panics poisons its thunk, which reports the
+        // panic to whoever forces it next; the worker carries on.
+        let _ = panic::catch_unwind(AssertUnwindSafe(job.run));
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn eager_pool_prefetch() {
+        let (tx, rx) = mpsc::channel();
+        let pool = EagerPool::new(2);
+        let thunk = ArcThunk::defer_send(move || {
+            tx.send(()).unwrap();
+            42
+        });
+
+        pool.prefetch(&thunk);
+        rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        assert_eq!(*thunk, 42);
+        assert!(thunk.is_evaluated());
+    }
+
+
+    #[test]
+    fn eager_pool_evaluates_once() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let pool = EagerPool::new(4);
+        let thunks: Vec<_> = (0..100).map(|i| {
+            let runs = runs.clone();
+            ArcThunk::defer_send(move || {
+                runs.fetch_add(1, Ordering::Relaxed);
+                i
+            })
+        }).collect();
+
+        for thunk in &thunks {
+            pool.prefetch(thunk);
+        }
+
+        assert_eq!(thunks.iter().map(|thunk| **thunk).sum::<usize>(), 4950);
+
+        drop(pool);
+        assert_eq!(runs.load(Ordering::Relaxed), 100);
+    }
+
+
+    #[test]
+    fn eager_pool_skips_dropped_thunks() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel::<()>();
+        let pool = EagerPool::new(1);
+
+        // Keep the only worker busy until the second thunk has been dropped.
+        let blocker = ArcThunk::defer_send(move || rx.recv_timeout(Duration::from_secs(10)).is_ok());
+        pool.prefetch(&blocker);
+
+        let dropped = ArcThunk::defer_send({
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        pool.prefetch(&dropped);
+        drop(dropped);
+
+        // The worker only gets to this one after it has passed the other.
+        let (done_tx, done_rx) = mpsc::channel();
+        let after = ArcThunk::defer_send(move || done_tx.send(()).unwrap());
+        pool.prefetch(&after);
+
+        tx.send(()).unwrap();
+        done_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        assert!(*blocker);
+        assert_eq!(runs.load(Ordering::Relaxed), 0);
+    }
+
+
+    /// Occupy the only worker of `pool` until the returned sender is used.
+    fn block(pool: &EagerPool) -> (mpsc::Sender<()>, ArcThunk<bool>) {
+        let (tx, rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel();
+        let blocker = ArcThunk::defer_send(move || {
+            started_tx.send(()).unwrap();
+            rx.recv_timeout(Duration::from_secs(10)).is_ok()
+        });
+        pool.prefetch(&blocker);
+
+        started_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        (tx, blocker)
+    }
+
+
+    #[test]
+    fn eager_pool_priorities() {
+        let pool = EagerPool::new(1);
+        let (tx, blocker) = block(&pool);
+        let (order_tx, order_rx) = mpsc::channel();
+
+        let thunks: Vec<_> = [1, 3, 2, 3].iter().enumerate().map(|(i, &priority)| {
+            let order_tx = order_tx.clone();
+            let thunk = ArcThunk::defer_send(move || order_tx.send(i).unwrap());
+            pool.prefetch_with_priority(&thunk, priority);
+            thunk
+        }).collect();
+
+        tx.send(()).unwrap();
+        assert!(*blocker);
+
+        let order: Vec<_> = (0..4).map(|_| order_rx.recv_timeout(Duration::from_secs(10)).unwrap()).collect();
+        assert_eq!(order, [1, 3, 2, 0]);
+
+        // Each closure sends before its thunk is marked evaluated.
+        for thunk in &thunks {
+            ArcThunk::wait(thunk);
+        }
+    }
+
+
+    #[test]
+    fn eager_pool_bump() {
+        let pool = EagerPool::new(1);
+        let (tx, blocker) = block(&pool);
+        let (order_tx, order_rx) = mpsc::channel();
+
+        let thunks: Vec<_> = (0..3).map(|i| {
+            let order_tx = order_tx.clone();
+            let thunk = ArcThunk::defer_send(move || order_tx.send(i).unwrap());
+            pool.prefetch(&thunk);
+            thunk
+        }).collect();
+
+        assert!(pool.bump(&thunks[2]));
+        assert!(!pool.bump(&blocker));
+
+        tx.send(()).unwrap();
+        assert!(*blocker);
+
+        let order: Vec<_> = (0..3).map(|_| order_rx.recv_timeout(Duration::from_secs(10)).unwrap()).collect();
+        assert_eq!(order, [2, 0, 1]);
+    }
+
+
+    #[test]
+    fn eager_pool_wait() {
+        let pool = EagerPool::new(1);
+        let main = thread::current().id();
+
+        let queued = ArcThunk::defer_send(move || thread::current().id() != main);
+        pool.prefetch(&queued);
+        assert!(*pool.wait(&queued));
+
+        let unqueued = ArcThunk::defer_send(move || thread::current().id() != main);
+        assert!(*pool.wait(&unqueued));
+    }
+
+
+    #[test]
+    fn thunk_group_cancel() {
+        let pool = EagerPool::new(1);
+        let group = pool.group();
+        let other = pool.group();
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let done = group.defer(|_| Ok(1));
+        assert_eq!(*pool.wait(&done), Ok(1));
+
+        let (tx, blocker) = block(&pool);
+        let queued: Vec<_> = (0..3).map(|_| {
+            let runs = runs.clone();
+            group.defer(move |_| Ok(runs.fetch_add(1, Ordering::Relaxed)))
+        }).collect();
+        let survivor = other.defer(|_| Ok(2));
+
+        assert_eq!(pool.pending(), 4);
+        group.cancel();
+        assert_eq!(pool.pending(), 1);
+
+        tx.send(()).unwrap();
+        assert!(*blocker);
+
+        assert_eq!(*pool.wait(&survivor), Ok(2));
+        assert!(queued.iter().all(|thunk| **thunk == Err(Cancelled)));
+        assert_eq!(runs.load(Ordering::Relaxed), 0);
+        assert_eq!(*done, Ok(1));
+        assert!(!other.is_cancelled());
+    }
+
+
+    #[test]
+    fn thunk_group_cancel_while_waiting() {
+        let pool = EagerPool::new(1);
+        let group = pool.group();
+        let (tx, blocker) = block(&pool);
+        let thunk = group.defer(|_| Ok(1));
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| *pool.wait(&thunk));
+
+            // Whether the waiter has bumped the prefetch yet or not, the
+            // prefetch must still run once the worker is free.
+            thread::sleep(Duration::from_millis(10));
+            group.cancel();
+
+            tx.send(()).unwrap();
+            assert_eq!(waiter.join().unwrap(), Err(Cancelled));
+        });
+
+        assert!(*blocker);
+    }
+
+
+    #[test]
+    fn thunk_group_cancel_running() {
+        let pool = EagerPool::new(1);
+        let group = pool.group();
+        let (tx, rx) = mpsc::channel();
+
+        let thunk = group.defer(move |token| {
+            tx.send(()).unwrap();
+
+            loop {
+                token.check()?;
+                thread::yield_now();
+            }
+        });
+
+        rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        group.cancel();
+
+        let result: Result<(), Cancelled> = *thunk;
+        assert_eq!(result, Err(Cancelled));
+    }
+}
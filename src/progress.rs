@@ -0,0 +1,201 @@
+//! Progress reporting for long-running deferred computations. The closure of a
+//! `ProgressThunk` is handed a `Progress` handle through which it reports how
+//! far along it is; observers can poll the progress or subscribe to updates,
+//! even from other threads.
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{LazyRef, Lazy};
+use crate::sync::AtomicThunk;
+
+
+/// A subscriber to a `Progress`.
+type Callback = Box<dyn Fn(f64) + Send + Sync>;
+
+
+struct ProgressState {
+    /// The bits of the current progress, an `f64` between `0.0` and `1.0`.
+    fraction: AtomicU64,
+
+    callbacks: Mutex<Vec<Callback>>,
+}
+
+
+/// A handle through which a deferred computation reports its progress, as a
+/// fraction between `0.0` and `1.0`. Cloning a `Progress` produces another
+/// handle to the same progress.
+#[derive(Clone)]
+pub struct Progress(Arc<ProgressState>);
+
+
+impl Progress {
+    fn new() -> Progress {
+        Progress(Arc::new(ProgressState {
+            fraction: AtomicU64::new(0.0f64.to_bits()),
+            callbacks: Mutex::new(Vec::new()),
+        }))
+    }
+
+
+    /// Report progress as a fraction of the total work. The fraction is
+    /// clamped to lie between `0.0` and `1.0`, and every subscribed callback
+    /// is invoked with it.
+    pub fn set(&self, fraction: f64) {
+        let fraction = if fraction.is_nan() { 0.0 } else { fraction.clamp(0.0, 1.0) };
+
+        self.0.fraction.store(fraction.to_bits(), Ordering::Release);
+
+        for callback in self.0.callbacks.lock().unwrap().iter() {
+            callback(fraction);
+        }
+    }
+
+
+    /// Report progress as a number of completed steps out of a total.
+    #[inline]
+    pub fn step(&self, done: usize, total: usize) {
+        if total == 0 {
+            self.set(1.0);
+        } else {
+            self.set(done as f64 / total as f64);
+        }
+    }
+
+
+    /// Get the most recently reported progress.
+    #[inline]
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.fraction.load(Ordering::Acquire))
+    }
+
+
+    /// Subscribe a callback which is invoked with the new progress every time
+    /// it is reported. Callbacks run on the thread reporting the progress, and
+    /// must not subscribe further callbacks themselves.
+    pub fn subscribe<F: Fn(f64) + Send + Sync + 'static>(&self, callback: F) {
+        self.0.callbacks.lock().unwrap().push(Box::new(callback));
+    }
+}
+
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Progress").field(&self.get()).finish()
+    }
+}
+
+
+/// A thread-safe thunk whose deferred computation reports its progress. Once
+/// the thunk has been evaluated, its progress is always `1.0`.
+pub struct ProgressThunk<T> {
+    progress: Progress,
+    thunk: AtomicThunk<T>,
+}
+
+
+impl<T> ProgressThunk<T> {
+    /// Defer a computation which reports its progress through the given
+    /// `Progress` handle.
+    pub fn defer<'a, F: FnOnce(&Progress) -> T + Send + 'a>(f: F) -> ProgressThunk<T>
+        where T: 'a
+    {
+        let progress = Progress::new();
+        let closure_progress = progress.clone();
+
+        ProgressThunk {
+            progress,
+            thunk: AtomicThunk::defer(move || {
+                let value = f(&closure_progress);
+                closure_progress.set(1.0);
+                value
+            }),
+        }
+    }
+
+
+    /// Construct a `ProgressThunk` with a precomputed value.
+    pub fn computed(t: T) -> ProgressThunk<T> {
+        let progress = Progress::new();
+        progress.set(1.0);
+
+        ProgressThunk {
+            progress,
+            thunk: AtomicThunk::computed(t),
+        }
+    }
+
+
+    /// Get the most recently reported progress of the computation, without
+    /// forcing it.
+    #[inline]
+    pub fn progress(&self) -> f64 {
+        self.progress.get()
+    }
+
+
+    /// Subscribe a callback which is invoked every time the computation
+    /// reports progress. Subscribe before forcing the thunk in order to
+    /// observe every update.
+    #[inline]
+    pub fn on_progress<F: Fn(f64) + Send + Sync + 'static>(&self, callback: F) {
+        self.progress.subscribe(callback);
+    }
+
+
+    /// Force the computation, returning a reference to the computed value.
+    #[inline]
+    pub fn force(&self) -> &T {
+        self.thunk.force()
+    }
+
+
+    /// Check whether the thunk has already been evaluated, without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.thunk.is_evaluated()
+    }
+
+
+    /// Unwrap the thunk into its value, forcing it if necessary.
+    #[inline]
+    pub fn unwrap(self) -> T {
+        self.thunk.unwrap()
+    }
+}
+
+
+impl<T> Deref for ProgressThunk<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn progress_thunk_reports() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let thunk = ProgressThunk::defer(|progress| {
+            (1..5).fold(0, |acc, i| {
+                progress.step(i, 4);
+                acc + i
+            })
+        });
+        let sink = seen.clone();
+
+        thunk.on_progress(move |fraction| sink.lock().unwrap().push(fraction));
+        assert_eq!(thunk.progress(), 0.0);
+        assert_eq!(*thunk, 10);
+        assert_eq!(thunk.progress(), 1.0);
+        assert_eq!(*seen.lock().unwrap(), vec![0.25, 0.5, 0.75, 1.0, 1.0]);
+    }
+}
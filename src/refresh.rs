@@ -0,0 +1,424 @@
+//! Thunks whose computed values can be discarded and recomputed. Unlike the
+//! thunks in `unsync` and `sync`, these hold onto a repeatable closure, so the
+//! value can be recomputed any number of times.
+
+use std::cell::{RefCell, UnsafeCell};
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+/// A non-thread-safe thunk which can be invalidated, causing its value to be
+/// recomputed by the stored closure the next time it is forced. This is useful
+/// for caching values derived from inputs which may change.
+pub struct RefreshableThunk<T, F: Fn() -> T> {
+    closure: F,
+
+    /// The cached value, if there is one. Interior mutability is used so that
+    /// dereferencing can fill in the cache.
+    value: UnsafeCell<Option<T>>,
+}
+
+
+impl<T, F: Fn() -> T> RefreshableThunk<T, F> {
+    /// Construct a `RefreshableThunk` which will compute its value with `f`
+    /// when first forced.
+    #[inline]
+    pub fn new(f: F) -> RefreshableThunk<T, F> {
+        RefreshableThunk {
+            closure: f,
+            value: UnsafeCell::new(None),
+        }
+    }
+
+
+    /// Force the thunk, computing its value if it is not cached, and return a
+    /// reference to the value.
+    #[inline]
+    pub fn force(&self) -> &T {
+        if unsafe { (*self.value.get()).is_none() } {
+            let value = (self.closure)();
+
+            // The closure may have forced this thunk itself, in which case we
+            // must not overwrite a value which may already be borrowed.
+            unsafe {
+                let cache = &mut *self.value.get();
+
+                if cache.is_none() {
+                    *cache = Some(value);
+                }
+            }
+        }
+
+        unsafe { (*self.value.get()).as_ref().expect("the cache was just filled") }
+    }
+
+
+    /// Check whether the thunk currently holds a cached value, without forcing
+    /// it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        unsafe { (*self.value.get()).is_some() }
+    }
+
+
+    /// Discard the cached value, if any. The value will be recomputed the next
+    /// time the thunk is forced.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        *self.value.get_mut() = None;
+    }
+
+
+    /// Immediately recompute the value, replacing the cached one.
+    #[inline]
+    pub fn refresh(&mut self) {
+        let value = (self.closure)();
+        *self.value.get_mut() = Some(value);
+    }
+
+
+    /// Take the cached value out of the thunk, leaving it invalidated. This
+    /// never forces the thunk.
+    #[inline]
+    pub fn take(&mut self) -> Option<T> {
+        self.value.get_mut().take()
+    }
+
+
+    /// Unwrap the thunk into its value, forcing it if necessary.
+    #[inline]
+    pub fn unwrap(mut self) -> T {
+        match self.take() {
+            Some(value) => value,
+            None => (self.closure)(),
+        }
+    }
+}
+
+
+impl<T, F: Fn() -> T> AsRef<T> for RefreshableThunk<T, F> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T, F: Fn() -> T> Deref for RefreshableThunk<T, F> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T, F: Fn() -> T> DerefMut for RefreshableThunk<T, F> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force();
+
+        self.value.get_mut().as_mut().expect("the thunk was just forced")
+    }
+}
+
+
+/// A non-thread-safe thunk whose computed value expires after a fixed
+/// time-to-live, after which it is recomputed by the stored closure the next
+/// time it is forced. This is useful for caching lookups which go stale, such
+/// as configuration or authentication tokens.
+///
+/// Since forcing may replace the cached value, it requires a mutable
+/// reference; `get` can be used to peek at a value which has not expired.
+pub struct ExpiringThunk<T, F: Fn() -> T = Box<dyn Fn() -> T>> {
+    closure: F,
+    ttl: Duration,
+    cache: Option<(T, Instant)>,
+}
+
+
+impl<T, F: Fn() -> T> ExpiringThunk<T, F> {
+    /// Construct an `ExpiringThunk` which computes its value with `f` when
+    /// first forced, and keeps it for `ttl` before recomputing it.
+    #[inline]
+    pub fn new(ttl: Duration, f: F) -> ExpiringThunk<T, F> {
+        ExpiringThunk {
+            closure: f,
+            ttl,
+            cache: None,
+        }
+    }
+
+
+    /// Force the thunk, recomputing its value if it has never been computed or
+    /// has expired, and return a reference to the value.
+    pub fn force(&mut self) -> &T {
+        if self.get().is_none() {
+            self.cache = Some(((self.closure)(), Instant::now()));
+        }
+
+        &self.cache.as_ref().expect("the cache was just filled").0
+    }
+
+
+    /// Get a reference to the cached value, if there is one and it has not
+    /// expired. This never forces the thunk.
+    #[inline]
+    pub fn get(&self) -> Option<&T> {
+        match self.cache {
+            Some((ref value, computed_at)) if computed_at.elapsed() < self.ttl => Some(value),
+            _ => None,
+        }
+    }
+
+
+    /// The time at which the cached value was last computed, if it has been
+    /// computed at all. This is reported even if the value has since expired.
+    #[inline]
+    pub fn last_computed_at(&self) -> Option<Instant> {
+        self.cache.as_ref().map(|&(_, computed_at)| computed_at)
+    }
+
+
+    /// The time-to-live of computed values.
+    #[inline]
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+
+    /// Discard the cached value, if any, forcing it to be recomputed the next
+    /// time the thunk is forced.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        self.cache = None;
+    }
+}
+
+
+/// A thread-safe thunk whose computed value expires after a fixed
+/// time-to-live, after which it is recomputed by the stored closure the next
+/// time it is forced.
+///
+/// Since another thread may replace an expired value at any time, the value is
+/// handed out as an `Arc<T>`. Threads which force the thunk while it is being
+/// recomputed wait for the new value instead of recomputing it themselves.
+pub struct AtomicExpiringThunk<T, F: Fn() -> T = Box<dyn Fn() -> T + Send + Sync>> {
+    closure: F,
+    ttl: Duration,
+    cache: Mutex<Option<(Arc<T>, Instant)>>,
+}
+
+
+impl<T, F: Fn() -> T> AtomicExpiringThunk<T, F> {
+    /// Construct an `AtomicExpiringThunk` which computes its value with `f`
+    /// when first forced, and keeps it for `ttl` before recomputing it.
+    #[inline]
+    pub fn new(ttl: Duration, f: F) -> AtomicExpiringThunk<T, F> {
+        AtomicExpiringThunk {
+            closure: f,
+            ttl,
+            cache: Mutex::new(None),
+        }
+    }
+
+
+    /// Force the thunk, recomputing its value if it has never been computed or
+    /// has expired, and return a handle to the value.
+    pub fn force(&self) -> Arc<T> {
+        let mut cache = self.cache.lock().unwrap();
+
+        match *cache {
+            Some((ref value, computed_at)) if computed_at.elapsed() < self.ttl => value.clone(),
+            _ => {
+                let value = Arc::new((self.closure)());
+                *cache = Some((value.clone(), Instant::now()));
+                value
+            }
+        }
+    }
+
+
+    /// Get a handle to the cached value, if there is one and it has not
+    /// expired. This never forces the thunk.
+    #[inline]
+    pub fn get(&self) -> Option<Arc<T>> {
+        match *self.cache.lock().unwrap() {
+            Some((ref value, computed_at)) if computed_at.elapsed() < self.ttl => {
+                Some(value.clone())
+            }
+            _ => None,
+        }
+    }
+
+
+    /// The time at which the cached value was last computed, if it has been
+    /// computed at all. This is reported even if the value has since expired.
+    #[inline]
+    pub fn last_computed_at(&self) -> Option<Instant> {
+        self.cache.lock().unwrap().as_ref().map(|&(_, computed_at)| computed_at)
+    }
+
+
+    /// The time-to-live of computed values.
+    #[inline]
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+
+    /// Discard the cached value, if any, forcing it to be recomputed the next
+    /// time the thunk is forced.
+    #[inline]
+    pub fn invalidate(&self) {
+        *self.cache.lock().unwrap() = None;
+    }
+}
+
+
+/// A non-thread-safe thunk whose computed value may be purged at any time to
+/// free memory, and which transparently recomputes the value with the stored
+/// closure if it is needed again.
+///
+/// Since the value may be purged while it is still in use, it is handed out as
+/// an `Rc<T>`; purging only drops the thunk's own handle to it.
+pub struct SoftThunk<T, F: Fn() -> T = Box<dyn Fn() -> T>> {
+    closure: F,
+    cache: RefCell<Option<Rc<T>>>,
+}
+
+
+impl<T, F: Fn() -> T> SoftThunk<T, F> {
+    /// Construct a `SoftThunk` which computes its value with `f` whenever it
+    /// is forced without a cached value.
+    #[inline]
+    pub fn new(f: F) -> SoftThunk<T, F> {
+        SoftThunk {
+            closure: f,
+            cache: RefCell::new(None),
+        }
+    }
+
+
+    /// Force the thunk, recomputing its value if it is not cached, and return
+    /// a handle to the value.
+    pub fn force(&self) -> Rc<T> {
+        if let Some(value) = self.get() {
+            return value;
+        }
+
+        let value = Rc::new((self.closure)());
+        *self.cache.borrow_mut() = Some(value.clone());
+        value
+    }
+
+
+    /// Get a handle to the cached value, if there is one. This never forces
+    /// the thunk.
+    #[inline]
+    pub fn get(&self) -> Option<Rc<T>> {
+        self.cache.borrow().clone()
+    }
+
+
+    /// Check whether the thunk currently holds a cached value.
+    #[inline]
+    pub fn is_cached(&self) -> bool {
+        self.cache.borrow().is_some()
+    }
+
+
+    /// Drop the cached value, if any. The memory is freed once all handles
+    /// previously returned by `force` and `get` are dropped, and the value
+    /// will be recomputed the next time the thunk is forced.
+    #[inline]
+    pub fn purge(&self) {
+        self.cache.borrow_mut().take();
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+
+    #[test]
+    fn refreshable_thunk_recomputes() {
+        let input = Cell::new(1);
+        let mut thunk = RefreshableThunk::new(|| input.get() * 10);
+
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 10);
+        input.set(2);
+        assert_eq!(*thunk, 10);
+        thunk.invalidate();
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 20);
+        input.set(3);
+        thunk.refresh();
+        assert!(thunk.is_evaluated());
+        assert_eq!(thunk.unwrap(), 30);
+    }
+
+    #[test]
+    fn expiring_thunk_expires() {
+        let runs = Cell::new(0);
+        let mut thunk = ExpiringThunk::new(Duration::from_secs(3600), || {
+            runs.set(runs.get() + 1);
+            runs.get()
+        });
+
+        assert_eq!(thunk.get(), None);
+        assert_eq!(thunk.last_computed_at(), None);
+        assert_eq!(*thunk.force(), 1);
+        assert_eq!(*thunk.force(), 1);
+        assert!(thunk.last_computed_at().is_some());
+        thunk.invalidate();
+        assert_eq!(*thunk.force(), 2);
+
+        let mut expired = ExpiringThunk::new(Duration::from_secs(0), || runs.get());
+
+        expired.force();
+        assert_eq!(expired.get(), None);
+        assert!(expired.last_computed_at().is_some());
+    }
+
+    #[test]
+    fn atomic_expiring_thunk_expires() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let runs = AtomicUsize::new(0);
+        let thunk = AtomicExpiringThunk::new(Duration::from_secs(0), || {
+            runs.fetch_add(1, Ordering::SeqCst)
+        });
+
+        assert_eq!(*thunk.force(), 0);
+        assert_eq!(*thunk.force(), 1);
+        assert_eq!(thunk.get(), None);
+    }
+
+    #[test]
+    fn soft_thunk_purges() {
+        let runs = Cell::new(0);
+        let thunk = SoftThunk::new(|| {
+            runs.set(runs.get() + 1);
+            vec![0u8; 1024]
+        });
+
+        assert!(!thunk.is_cached());
+        let value = thunk.force();
+        assert_eq!(value.len(), 1024);
+        thunk.purge();
+        assert!(thunk.get().is_none());
+        assert_eq!(value.len(), 1024);
+        thunk.force();
+        assert_eq!(runs.get(), 2);
+        thunk.force();
+        assert_eq!(runs.get(), 2);
+    }
+}
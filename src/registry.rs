@@ -0,0 +1,266 @@
+//! Keyed registries of shared thunks, for de-duplicating pending work. The
+//! first computation registered under a given key is the only one which is
+//! ever kept; registering the same key again hands back the existing thunk, so
+//! each key's computation runs at most once for the lifetime of the registry.
+
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use crate::LazyRef;
+use crate::sync::ArcThunk;
+use crate::unsync::RcThunk;
+
+
+/// A non-thread-safe registry handing out `RcThunk`s by key.
+pub struct ThunkRegistry<K, T> {
+    thunks: RefCell<HashMap<K, RcThunk<T>>>,
+}
+
+
+/// A thread-safe registry handing out `ArcThunk`s by key.
+pub struct AtomicThunkRegistry<K, T> {
+    thunks: Mutex<HashMap<K, ArcThunk<T>>>,
+}
+
+
+impl<K: Hash + Eq, T> Default for ThunkRegistry<K, T> {
+    #[inline]
+    fn default() -> ThunkRegistry<K, T> {
+        ThunkRegistry::new()
+    }
+}
+
+
+impl<K: Hash + Eq + fmt::Debug, T> fmt::Debug for ThunkRegistry<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.thunks.borrow().keys()).finish()
+    }
+}
+
+
+impl<K: Hash + Eq, T> ThunkRegistry<K, T> {
+    /// Create an empty registry.
+    #[inline]
+    pub fn new() -> ThunkRegistry<K, T> {
+        ThunkRegistry { thunks: RefCell::new(HashMap::new()) }
+    }
+
+
+    /// Get the thunk registered under `key`, deferring `f` as a new thunk for
+    /// the key if there is none. If a thunk was already registered, `f` is
+    /// dropped without being run.
+    pub fn register<'a, F: FnOnce() -> T + 'a>(&self, key: K, f: F) -> RcThunk<T>
+        where T: 'a
+    {
+        match self.thunks.borrow_mut().entry(key) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => entry.insert(RcThunk::defer(f)).clone(),
+        }
+    }
+
+
+    /// Get the thunk registered under `key`, if any.
+    #[inline]
+    pub fn get<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<RcThunk<T>>
+        where K: Borrow<Q>
+    {
+        self.thunks.borrow().get(key).cloned()
+    }
+
+
+    /// Check whether a thunk is registered under `key`.
+    #[inline]
+    pub fn contains_key<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> bool
+        where K: Borrow<Q>
+    {
+        self.thunks.borrow().contains_key(key)
+    }
+
+
+    /// Unregister the thunk under `key`, returning it. Registering the key
+    /// again afterwards will defer a fresh computation.
+    #[inline]
+    pub fn remove<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<RcThunk<T>>
+        where K: Borrow<Q>
+    {
+        self.thunks.borrow_mut().remove(key)
+    }
+
+
+    /// The number of registered thunks.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.thunks.borrow().len()
+    }
+
+
+    /// Check whether no thunks are registered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.thunks.borrow().is_empty()
+    }
+
+
+    /// Unregister every thunk. Outstanding clones of the thunks are unaffected.
+    #[inline]
+    pub fn clear(&self) {
+        self.thunks.borrow_mut().clear();
+    }
+}
+
+
+impl<K: Hash + Eq, T> Default for AtomicThunkRegistry<K, T> {
+    #[inline]
+    fn default() -> AtomicThunkRegistry<K, T> {
+        AtomicThunkRegistry::new()
+    }
+}
+
+
+impl<K: Hash + Eq + fmt::Debug, T> fmt::Debug for AtomicThunkRegistry<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.thunks.lock().unwrap().keys()).finish()
+    }
+}
+
+
+impl<K: Hash + Eq, T> AtomicThunkRegistry<K, T> {
+    /// Create an empty registry.
+    #[inline]
+    pub fn new() -> AtomicThunkRegistry<K, T> {
+        AtomicThunkRegistry { thunks: Mutex::new(HashMap::new()) }
+    }
+
+
+    /// Get the thunk registered under `key`, deferring `f` as a new thunk for
+    /// the key if there is none. If a thunk was already registered, `f` is
+    /// dropped without being run. The registry is only locked while looking
+    /// up the key, never while a thunk is being forced.
+    pub fn register<'a, F: FnOnce() -> T + Send + 'a>(&self, key: K, f: F) -> ArcThunk<T>
+        where T: 'a
+    {
+        match self.thunks.lock().unwrap().entry(key) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => entry.insert(ArcThunk::defer(f)).clone(),
+        }
+    }
+
+
+    /// Get the thunk registered under `key`, if any.
+    #[inline]
+    pub fn get<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<ArcThunk<T>>
+        where K: Borrow<Q>
+    {
+        self.thunks.lock().unwrap().get(key).cloned()
+    }
+
+
+    /// Check whether a thunk is registered under `key`.
+    #[inline]
+    pub fn contains_key<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> bool
+        where K: Borrow<Q>
+    {
+        self.thunks.lock().unwrap().contains_key(key)
+    }
+
+
+    /// Unregister the thunk under `key`, returning it. Registering the key
+    /// again afterwards will defer a fresh computation.
+    #[inline]
+    pub fn remove<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<ArcThunk<T>>
+        where K: Borrow<Q>
+    {
+        self.thunks.lock().unwrap().remove(key)
+    }
+
+
+    /// The number of registered thunks.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.thunks.lock().unwrap().len()
+    }
+
+
+    /// Check whether no thunks are registered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.thunks.lock().unwrap().is_empty()
+    }
+
+
+    /// Unregister every thunk. Outstanding clones of the thunks are unaffected.
+    #[inline]
+    pub fn clear(&self) {
+        self.thunks.lock().unwrap().clear();
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn registry_deduplicates() {
+        let runs = Cell::new(0);
+        let registry = ThunkRegistry::new();
+
+        let first = registry.register("key", || {
+            runs.set(runs.get() + 1);
+            1
+        });
+        let second = registry.register("key", || {
+            runs.set(runs.get() + 1);
+            2
+        });
+
+        assert_eq!(*first, 1);
+        assert_eq!(*second, 1);
+        assert_eq!(runs.get(), 1);
+        assert_eq!(registry.len(), 1);
+    }
+
+
+    #[test]
+    fn registry_remove() {
+        let registry = ThunkRegistry::new();
+        registry.register(String::from("key"), || 1);
+
+        assert!(registry.contains_key("key"));
+        assert_eq!(registry.remove("key").map(|thunk| *thunk), Some(1));
+        assert!(registry.is_empty());
+        assert_eq!(*registry.register(String::from("key"), || 2), 2);
+    }
+
+
+    #[test]
+    fn atomic_registry_deduplicates_across_threads() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let registry = Arc::new(AtomicThunkRegistry::new());
+
+        let handles: Vec<_> = (0..8).map(|_| {
+            let runs = runs.clone();
+            let registry = registry.clone();
+            thread::spawn(move || {
+                let thunk = registry.register(42, move || runs.fetch_add(1, Ordering::SeqCst));
+                *thunk
+            })
+        }).collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 0);
+        }
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,241 @@
+//! Thunks which can be evaluated incrementally. A `GenThunk` expresses its
+//! computation as a step function, which is called repeatedly until it
+//! produces a value; callers can make partial progress within a budget of
+//! steps or of time, for example once per frame, instead of forcing the whole
+//! computation at once.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+
+/// The result of running one step of a `GenThunk`'s computation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step<T> {
+    /// The computation made progress, but is not finished yet.
+    Continue,
+
+    /// The computation finished with a value.
+    Done(T),
+}
+
+
+/// A non-thread-safe thunk whose computation is a resumable step function.
+pub struct GenThunk<T> {
+    state: UnsafeCell<State<T>>,
+}
+
+
+enum State<T> {
+    Running(Box<dyn FnMut() -> Step<T>>),
+    Done(T),
+
+    /// The step function is currently running, or it panicked.
+    Stepping,
+}
+
+
+impl<T> GenThunk<T> {
+    /// Defer a computation expressed as a step function. Any state the
+    /// computation needs to carry between steps should be captured by the
+    /// closure.
+    #[inline]
+    pub fn new<F: FnMut() -> Step<T> + 'static>(step: F) -> GenThunk<T> {
+        GenThunk { state: UnsafeCell::new(State::Running(Box::new(step))) }
+    }
+
+
+    /// Construct an already completed `GenThunk`.
+    #[inline]
+    pub fn computed(t: T) -> GenThunk<T> {
+        GenThunk { state: UnsafeCell::new(State::Done(t)) }
+    }
+
+
+    /// Run up to `n` steps of the computation, returning `true` if it has
+    /// completed.
+    pub fn step(&self, n: usize) -> bool {
+        for _ in 0..n {
+            if self.step_once() {
+                return true;
+            }
+        }
+
+        self.is_evaluated()
+    }
+
+
+    /// Run steps of the computation until it completes or `budget` has
+    /// elapsed, returning `true` if it has completed. At least one step is
+    /// run, and a step which is already running when the budget runs out is
+    /// allowed to finish.
+    pub fn force_for(&self, budget: Duration) -> bool {
+        let start = Instant::now();
+
+        loop {
+            if self.step_once() {
+                return true;
+            }
+
+            if start.elapsed() >= budget {
+                return false;
+            }
+        }
+    }
+
+
+    /// Run the computation to completion, returning a reference to its value.
+    #[inline]
+    pub fn force(&self) -> &T {
+        while !self.step_once() {}
+
+        match self.try_get() {
+            Some(value) => value,
+            None => unreachable!(),
+        }
+    }
+
+
+    /// Check whether the computation has completed.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.try_get().is_some()
+    }
+
+
+    /// Get a reference to the value if the computation has completed. This
+    /// never runs any steps.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        match unsafe { &*self.state.get() } {
+            State::Done(value) => Some(value),
+            State::Running(_) | State::Stepping => None,
+        }
+    }
+
+
+    /// Run the computation to completion and take ownership of its value.
+    #[inline]
+    pub fn unwrap(self) -> T {
+        self.force();
+
+        match self.state.into_inner() {
+            State::Done(value) => value,
+            State::Running(_) | State::Stepping => unreachable!(),
+        }
+    }
+
+
+    /// Run a single step, returning `true` if the computation has completed.
+    fn step_once(&self) -> bool {
+        match unsafe { &*self.state.get() } {
+            State::Running(_) => {}
+            State::Done(_) => return true,
+            State::Stepping => {
+                panic!("attempted to step a `GenThunk` which is already stepping or panicked")
+            }
+        }
+
+        // The step function is moved out while it runs, so that neither a
+        // reentrant step nor a panic can observe it half-run. No references
+        // into the state can exist while it is still running.
+        let mut step = match mem::replace(unsafe { &mut *self.state.get() }, State::Stepping) {
+            State::Running(step) => step,
+            State::Done(_) | State::Stepping => unreachable!(),
+        };
+
+        let (next, done) = match step() {
+            Step::Continue => (State::Running(step), false),
+            Step::Done(value) => (State::Done(value), true),
+        };
+
+        unsafe { *self.state.get() = next };
+
+        done
+    }
+}
+
+
+impl<T> AsRef<T> for GenThunk<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T> Deref for GenThunk<T> {
+    type Target = T;
+
+    /// Dereferencing a `GenThunk` runs any remaining steps to completion.
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T> From<T> for GenThunk<T> {
+    #[inline]
+    fn from(t: T) -> GenThunk<T> {
+        GenThunk::computed(t)
+    }
+}
+
+
+impl<T: fmt::Debug> fmt::Debug for GenThunk<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("GenThunk").field(&self.try_get()).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sum_to(n: u64) -> GenThunk<u64> {
+        let mut i = 0;
+        let mut acc = 0;
+
+        GenThunk::new(move || {
+            if i == n {
+                Step::Done(acc)
+            } else {
+                i += 1;
+                acc += i;
+                Step::Continue
+            }
+        })
+    }
+
+
+    #[test]
+    fn gen_thunk_steps() {
+        let thunk = sum_to(10);
+
+        assert!(!thunk.step(5));
+        assert!(!thunk.is_evaluated());
+        assert!(thunk.step(6));
+        assert_eq!(thunk.try_get(), Some(&55));
+    }
+
+
+    #[test]
+    fn gen_thunk_force_for() {
+        let thunk = sum_to(1000);
+
+        while !thunk.force_for(Duration::from_millis(1)) {}
+
+        assert_eq!(*thunk, 500500);
+    }
+
+
+    #[test]
+    fn gen_thunk_deref_completes() {
+        assert_eq!(sum_to(100).unwrap(), 5050);
+        assert_eq!(*GenThunk::computed(1), 1);
+    }
+}
@@ -0,0 +1,365 @@
+//! Lazily concatenated strings and byte buffers. A `LazyConcat` is a rope of
+//! `RcThunk` segments: concatenating two of them takes constant time and
+//! forces nothing, and the segments are only computed when the contents are
+//! written out, one at a time, or when the whole flattened result is
+//! demanded.
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::Add;
+use std::rc::Rc;
+
+use crate::LazyRef;
+use crate::unsync::RcThunk;
+
+
+/// A type which can be used as a segment of a `LazyConcat`.
+pub trait Segment: Default {
+    /// Append another segment to this one.
+    fn append(&mut self, other: &Self);
+
+    /// The contents of the segment, as bytes.
+    fn as_bytes(&self) -> &[u8];
+}
+
+
+impl Segment for String {
+    #[inline]
+    fn append(&mut self, other: &String) {
+        self.push_str(other);
+    }
+
+
+    #[inline]
+    fn as_bytes(&self) -> &[u8] {
+        str::as_bytes(self)
+    }
+}
+
+
+impl Segment for Vec<u8> {
+    #[inline]
+    fn append(&mut self, other: &Vec<u8>) {
+        self.extend_from_slice(other);
+    }
+
+
+    #[inline]
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+
+/// A non-thread-safe rope of lazily computed segments. Cloning a `LazyConcat`
+/// is cheap, and clones share their segments. Formatting a `LazyConcat<String>`
+/// with `Display` forces each segment only as it is reached.
+pub struct LazyConcat<S> {
+    node: Rc<Node<S>>,
+}
+
+
+enum Node<S> {
+    Empty,
+    Leaf(RcThunk<S>),
+    Concat {
+        left: LazyConcat<S>,
+        right: LazyConcat<S>,
+
+        /// The flattened contents of both halves, which is only computed if
+        /// it is asked for.
+        flat: RcThunk<S>,
+    },
+}
+
+
+impl<S> Clone for LazyConcat<S> {
+    #[inline]
+    fn clone(&self) -> LazyConcat<S> {
+        LazyConcat { node: self.node.clone() }
+    }
+}
+
+
+impl<S> Drop for LazyConcat<S> {
+    fn drop(&mut self) {
+        // Dropping a long chain of concatenations recursively would overflow
+        // the call stack, so nodes which are about to be freed are emptied
+        // here first, and their halves dropped from an explicit stack.
+        let mut stack = Vec::new();
+        take_halves(&mut self.node, &mut stack);
+
+        while let Some(mut rope) = stack.pop() {
+            take_halves(&mut rope.node, &mut stack);
+        }
+    }
+}
+
+
+/// If `node` is the last handle to a concatenation, empty it, and push its
+/// halves onto `stack`.
+fn take_halves<S>(node: &mut Rc<Node<S>>, stack: &mut Vec<LazyConcat<S>>) {
+    if let Some(node) = Rc::get_mut(node) {
+        if let Node::Concat { left, right, flat } = mem::replace(node, Node::Empty) {
+            // The deferred flattening holds handles to both halves as well.
+            drop(flat);
+            stack.push(left);
+            stack.push(right);
+        }
+    }
+}
+
+
+impl<S> Default for LazyConcat<S> {
+    #[inline]
+    fn default() -> LazyConcat<S> {
+        LazyConcat::new()
+    }
+}
+
+
+impl<S> LazyConcat<S> {
+    /// Create an empty rope.
+    #[inline]
+    pub fn new() -> LazyConcat<S> {
+        LazyConcat { node: Rc::new(Node::Empty) }
+    }
+
+
+    /// Create a rope consisting of a single, possibly deferred segment.
+    #[inline]
+    pub fn segment(thunk: RcThunk<S>) -> LazyConcat<S> {
+        LazyConcat { node: Rc::new(Node::Leaf(thunk)) }
+    }
+
+
+    /// Create a rope consisting of a single deferred segment.
+    #[inline]
+    pub fn defer<'a, F: FnOnce() -> S + 'a>(f: F) -> LazyConcat<S>
+        where S: 'a
+    {
+        LazyConcat::segment(RcThunk::defer(f))
+    }
+
+
+    /// Call `f` on each segment in order, without forcing them. Where part of
+    /// the rope has already been flattened, `f` is given the flattened
+    /// segment instead of the segments it was built from.
+    pub fn for_each_segment<F: FnMut(&RcThunk<S>)>(&self, mut f: F) {
+        // An explicit stack keeps long chains of concatenations from
+        // overflowing the call stack.
+        let mut stack = vec![self];
+
+        while let Some(rope) = stack.pop() {
+            match *rope.node {
+                Node::Empty => {}
+                Node::Leaf(ref thunk) => f(thunk),
+                Node::Concat { ref left, ref right, ref flat } => {
+                    if flat.is_evaluated() {
+                        f(flat);
+                    } else {
+                        stack.push(right);
+                        stack.push(left);
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+impl<S: Segment> LazyConcat<S> {
+    /// Concatenate two ropes in constant time, without forcing either.
+    pub fn concat(&self, other: &LazyConcat<S>) -> LazyConcat<S> {
+        match (&*self.node, &*other.node) {
+            (&Node::Empty, _) => return other.clone(),
+            (_, &Node::Empty) => return self.clone(),
+            _ => {}
+        }
+
+        let (left, right) = (self.clone(), other.clone());
+        let flat = RcThunk::defer(move || {
+            let mut out = S::default();
+            left.for_each_segment(|segment| out.append(segment));
+            right.for_each_segment(|segment| out.append(segment));
+            out
+        });
+
+        LazyConcat {
+            node: Rc::new(Node::Concat {
+                left: self.clone(),
+                right: other.clone(),
+                flat,
+            }),
+        }
+    }
+
+
+    /// Append another rope to this one in constant time.
+    #[inline]
+    pub fn push(&mut self, other: &LazyConcat<S>) {
+        *self = self.concat(other);
+    }
+
+
+    /// Get the flattened contents of the rope as a single thunk. Forcing it
+    /// forces every segment; the result is cached and shared with every rope
+    /// built from this one.
+    pub fn flatten(&self) -> RcThunk<S> {
+        match *self.node {
+            Node::Empty => RcThunk::computed(S::default()),
+            Node::Leaf(ref thunk) => thunk.clone(),
+            Node::Concat { ref flat, .. } => flat.clone(),
+        }
+    }
+
+
+    /// Write the contents of the rope to `w`, forcing each segment only as it
+    /// is reached.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut result = Ok(());
+
+        self.for_each_segment(|segment| {
+            if result.is_ok() {
+                result = w.write_all(segment.as_bytes());
+            }
+        });
+
+        result
+    }
+}
+
+
+impl<S: Segment> Add for LazyConcat<S> {
+    type Output = LazyConcat<S>;
+
+    #[inline]
+    fn add(self, other: LazyConcat<S>) -> LazyConcat<S> {
+        self.concat(&other)
+    }
+}
+
+
+impl<'a, S: Segment> Add<&'a LazyConcat<S>> for LazyConcat<S> {
+    type Output = LazyConcat<S>;
+
+    #[inline]
+    fn add(self, other: &'a LazyConcat<S>) -> LazyConcat<S> {
+        self.concat(other)
+    }
+}
+
+
+impl<S> From<S> for LazyConcat<S> {
+    #[inline]
+    fn from(s: S) -> LazyConcat<S> {
+        LazyConcat::segment(RcThunk::computed(s))
+    }
+}
+
+
+impl<'a> From<&'a str> for LazyConcat<String> {
+    #[inline]
+    fn from(s: &'a str) -> LazyConcat<String> {
+        LazyConcat::from(String::from(s))
+    }
+}
+
+
+impl<S> From<RcThunk<S>> for LazyConcat<S> {
+    #[inline]
+    fn from(thunk: RcThunk<S>) -> LazyConcat<S> {
+        LazyConcat::segment(thunk)
+    }
+}
+
+
+impl fmt::Display for LazyConcat<String> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut result = Ok(());
+
+        self.for_each_segment(|segment| {
+            if result.is_ok() {
+                result = f.write_str(segment);
+            }
+        });
+
+        result
+    }
+}
+
+
+impl<S> fmt::Debug for LazyConcat<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut segments = 0;
+        let mut evaluated = 0;
+
+        self.for_each_segment(|segment| {
+            segments += 1;
+            evaluated += segment.is_evaluated() as usize;
+        });
+
+        f.debug_struct("LazyConcat")
+            .field("segments", &segments)
+            .field("evaluated", &evaluated)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+
+    #[test]
+    fn lazy_concat_display() {
+        let forced = Rc::new(Cell::new(0));
+        let counter = forced.clone();
+
+        let rope = LazyConcat::from("Hello, ")
+            + LazyConcat::defer(move || {
+                counter.set(counter.get() + 1);
+                String::from("world")
+            })
+            + LazyConcat::from("!");
+
+        assert_eq!(forced.get(), 0);
+        assert_eq!(rope.to_string(), "Hello, world!");
+        assert_eq!(rope.to_string(), "Hello, world!");
+        assert_eq!(forced.get(), 1);
+    }
+
+
+    #[test]
+    fn lazy_concat_flatten_is_shared() {
+        let rope = LazyConcat::from(vec![1u8, 2]) + LazyConcat::from(vec![3u8]);
+        let extended = rope.clone() + LazyConcat::from(vec![4u8]);
+
+        assert_eq!(*rope.flatten(), vec![1, 2, 3]);
+
+        let mut segments = 0;
+        extended.for_each_segment(|_| segments += 1);
+        assert_eq!(segments, 2);
+
+        let mut out = Vec::new();
+        extended.write_to(&mut out).unwrap();
+        assert_eq!(out, vec![1, 2, 3, 4]);
+    }
+
+
+    #[test]
+    fn lazy_concat_long_chain() {
+        // Miri is far too slow for a chain long enough to overflow the stack.
+        let len = if cfg!(miri) { 1000 } else { 100000 };
+        let mut rope = LazyConcat::new();
+
+        for i in 0..len {
+            rope.push(&LazyConcat::defer(move || (i % 10).to_string()));
+        }
+
+        assert_eq!(rope.flatten().len(), len);
+    }
+}
@@ -0,0 +1,298 @@
+//! Shared thunks, generic over both the thunk and the reference-counted
+//! pointer which shares it. A `Shared<L, P>` is to any `Lazy` thunk type `L`
+//! what `RcThunk` and `ArcThunk` are to `Thunk` and `AtomicThunk`, with the
+//! pointer picked by the `RcPointer` and `ArcPointer` markers.
+//!
+//! `Shared` implements `LazyRef` and `LazyShared` whenever a value can be
+//! converted into it directly. That conversion is provided here for `Thunk`,
+//! `AtomicThunk`, and `Strict`; it cannot be written generically over `L`,
+//! since it would overlap with the standard library's `impl<T> From<T> for T`.
+
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::{LazyRef, LazyMut, Lazy, LazyLocal, LazySend, LazyShared};
+use crate::strict::Strict;
+use crate::sync::AtomicThunk;
+use crate::unsync::Thunk;
+
+
+/// The `SharedPointer` trait abstracts reference-counted pointers which can
+/// share a thunk, such as `Rc` and `Arc`.
+pub trait SharedPointer {
+    /// The pointer to a shared `L`.
+    type Ptr<L>: Deref<Target = L> + Clone;
+
+    /// Move a value into a new, unshared pointer.
+    fn new<L>(l: L) -> Self::Ptr<L>;
+
+    /// Move the value out of the pointer if it is the only strong reference.
+    fn try_unwrap<L>(ptr: Self::Ptr<L>) -> Result<L, Self::Ptr<L>>;
+
+    /// Get a mutable reference to the value if the pointer is the only
+    /// reference, strong or weak.
+    fn get_mut<L>(ptr: &mut Self::Ptr<L>) -> Option<&mut L>;
+
+    /// Check whether two pointers point to the same value.
+    fn ptr_eq<L>(this: &Self::Ptr<L>, other: &Self::Ptr<L>) -> bool;
+}
+
+
+/// Picks `Rc` as the pointer of a `Shared`.
+pub enum RcPointer {}
+
+
+/// Picks `Arc` as the pointer of a `Shared`.
+pub enum ArcPointer {}
+
+
+impl SharedPointer for RcPointer {
+    type Ptr<L> = Rc<L>;
+
+    #[inline]
+    fn new<L>(l: L) -> Rc<L> {
+        Rc::new(l)
+    }
+
+
+    #[inline]
+    fn try_unwrap<L>(ptr: Rc<L>) -> Result<L, Rc<L>> {
+        Rc::try_unwrap(ptr)
+    }
+
+
+    #[inline]
+    fn get_mut<L>(ptr: &mut Rc<L>) -> Option<&mut L> {
+        Rc::get_mut(ptr)
+    }
+
+
+    #[inline]
+    fn ptr_eq<L>(this: &Rc<L>, other: &Rc<L>) -> bool {
+        Rc::ptr_eq(this, other)
+    }
+}
+
+
+impl SharedPointer for ArcPointer {
+    type Ptr<L> = Arc<L>;
+
+    #[inline]
+    fn new<L>(l: L) -> Arc<L> {
+        Arc::new(l)
+    }
+
+
+    #[inline]
+    fn try_unwrap<L>(ptr: Arc<L>) -> Result<L, Arc<L>> {
+        Arc::try_unwrap(ptr)
+    }
+
+
+    #[inline]
+    fn get_mut<L>(ptr: &mut Arc<L>) -> Option<&mut L> {
+        Arc::get_mut(ptr)
+    }
+
+
+    #[inline]
+    fn ptr_eq<L>(this: &Arc<L>, other: &Arc<L>) -> bool {
+        Arc::ptr_eq(this, other)
+    }
+}
+
+
+/// A thunk of type `L` shared behind the pointer picked by `P`.
+pub struct Shared<L, P: SharedPointer>(P::Ptr<L>);
+
+
+/// A thunk shared behind an `Rc`.
+pub type RcShared<L> = Shared<L, RcPointer>;
+
+
+/// A thunk shared behind an `Arc`.
+pub type ArcShared<L> = Shared<L, ArcPointer>;
+
+
+impl<L, P: SharedPointer> Shared<L, P> {
+    /// Share an existing thunk, without forcing it.
+    #[inline]
+    pub fn new(thunk: L) -> Shared<L, P> {
+        Shared(P::new(thunk))
+    }
+
+
+    /// Get a reference to the shared thunk, without forcing it.
+    #[inline]
+    pub fn thunk(this: &Shared<L, P>) -> &L {
+        &this.0
+    }
+}
+
+
+impl<L, P: SharedPointer> Clone for Shared<L, P> {
+    #[inline]
+    fn clone(&self) -> Shared<L, P> {
+        Shared(self.0.clone())
+    }
+}
+
+
+impl<L: LazyRef, P: SharedPointer> Deref for Shared<L, P>
+    where L::Target: Into<L>
+{
+    type Target = L::Target;
+
+    #[inline]
+    fn deref(&self) -> &L::Target {
+        self.0.force()
+    }
+}
+
+
+impl<L: LazyRef, P: SharedPointer> LazyRef for Shared<L, P>
+    where L::Target: Into<L> + Into<Shared<L, P>>
+{
+    #[inline]
+    fn force(&self) -> &L::Target {
+        self.0.force()
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<L: LazyLocal, P: SharedPointer> LazyLocal for Shared<L, P>
+    where L::Target: Into<L> + Into<Shared<L, P>>
+{
+    #[inline]
+    fn defer_local<'a, F: FnOnce() -> L::Target + 'a>(f: F) -> Shared<L, P>
+        where L::Target: 'a
+    {
+        Shared::new(L::defer(f))
+    }
+}
+
+
+impl<L: LazySend, P: SharedPointer> LazySend for Shared<L, P>
+    where L::Target: Into<L> + Into<Shared<L, P>>
+{
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> L::Target + Send + 'a>(f: F) -> Shared<L, P>
+        where L::Target: 'a
+    {
+        Shared::new(L::defer_send(f))
+    }
+}
+
+
+impl<L: Lazy, P: SharedPointer> LazyShared for Shared<L, P>
+    where L::Target: Into<L> + Into<Shared<L, P>>
+{
+    #[inline]
+    fn try_unwrap(this: Shared<L, P>) -> Result<L::Target, Shared<L, P>> {
+        P::try_unwrap(this.0).map(Lazy::unwrap).map_err(Shared)
+    }
+
+
+    #[inline]
+    fn get_mut(this: &mut Shared<L, P>) -> Option<&mut L::Target> {
+        P::get_mut(&mut this.0).map(LazyMut::force_mut)
+    }
+
+
+    fn make_mut(this: &mut Shared<L, P>) -> &mut L::Target
+        where L::Target: Clone
+    {
+        if P::get_mut(&mut this.0).is_none() {
+            this.0 = P::new(L::computed(this.force().clone()));
+        }
+
+        P::get_mut(&mut this.0).unwrap().force_mut()
+    }
+
+
+    #[inline]
+    fn ptr_eq(this: &Shared<L, P>, other: &Shared<L, P>) -> bool {
+        P::ptr_eq(&this.0, &other.0)
+    }
+}
+
+
+impl<T, P: SharedPointer> From<T> for Shared<Thunk<T>, P> {
+    #[inline]
+    fn from(t: T) -> Shared<Thunk<T>, P> {
+        Shared::new(Thunk::computed(t))
+    }
+}
+
+
+impl<T, P: SharedPointer> From<T> for Shared<AtomicThunk<T>, P> {
+    #[inline]
+    fn from(t: T) -> Shared<AtomicThunk<T>, P> {
+        Shared::new(AtomicThunk::computed(t))
+    }
+}
+
+
+impl<T, P: SharedPointer> From<T> for Shared<Strict<T>, P> {
+    #[inline]
+    fn from(t: T) -> Shared<Strict<T>, P> {
+        Shared::new(Strict::computed(t))
+    }
+}
+
+
+impl<L: fmt::Debug, P: SharedPointer> fmt::Debug for Shared<L, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Shared").field(&*self.0).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+
+    fn shared_make_mut<L: Lazy + LazySend<Target = Vec<u32>>, P: SharedPointer>()
+        where Vec<u32>: Into<L> + Into<Shared<L, P>>
+    {
+        let mut shared0 = Shared::<L, P>::defer_send(|| vec![1, 2]);
+        let shared1 = shared0.clone();
+
+        assert!(LazyShared::ptr_eq(&shared0, &shared1));
+        LazyShared::make_mut(&mut shared0).push(3);
+        assert!(!LazyShared::ptr_eq(&shared0, &shared1));
+
+        assert_eq!(*shared0, vec![1, 2, 3]);
+        assert_eq!(LazyShared::try_unwrap(shared1).ok(), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn shared_rc() {
+        shared_make_mut::<Thunk<Vec<u32>>, RcPointer>();
+        shared_make_mut::<Strict<Vec<u32>>, RcPointer>();
+    }
+
+    #[test]
+    fn shared_arc() {
+        shared_make_mut::<AtomicThunk<Vec<u32>>, ArcPointer>();
+
+        let shared: ArcShared<AtomicThunk<u32>> = Shared::defer_send(|| 1 + 1);
+        let handle = {
+            let shared = shared.clone();
+            thread::spawn(move || *shared)
+        };
+
+        assert_eq!(handle.join().unwrap(), 2);
+        assert!(shared.is_evaluated());
+    }
+}
@@ -0,0 +1,181 @@
+//! Speculative evaluation. A `SpeculativeThunk` starts running its computation
+//! on a background thread as soon as it is constructed, so that by the time it
+//! is forced the value is often already available.
+
+use std::fmt;
+use std::ops::Deref;
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crate::{LazyRef, Lazy};
+use crate::sync::AtomicThunk;
+
+
+/// A thread-safe thunk which begins evaluating in the background when it is
+/// deferred. Forcing it takes the finished result, or blocks until the
+/// background computation finishes. If the computation panics, the panic is
+/// propagated to the thread which forces the thunk.
+///
+/// Each deferred `SpeculativeThunk` spawns its own thread, so it is best
+/// suited to computations which are expensive relative to spawning a thread.
+pub struct SpeculativeThunk<T> {
+    thunk: AtomicThunk<T>,
+    ready: Arc<AtomicBool>,
+}
+
+
+impl<T: Send + 'static> SpeculativeThunk<T> {
+    /// Start running `f` on a background thread.
+    pub fn defer<F: FnOnce() -> T + Send + 'static>(f: F) -> SpeculativeThunk<T> {
+        let ready = Arc::new(AtomicBool::new(false));
+        let ready_bg = ready.clone();
+
+        let handle = thread::spawn(move || {
+            let value = f();
+            ready_bg.store(true, Ordering::Release);
+            value
+        });
+
+        SpeculativeThunk {
+            thunk: AtomicThunk::defer(move || match handle.join() {
+                Ok(value) => value,
+                Err(payload) => panic::resume_unwind(payload),
+            }),
+            ready,
+        }
+    }
+}
+
+
+impl<T> SpeculativeThunk<T> {
+    /// Construct an already computed `SpeculativeThunk`. No thread is spawned.
+    #[inline]
+    pub fn computed(t: T) -> SpeculativeThunk<T> {
+        SpeculativeThunk {
+            thunk: AtomicThunk::computed(t),
+            ready: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+
+    /// Take the result of the background computation, blocking until it is
+    /// finished if necessary, and return a reference to it.
+    #[inline]
+    pub fn force(&self) -> &T {
+        self.thunk.force()
+    }
+
+
+    /// Check whether the thunk has been forced.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.thunk.is_evaluated()
+    }
+
+
+    /// Check whether the background computation has finished, meaning that
+    /// forcing the thunk will not block. This is `false` if the computation
+    /// panicked.
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+
+    /// Get a reference to the value if the thunk has already been forced.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.thunk.try_get()
+    }
+
+
+    /// Force the thunk and take ownership of its value.
+    #[inline]
+    pub fn unwrap(self) -> T {
+        self.thunk.unwrap()
+    }
+}
+
+
+impl<T> AsRef<T> for SpeculativeThunk<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T> Deref for SpeculativeThunk<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T> From<T> for SpeculativeThunk<T> {
+    #[inline]
+    fn from(t: T) -> SpeculativeThunk<T> {
+        SpeculativeThunk::computed(t)
+    }
+}
+
+
+impl<T: fmt::Debug> fmt::Debug for SpeculativeThunk<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpeculativeThunk")
+            .field("value", &self.try_get())
+            .field("ready", &self.is_ready())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn speculative_thunk_runs_before_force() {
+        let (tx, rx) = mpsc::channel();
+        let thunk = SpeculativeThunk::defer(move || {
+            tx.send(()).unwrap();
+            42
+        });
+
+        rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 42);
+        assert!(thunk.is_ready());
+    }
+
+
+    #[test]
+    fn speculative_thunk_shared() {
+        let thunk = Arc::new(SpeculativeThunk::defer(|| (0..1000u64).sum::<u64>()));
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let thunk = thunk.clone();
+            thread::spawn(move || **thunk)
+        }).collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 499500);
+        }
+    }
+
+
+    #[test]
+    #[should_panic]
+    fn speculative_thunk_propagates_panic() {
+        let thunk: SpeculativeThunk<()> = SpeculativeThunk::defer(|| panic!("speculation failed"));
+        thunk.force();
+    }
+}
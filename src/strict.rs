@@ -1,6 +1,6 @@
-use std::ops::{Deref, DerefMut};
+use core::ops::{Deref, DerefMut};
 
-use crate::{LazyRef, LazyMut, Lazy};
+use crate::{LazyRef, LazyMut, Lazy, LazyLocal, LazySend};
 
 
 /// A do-nothing, strict "thunk". This is intended for implementing structures which
@@ -9,6 +9,30 @@ use crate::{LazyRef, LazyMut, Lazy};
 pub struct Strict<T>(T);
 
 
+impl<T> Strict<T> {
+    /// Construct a strict thunk of a value. Unlike `LazyRef::computed`, this
+    /// is a `const fn`, so it can initialize `const` and `static` items.
+    #[inline]
+    pub const fn computed(t: T) -> Strict<T> {
+        Strict(t)
+    }
+
+
+    /// Combine two strict thunks into a strict thunk of a pair.
+    #[inline]
+    pub fn zip<U>(self, other: Strict<U>) -> Strict<(T, U)> {
+        Strict((self.0, other.0))
+    }
+
+
+    /// Combine two strict thunks with a function, which is run immediately.
+    #[inline]
+    pub fn zip_with<U, V, F: FnOnce(T, U) -> V>(self, other: Strict<U>, f: F) -> Strict<V> {
+        Strict(f(self.0, other.0))
+    }
+}
+
+
 impl<T> From<T> for Strict<T> {
     fn from(t: T) -> Strict<T> {
         Strict(t)
@@ -48,17 +72,42 @@ impl<T> DerefMut for Strict<T> {
 
 impl<T> LazyRef for Strict<T> {
     #[inline]
-    fn defer<'a, F: FnOnce() -> T + 'a>(f: F) -> Strict<T> {
+    fn force(&self) -> &T {
+        &self.0
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        true
+    }
+}
+
+
+impl<T> LazyLocal for Strict<T> {
+    #[inline]
+    fn defer_local<'a, F: FnOnce() -> T + 'a>(f: F) -> Strict<T> {
         Strict(f())
     }
+}
 
 
+impl<T> LazySend for Strict<T> {
     #[inline]
-    fn force(&self) {}
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> Strict<T>
+        where T: 'a
+    {
+        Strict(f())
+    }
 }
 
 
-impl<T> LazyMut for Strict<T> {}
+impl<T> LazyMut for Strict<T> {
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
 
 
 impl<T> Lazy for Strict<T> {
@@ -71,7 +120,11 @@ impl<T> Lazy for Strict<T> {
 mod test {
     use super::*;
 
-    use test::{self, Bencher};
+    #[cfg(all(feature = "nightly", not(feature = "std")))]
+    use std::vec::Vec;
+    use std::hint::black_box;
+    #[cfg(feature = "nightly")]
+    use test::Bencher;
 
     #[test]
     fn thunk_computed() {
@@ -80,36 +133,47 @@ mod test {
         assert_eq!(*thunk, 2);
     }
 
+    #[test]
+    fn thunk_computed_const() {
+        static TWO: Strict<u32> = Strict::computed(2);
+
+        assert_eq!(*TWO, 2);
+    }
+
     #[test]
     fn thunk_deferred() {
-        let thunk = Strict::defer(|| test::black_box(1) + 1);
+        let thunk = Strict::defer(|| black_box(1) + 1);
 
         assert_eq!(*thunk, 2);
     }
 
+    #[cfg(feature = "nightly")]
     fn ten_thousand_xors_strict(n: usize) -> Strict<usize> {
-        Strict::computed((0..test::black_box(10000)).fold(test::black_box(n), |old, new| old ^ new))
+        Strict::computed((0..black_box(10000)).fold(black_box(n), |old, new| old ^ new))
     }
 
+    #[cfg(feature = "nightly")]
     fn ten_thousand_xors_lazy(n: usize) -> Strict<usize> {
         Strict::defer(move || {
-                         (0..test::black_box(10000)).fold(test::black_box(n), |old, new| old ^ new)
+                         (0..black_box(10000)).fold(black_box(n), |old, new| old ^ new)
                      })
     }
 
+    #[cfg(feature = "nightly")]
     #[bench]
     fn ten_thousand_xors_threadsafe_strict(b: &mut Bencher) {
         b.iter(|| {
                    let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_strict).collect();
-                   test::black_box(things.pop())
+                   black_box(things.pop())
                })
     }
 
+    #[cfg(feature = "nightly")]
     #[bench]
     fn ten_thousand_xors_threadsafe_lazy(b: &mut Bencher) {
         b.iter(|| {
             let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_lazy).collect();
-            test::black_box(things.pop())
+            black_box(things.pop())
         })
     }
 }
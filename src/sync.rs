@@ -1,83 +1,233 @@
-use std::borrow::{Borrow, BorrowMut};
-use std::cell::UnsafeCell;
-use std::mem;
-use std::ops::{Deref, DerefMut};
-use std::ptr;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-use unreachable::{unreachable, UncheckedOptionExt};
-
-use crate::{LazyRef, LazyMut, Lazy};
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::{Arc, Weak};
+#[cfg(feature = "allocator_api")]
+use core::alloc::Allocator;
+use core::any::Any;
+use core::borrow::{Borrow, BorrowMut};
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::{self, ManuallyDrop, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+#[cfg(feature = "std")]
+use std::cell::RefCell;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(loom)]
+use loom::thread_local;
+
+#[cfg(feature = "stable_deref")]
+use stable_deref_trait::{CloneStableDeref, StableDeref};
+use unreachable::unreachable;
+
+use crate::{LazyRef, LazyMut, Lazy, LazySend, LazyShared};
+#[cfg(feature = "std")]
+use crate::atomic::AtomicBool;
+#[cfg(feature = "std")]
+use crate::atomic::thread::{self, JoinHandle, Thread};
+use crate::atomic::{get_unique, hint, set_unique, AtomicUsize, Ordering};
+use crate::erased::{never_called, Deferred, ErasedClosure};
+
+
+/// Define a `const fn`, except under `loom`, whose atomics cannot be
+/// constructed in a constant context.
+macro_rules! const_unless_loom {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($rest)*
+
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
 
 
 /// A thread-safe `AtomicThunk`, representing a lazily computed value.
 ///
+/// An `AtomicThunk<T>` is `Send` whenever `T` is. It is only `Sync` if `T` is
+/// both `Send` and `Sync`, since forcing it through a shared reference may
+/// compute the value on one thread and hand it to others. A `Cell` may be
+/// moved between threads, but not computed by a shared `AtomicThunk`:
+///
+/// ```compile_fail
+/// use std::cell::Cell;
+/// use thunk::AtomicThunk;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<AtomicThunk<Cell<u32>>>();
+/// ```
+///
+/// Nor may a value which must stay on the thread which created it, such as a
+/// `MutexGuard`, even though it is `Sync`:
+///
+/// ```compile_fail
+/// use std::sync::MutexGuard;
+/// use thunk::AtomicThunk;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<AtomicThunk<MutexGuard<'static, u32>>>();
+/// ```
+///
+/// As with `Thunk`, pinning an `AtomicThunk` pins its value, which
+/// `get_pin_mut` projects to; an `AtomicThunk<T>` is `Unpin` only if `T` is.
+///
 /// TODO: Test `Option<UnsafeCell<Cache<T>>>` instead of storing thunk
 /// invalidation in the atomic `flag`.
 pub struct AtomicThunk<T> {
-    /// The `lock` mutex is used for preventing other threads from accessing the
-    /// thunk's internals when a thunk is evaluating.
-    lock: Mutex<()>,
-
-    /// The `flag` represents the current state of the thunk - deferred, evaluated,
-    /// locking, or locked.
+    /// The `flag` represents the current state of the thunk - deferred,
+    /// evaluated, running, poisoned, or invalidated. While the thunk is
+    /// running, it also holds the queue of threads waiting for the value; see
+    /// `force_with`.
     flag: AtomicUsize,
 
     /// The thunk and/or its computed result are stored in an `UnsafeCell` so that
     /// the fact that a `AtomicThunk` is either computed *or* non-computed can be made
     /// opaque to the user. This way, an immutable reference can have its thunk
     /// forced.
-    data: UnsafeCell<Cache<T>>,
+    data: UnsafeCell<MaybeUninit<Cache<T>>>,
 }
 
 
+// Every closure stored in an `AtomicThunk` is `Send`, or by the contract of
+// `defer_local` never leaves its thread, so only the value matters. Any thread
+// holding a shared reference may be the one to compute the value, so sharing
+// the thunk sends the value as well as sharing it.
 unsafe impl<T: Send> Send for AtomicThunk<T> {}
-unsafe impl<T: Sync> Sync for AtomicThunk<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomicThunk<T> {}
 
 
 /// The `AtomicThunk` is not yet evaluated. We can try to lock it and evaluate.
+/// The `deferred` field of the cache holds the closure.
 const THUNK_DEFERRED: usize = 0;
 
-/// The `AtomicThunk` is evaluated, and can be safely accessed.
+/// The `AtomicThunk` is evaluated, and can be safely accessed. The
+/// `evaluated` field of the cache holds the value.
 const THUNK_EVALUATED: usize = 1;
 
-/// The `AtomicThunk` is currently *locking* - the `Mutex` is not yet locked but will
-/// be very soon.
-const THUNK_LOCKING: usize = 2;
+/// Some thread is evaluating the `AtomicThunk`, and has moved the closure out
+/// of the cache, leaving it uninitialized. The rest of the flag's bits point
+/// to the queue of threads waiting for it to finish, if there are any.
+/// A deferred `AtomicThunk` may also have a queue, of threads which are
+/// waiting for some other thread to force it.
+const THUNK_RUNNING: usize = 2;
+
+/// The computation of the `AtomicThunk` panicked. The closure is gone, and
+/// there is no value to take its place, so the cache is uninitialized and
+/// forcing the thunk again panics.
+const THUNK_POISONED: usize = 3;
+
+/// There is no data in the `AtomicThunk` - it has been removed and dealt with,
+/// or it has never been supplied, and the cache is uninitialized. Thus, the
+/// thunk is invalidated and should only be dropped, unless it is the backing
+/// thunk of an uninitialized `AtomicLateThunk` or `FnPtrThunk`.
+const THUNK_INVALIDATED: usize = 4;
+
+/// The bits of the flag which hold the state; the rest hold the queue of a
+/// running thunk.
+const STATE_MASK: usize = 0b111;
 
-/// The thread which is going to evaluate the `AtomicThunk` has a lock on the `Mutex`.
-/// When the `Mutex` becomes unlocked, the computed result may be accessed.
-const THUNK_LOCKED: usize = 3;
 
-/// There is no data in the `AtomicThunk` - it has been removed and dealt with. Thus,
-/// the thunk is invalidated and should only be dropped. Any function which can
-/// put the thunk in this state is already marked unsafe.
-const THUNK_INVALIDATED: usize = 4;
+/// A snapshot of the state of an `AtomicThunk`. Since other threads may force
+/// the thunk at any time, the state may already be stale by the time it is
+/// inspected; however, once a thunk is `Evaluated`, it stays that way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThunkState {
+    /// The thunk has not been forced yet.
+    Deferred,
+
+    /// Some thread is currently evaluating the thunk.
+    Evaluating,
+
+    /// The thunk has been evaluated, and its value can be accessed without
+    /// blocking.
+    Evaluated,
+
+    /// The computation of the thunk panicked, so it will never have a value.
+    Poisoned,
+
+    /// The thunk holds neither a closure nor a value: it is the backing thunk
+    /// of an `AtomicLateThunk` or `FnPtrThunk` which has no value yet.
+    Empty,
+}
+
+
+/// The error produced by a non-blocking attempt to force a thunk which some
+/// other thread is currently evaluating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WouldBlock;
+
+
+impl fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the thunk is being evaluated by another thread")
+    }
+}
+
+
+#[cfg(feature = "std")]
+impl Error for WouldBlock {}
+
+
+/// The error produced by forcing a thunk with a timeout, if some other thread
+/// was still evaluating it when the timeout expired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeout;
+
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("timed out waiting for another thread to evaluate the thunk")
+    }
+}
+
+
+#[cfg(feature = "std")]
+impl Error for Timeout {}
+
+
+/// The error produced by forcing a thunk whose computation panicked, whether
+/// on this thread or another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Poisoned;
+
+
+impl fmt::Display for Poisoned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the computation of the thunk panicked")
+    }
+}
+
+
+#[cfg(feature = "std")]
+impl Error for Poisoned {}
 
 
 /// The storage for a possibly deferred, thread-safe thunk. A thunk is either
-/// deferred - in which case it contains a boxed closure which holds necessary
-/// data to run the deferred computation; or, it holds the already computed
-/// result.
-#[allow(unions_with_drop_fields)]
+/// deferred - in which case it contains a closure (boxed if it is too large to
+/// store inline) which holds necessary data to run the deferred computation;
+/// or, it holds the already computed result. Which one, if either, is given by
+/// the state of the thunk's flag; in the other states, the surrounding
+/// `MaybeUninit` is left uninitialized.
 union Cache<T> {
-    deferred: Box<dyn FnOnce() -> ()>,
-    evaluated: T,
-
-    #[allow(dead_code)]
-    evaluating: (),
+    deferred: ManuallyDrop<ErasedClosure<T>>,
+    evaluated: ManuallyDrop<T>,
 }
 
 
 impl<T> Drop for AtomicThunk<T> {
     fn drop(&mut self) {
-        match unsafe { ptr::read(&self.flag) }.into_inner() {
-            THUNK_DEFERRED => mem::drop(unsafe { self.take_data().deferred }),
-            THUNK_EVALUATED => mem::drop(unsafe { self.take_data().evaluated }),
-            THUNK_INVALIDATED => {}
-            THUNK_LOCKING | THUNK_LOCKED => {
-                unreachable!("thunks should never be dropped while locking or locked!")
+        match settle(&mut self.flag) {
+            THUNK_DEFERRED => mem::drop(unsafe { ManuallyDrop::into_inner(self.take_data().deferred) }),
+            THUNK_EVALUATED => mem::drop(unsafe { ManuallyDrop::into_inner(self.take_data().evaluated) }),
+            THUNK_POISONED | THUNK_INVALIDATED => {}
+            THUNK_RUNNING => {
+                unreachable!("thunks should never be dropped while running!")
             }
             _ => unsafe { unreachable() },
         }
@@ -89,14 +239,13 @@ impl<T> Cache<T> {
     /// PRECONDITION: `Cache` must be `Deferred`! UB results otherwise.
     ///
     /// Evaluate the thunk and replace the `Cache` with an `Evaluated` value
-    /// containing the computed result.
+    /// containing the computed result. While the closure runs, and if it
+    /// panics, the cache holds nothing.
     #[inline]
     unsafe fn evaluate_thunk(&mut self) {
-        let Cache { deferred: thunk } = mem::replace(self, Cache { evaluating: () });
+        let thunk = ManuallyDrop::take(&mut self.deferred);
 
-        let thunk_cast = Box::from_raw(Box::into_raw(thunk) as *mut dyn FnOnce() -> T);
-
-        mem::replace(self, Cache { evaluated: thunk_cast() });
+        *self = Cache { evaluated: ManuallyDrop::new(thunk.call()) };
     }
 }
 
@@ -138,9 +287,7 @@ impl<T> Deref for AtomicThunk<T> {
 
     #[inline]
     fn deref(&self) -> &T {
-        self.force();
-
-        unsafe { &self.data.get().as_ref().unchecked_unwrap().evaluated }
+        self.force()
     }
 }
 
@@ -148,300 +295,3278 @@ impl<T> Deref for AtomicThunk<T> {
 impl<T> DerefMut for AtomicThunk<T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut T {
-        self.force();
+        self.force_mut()
+    }
+}
 
-        unsafe { &mut self.data.get().as_mut().unchecked_unwrap().evaluated }
+
+/// Force a thread-safe thunk whose state is kept in `flag`, running
+/// `evaluate` if this thread is the one which gets to evaluate it, and waiting
+/// for whichever thread does otherwise. `evaluate` must leave the thunk's
+/// value ready to be read. This is shared by `AtomicThunk` and `ArcThunk`.
+///
+/// This is the same protocol as `std::sync::Once`: the evaluating thread
+/// moves the flag to THUNK_RUNNING, and threads which find it running push
+/// themselves onto a queue threaded through the flag and park until the
+/// evaluating thread publishes the value and wakes them.
+///
+/// If the evaluation panics, whether on this thread or another, the thunk is
+/// poisoned and `Poisoned` is returned from then on.
+#[inline]
+unsafe fn force_with<F: FnOnce()>(flag: &AtomicUsize, evaluate: F) -> Result<(), Poisoned> {
+    // Most forces find the thunk long since evaluated, so check for that with
+    // a plain load, which leaves the cache line shared between the readers.
+    if flag.load(Ordering::Acquire) == THUNK_EVALUATED {
+        return Ok(());
     }
+
+    transaction(|| force_slow(flag, evaluate))
 }
 
 
-impl<T> From<T> for AtomicThunk<T> {
-    #[inline]
-    fn from(t: T) -> Self {
-        AtomicThunk {
-            lock: Mutex::new(()),
-            flag: AtomicUsize::new(THUNK_EVALUATED),
-            data: UnsafeCell::new(Cache { evaluated: t }),
+/// The rest of `force_with`, kept out of line so that the fast path inlines
+/// into every force.
+#[cold]
+#[inline(never)]
+unsafe fn force_slow<F: FnOnce()>(flag: &AtomicUsize, evaluate: F) -> Result<(), Poisoned> {
+    match claim(flag) {
+        // If we've successfully taken control of the AtomicThunk:
+        Ok(_) => {
+            run(flag, evaluate, THUNK_POISONED);
+            Ok(())
         }
+
+        // If the `AtomicThunk` is evaluated, do nothing.
+        Err(THUNK_EVALUATED) => Ok(()),
+
+        Err(THUNK_POISONED) => Err(Poisoned),
+
+        // A thunk is only left invalidated if a transformation passed to
+        // `modify` panicked.
+        Err(THUNK_INVALIDATED) => panic!("attempted to force an invalidated `AtomicThunk`"),
+
+        // If the `AtomicThunk` is running, wait until the thunk is done
+        // evaluating.
+        Err(state) => match besiege(flag, state, None) {
+            Ok(THUNK_EVALUATED) => Ok(()),
+            Ok(THUNK_POISONED) => Err(Poisoned),
+            Ok(_) => panic!("attempted to force an invalidated `AtomicThunk`"),
+            Err(_) => unreachable(),
+        },
     }
 }
 
 
-impl<T> AtomicThunk<T> {
-    #[inline]
-    fn take_data(&mut self) -> Cache<T> {
-        self.flag.store(THUNK_INVALIDATED, Ordering::Relaxed);
-        mem::replace(&mut self.data, UnsafeCell::new(Cache { evaluating: () })).into_inner()
-    }
+/// Panic on forcing a poisoned thunk.
+#[cold]
+#[inline(never)]
+fn poisoned() -> ! {
+    panic!("attempted to force an `AtomicThunk` whose computation panicked")
+}
 
 
-    /// PRECONDITIONS: flag must not be THUNK_DEFERRED or THUNK_INVALIDATED.
-    ///
-    /// `.besiege()` expects an evaluated or locked `AtomicThunk`.
-    /// - If the `AtomicThunk` is locking, it will spin until the `AtomicThunk` is locked and
-    ///   then wait to acquire and summarily release the mutex.
-    /// - If the `AtomicThunk` is locked, it will wait for a lock on the mutex before
-    ///   immediately releasing it and returning.
-    /// - If the `AtomicThunk` is evaluated, it will immediately return.
-    #[inline]
-    unsafe fn besiege(&self) {
-        loop {
-            match self.flag.load(Ordering::Acquire) {
-                // If the AtomicThunk has been evaluated, unwrap it and return it.
-                THUNK_EVALUATED => return,
-
-                // If we're waiting for the lock to become available, then spin.
-                THUNK_LOCKING => {}
-
-                // If the lock is available, lock it so that we can stop
-                // spinning in place.
-                THUNK_LOCKED => {
-                    let _ = self.lock.lock().unwrap();
-                    return;
-                }
+/// Like `force_with`, but if another thread is evaluating the thunk, wait for
+/// it only until `deadline`. If this thread gets to evaluate the thunk, the
+/// evaluation always runs to completion.
+#[cfg(feature = "std")]
+#[inline]
+unsafe fn force_until<F: FnOnce()>(flag: &AtomicUsize, evaluate: F, deadline: Instant) -> Result<(), Timeout> {
+    transaction(|| match claim(flag) {
+        Ok(_) => {
+            run(flag, evaluate, THUNK_POISONED);
+            Ok(())
+        }
+        Err(THUNK_EVALUATED) => Ok(()),
+        Err(THUNK_POISONED) => poisoned(),
+        Err(THUNK_INVALIDATED) => panic!("attempted to force an invalidated `AtomicThunk`"),
+        Err(state) => match besiege(flag, state, Some(deadline))? {
+            THUNK_EVALUATED => Ok(()),
+            THUNK_POISONED => poisoned(),
+            _ => panic!("attempted to force an invalidated `AtomicThunk`"),
+        },
+    })
+}
 
-                THUNK_DEFERRED |
-                THUNK_INVALIDATED |
-                _ => unreachable(),
-            }
+
+/// Like `force_with`, but if another thread is evaluating the thunk, return
+/// `WouldBlock` instead of waiting for it.
+#[inline]
+unsafe fn try_force_with<F: FnOnce()>(flag: &AtomicUsize, evaluate: F) -> Result<(), WouldBlock> {
+    transaction(|| match claim(flag) {
+        Ok(_) => {
+            run(flag, evaluate, THUNK_POISONED);
+            Ok(())
         }
-    }
+        Err(THUNK_EVALUATED) => Ok(()),
+        Err(THUNK_POISONED) => poisoned(),
+        Err(THUNK_INVALIDATED) => panic!("attempted to force an invalidated `AtomicThunk`"),
+        Err(_) => Err(WouldBlock),
+    })
 }
 
 
-impl<T> LazyRef for AtomicThunk<T> {
-    #[inline]
-    fn defer<'a, F: FnOnce() -> T + 'a>(f: F) -> AtomicThunk<T>
-        where T: 'a
-    {
-        let thunk = unsafe {
-            let thunk_raw: *mut dyn FnOnce() -> T = Box::into_raw(Box::new(f));
-            Box::from_raw(thunk_raw as *mut (dyn FnOnce() -> () + 'static))
-        };
+/// Run one attempt to force a thunk, from claiming it to publishing its value.
+/// With the `interrupt_safe` feature, this is a critical section. An interrupt
+/// handler can then never find a thunk which the code it interrupted was
+/// evaluating, and wait for it forever.
+#[cfg(feature = "interrupt_safe")]
+#[inline]
+fn transaction<R, F: FnOnce() -> R>(f: F) -> R {
+    critical_section::with(|_| f())
+}
 
-        AtomicThunk {
-            lock: Mutex::new(()),
-            flag: AtomicUsize::new(THUNK_DEFERRED),
-            data: UnsafeCell::new(Cache { deferred: thunk }),
+
+#[cfg(not(feature = "interrupt_safe"))]
+#[inline(always)]
+fn transaction<R, F: FnOnce() -> R>(f: F) -> R {
+    f()
+}
+
+
+/// Move a deferred thunk to THUNK_RUNNING, keeping the queue of any threads
+/// which are waiting for it in `wait`. If the thunk is not deferred, its
+/// current state is returned instead.
+#[inline]
+fn claim(flag: &AtomicUsize) -> Result<(), usize> {
+    let mut state = flag.load(Ordering::Acquire);
+
+    while state & STATE_MASK == THUNK_DEFERRED {
+        match flag.compare_exchange_weak(state,
+                                         state | THUNK_RUNNING,
+                                         Ordering::Acquire,
+                                         Ordering::Acquire) {
+            Ok(_) => return Ok(()),
+            Err(current) => state = current,
         }
     }
 
+    Err(state)
+}
 
-    #[inline]
-    fn force(&self) {
-        match self.flag
-                  .compare_and_swap(THUNK_DEFERRED, THUNK_LOCKING, Ordering::Acquire) {
-            // If we've successfully taken control of the AtomicThunk:
-            THUNK_DEFERRED => {
-                // Lock the mutex, and then set the flag to THUNK_LOCKED so that
-                // other threads know that they can stop spinning and instead
-                // lock the mutex. This lets them consume less resources by
-                // relying on the scheduler to wake them up, rather than spin
-                // until the mutex is released. (??? is this true?)
-                let _mutex_lock = self.lock.lock().unwrap();
-                self.flag.store(THUNK_LOCKED, Ordering::Release);
 
-                unsafe {
-                    (*self.data.get()).evaluate_thunk();
-
-                    // The mutex will be unlocked at the end of the scope - first
-                    // though, we store THUNK_EVALUATED into the flag so that
-                    // threads released from the mutex see the correct "EVALUATED"
-                    // flag and threads which did not see THUNK_LOCKING or
-                    // THUNK_LOCKED and have not acquired the mutex are allowed
-                    // to grab the value.
-                    self.flag.store(THUNK_EVALUATED, Ordering::Release);
+/// Wait for another thread to evaluate the thunk, without ever evaluating it
+/// on this thread, until `deadline` passes if there is one.
+#[inline]
+unsafe fn wait_until(flag: &AtomicUsize, deadline: Option<Deadline>) -> Result<(), Timeout> {
+    match besiege(flag, flag.load(Ordering::Acquire), deadline)? {
+        THUNK_EVALUATED => Ok(()),
+        THUNK_POISONED => poisoned(),
+        _ => panic!("attempted to force an invalidated `AtomicThunk`"),
+    }
+}
+
+
+/// PRECONDITION: this thread must have just moved `flag` to THUNK_RUNNING.
+///
+/// Run `evaluate`, and publish the result to every thread waiting in
+/// `besiege`. If `evaluate` panics, the thunk is moved to `failed` instead,
+/// and the waiting threads are woken up to find out.
+#[inline]
+unsafe fn run<F: FnOnce()>(flag: &AtomicUsize, evaluate: F, failed: usize) {
+    enter(flag);
+    let mut publish = Publish { flag, state: failed };
+    evaluate();
+    publish.state = THUNK_EVALUATED;
+}
+
+
+/// PRECONDITIONS: `state` must be the current state of the thunk.
+///
+/// Park the current thread until some thread finishes evaluating the thunk, and
+/// return its final state: THUNK_EVALUATED, THUNK_POISONED if the evaluation
+/// panicked, or THUNK_INVALIDATED if it was filling the thunk and panicked. If
+/// `deadline` passes first, `Timeout` is returned.
+#[cfg(feature = "std")]
+#[inline]
+unsafe fn besiege(flag: &AtomicUsize, mut state: usize, deadline: Option<Deadline>) -> Result<usize, Timeout> {
+    // The node is shared with the queue, so that we can give up on waiting
+    // while the evaluating thread still holds a pointer to it.
+    let node = Arc::new(Waiter {
+        thread: thread::current(),
+        signaled: AtomicBool::new(false),
+        next: AtomicUsize::new(0),
+    });
+
+    let mut backoff = Backoff::new();
+
+    loop {
+        match state & STATE_MASK {
+            THUNK_EVALUATED | THUNK_POISONED | THUNK_INVALIDATED => return Ok(state & STATE_MASK),
+            THUNK_DEFERRED => {}
+            THUNK_RUNNING => {
+                if is_evaluating(flag) {
+                    panic!("<<loop>>: attempted to force an `AtomicThunk` from within its own \
+                            computation");
+                }
+
+                // Most evaluations are short, so spin for a while before
+                // paying for a trip through the queue and the scheduler.
+                if backoff.snooze() {
+                    state = flag.load(Ordering::Acquire);
+                    continue;
                 }
             }
+            _ => unreachable(),
+        }
 
-            // If the `AtomicThunk` is evaluated, do nothing.
-            THUNK_EVALUATED => {}
+        // Push ourselves onto the queue, which takes a reference to the node.
+        // The queue is made of addresses, so their provenance is exposed to
+        // turn them back into pointers later.
+        node.next.store(state & !STATE_MASK, Ordering::Relaxed);
+        let me = Arc::as_ptr(&node).expose_provenance();
+
+        if let Err(current) = flag.compare_exchange(state, me | (state & STATE_MASK), Ordering::Release, Ordering::Acquire) {
+            state = current;
+            continue;
+        }
 
-            // If the `AtomicThunk` is `LOCKING` or `LOCKED`, wait until the thunk is
-            // done evaluating and then return a reference to the inner value.
-            THUNK_LOCKING | THUNK_LOCKED => unsafe { self.besiege() },
+        mem::forget(node.clone());
 
-            // Only `THUNK_DEFERRED`, `THUNK_EVALUATED`, `THUNK_LOCKING`, and
-            // `THUNK_LOCKED` are valid values of the flag.
-            THUNK_INVALIDATED |
-            _ => unsafe { unreachable() },
+        // `park` may wake up spuriously, so only trust `signaled`.
+        while !node.signaled.load(Ordering::Acquire) {
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+
+                    if now >= deadline {
+                        unlink(flag, &node);
+                        return Err(Timeout);
+                    }
+
+                    #[cfg(not(loom))]
+                    thread::park_timeout(deadline - now);
+
+                    // `loom` has no clock, so the deadline is checked
+                    // every time this thread is scheduled instead.
+                    #[cfg(loom)]
+                    thread::yield_now();
+                }
+            }
         }
+
+        state = flag.load(Ordering::Acquire);
     }
 }
 
 
-impl<T> LazyMut for AtomicThunk<T> {}
+/// Take the node of a waiter which has given up back off the queue, so that a
+/// thread which keeps polling a thunk does not grow the queue without bound.
+/// Only the head of the queue can be unlinked, since the nodes behind it are
+/// not ours to read; a node with other waiters queued after it stays linked
+/// until the thunk is evaluated or dropped.
+#[cfg(feature = "std")]
+#[inline]
+unsafe fn unlink(flag: &AtomicUsize, node: &Arc<Waiter>) {
+    let me = Arc::as_ptr(node);
+    let mut state = flag.load(Ordering::Acquire);
+
+    while state & !STATE_MASK == me.addr() {
+        let next = node.next.load(Ordering::Relaxed) | (state & STATE_MASK);
+
+        match flag.compare_exchange_weak(state, next, Ordering::Acquire, Ordering::Acquire) {
+            // The queue's reference to the node is ours again.
+            Ok(_) => return mem::drop(Arc::from_raw(me)),
+            Err(current) => state = current,
+        }
+    }
+}
 
 
-impl<T> Lazy for AtomicThunk<T> {
-    #[inline]
-    fn unwrap(mut self) -> T {
-        self.force();
+/// Without `std`, there is no way to park a thread, so waiting threads spin
+/// until the evaluating thread publishes its result, and the queue is always
+/// empty. There is no clock either, so there can be no deadline.
+#[cfg(not(feature = "std"))]
+#[inline]
+unsafe fn besiege(flag: &AtomicUsize, mut state: usize, _: Option<Deadline>) -> Result<usize, Timeout> {
+    loop {
+        match state & STATE_MASK {
+            THUNK_EVALUATED | THUNK_POISONED | THUNK_INVALIDATED => return Ok(state & STATE_MASK),
+            THUNK_DEFERRED | THUNK_RUNNING => hint::spin_loop(),
+            _ => unreachable(),
+        }
 
-        unsafe { self.take_data().evaluated }
+        state = flag.load(Ordering::Acquire);
     }
 }
 
 
-/// An `Arc`-wrapped `AtomicThunk` which implements `LazyRef`.
-pub struct ArcThunk<T>(Arc<AtomicThunk<T>>);
+/// The point in time at which a waiting thread gives up.
+#[cfg(feature = "std")]
+type Deadline = Instant;
 
 
-impl<T> ArcThunk<T> {
-    /// If the `ArcThunk` is unevaluated, this will force it. If the `RcThunk` is
-    /// the sole, unique owner of the underlying thunk, this will return the forced
-    /// value; otherwise, it will return an `Err` containing the original `ArcThunk`.
-    pub fn try_unwrap(this: ArcThunk<T>) -> Result<T, ArcThunk<T>> {
-        match Arc::try_unwrap(this.0) {
-            Ok(thunk) => Ok(thunk.unwrap()),
-            Err(rc) => Err(ArcThunk(rc)),
-        }
-    }
+#[cfg(not(feature = "std"))]
+enum Deadline {}
 
 
-    /// If the `ArcThunk` is unevaluated, this will force it. If the `RcThunk` is
-    /// the sole, unique owner of the underlying thunk, this will return a
-    /// mutable reference to the forced value; otherwise, it will return `None`.
-    pub fn get_mut(this: &mut ArcThunk<T>) -> Option<&mut T> {
-        Arc::get_mut(&mut this.0).map(DerefMut::deref_mut)
-    }
+#[cfg(feature = "std")]
+thread_local! {
+    /// The flags of the thunks which this thread is currently evaluating,
+    /// innermost last. A thread which waits for one of these would be waiting
+    /// for itself. There is no `const` initializer, since `loom`'s
+    /// `thread_local!` does not accept one.
+    #[allow(clippy::missing_const_for_thread_local)]
+    static EVALUATING: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
 
 
-    /// If the `ArcThunk` is unevaluated, this will force it. If the `RcThunk`
-    /// is the sole, unique owner of the underlying thunk, this will return a
-    /// mutable reference to the forced value; if it is not, then it will clone
-    /// the forced value and return a mutable reference to the newly cloned
-    /// value. The `&mut ArcThunk` passed in will be updated to reference the
-    /// newly cloned value.
-    pub fn make_mut(this: &mut ArcThunk<T>) -> &mut T
-        where T: Clone
-    {
-        // No, moving it into a temp doesn't help. We just have to trust the CSE
-        // pass here. This is a known borrowchecking issue.
-        if Arc::get_mut(&mut this.0).is_some() {
-            return &mut **Arc::get_mut(&mut this.0)
-                              .expect("We know it's `some` - this won't change.");
-        }
+/// Record that this thread has started evaluating the thunk behind `flag`.
+/// Without `std`, thunks which force themselves are not detected, and spin
+/// forever instead.
+#[inline]
+fn enter(flag: &AtomicUsize) {
+    #[cfg(feature = "std")]
+    let _ = EVALUATING.try_with(|evaluating| evaluating.borrow_mut().push(flag as *const _ as usize));
 
-        let new_rc = Arc::new(AtomicThunk::computed((*this.0).clone()));
-        this.0 = new_rc;
-        ArcThunk::get_mut(this).unwrap()
-    }
+    #[cfg(not(feature = "std"))]
+    let _ = flag;
 }
 
 
-impl<T> Clone for ArcThunk<T> {
-    fn clone(&self) -> Self {
-        ArcThunk(self.0.clone())
-    }
+/// Record that this thread has finished its innermost evaluation.
+#[inline]
+fn leave() {
+    #[cfg(feature = "std")]
+    let _ = EVALUATING.try_with(|evaluating| evaluating.borrow_mut().pop());
 }
 
 
-impl<T> AsRef<T> for ArcThunk<T> {
-    fn as_ref(&self) -> &T {
-        &self.0
-    }
+/// Check whether this thread is the one evaluating the thunk behind `flag`.
+#[cfg(feature = "std")]
+#[cold]
+fn is_evaluating(flag: &AtomicUsize) -> bool {
+    let flag = flag as *const _ as usize;
+    EVALUATING.try_with(|evaluating| evaluating.borrow().contains(&flag)).unwrap_or(false)
 }
 
 
-impl<T> Deref for ArcThunk<T> {
-    type Target = T;
+/// Free the queue of a deferred thunk whose waiters have all given up, and
+/// return the bare state. A unique reference to the flag guarantees that no
+/// thread is still waiting.
+#[inline]
+fn settle(flag: &mut AtomicUsize) -> usize {
+    let state = get_unique(flag);
 
-    fn deref(&self) -> &T {
-        &self.0
+    #[cfg(feature = "std")]
+    {
+        let mut waiter = ptr::with_exposed_provenance::<Waiter>(state & !STATE_MASK);
+
+        while !waiter.is_null() {
+            let node = unsafe { Arc::from_raw(waiter) };
+            waiter = ptr::with_exposed_provenance(node.next.load(Ordering::Relaxed));
+        }
     }
+
+    set_unique(flag, state & STATE_MASK);
+    state & STATE_MASK
 }
 
 
-impl<T> From<T> for ArcThunk<T> {
-    fn from(t: T) -> ArcThunk<T> {
-        ArcThunk(Arc::new(AtomicThunk::computed(t)))
-    }
+/// Bounded exponential backoff for threads which find a thunk running: first
+/// spin for exponentially longer stretches, then yield to the scheduler a
+/// few times, and finally give up so that the thread can park.
+#[cfg(feature = "std")]
+struct Backoff {
+    step: u32,
 }
 
 
-impl<T> LazyRef for ArcThunk<T> {
+#[cfg(feature = "std")]
+impl Backoff {
+    /// Spin for `2^SPIN_LIMIT` iterations at most before yielding.
+    const SPIN_LIMIT: u32 = 6;
+
+    /// Give up and park after this many steps.
+    const YIELD_LIMIT: u32 = 10;
+
+
     #[inline]
-    fn defer<'a, F: FnOnce() -> T + 'a>(f: F) -> ArcThunk<T> {
-        ArcThunk(Arc::new(AtomicThunk::defer(f)))
+    fn new() -> Backoff {
+        Backoff { step: 0 }
     }
 
 
+    /// Back off once, returning `false` once it is time to park instead.
     #[inline]
-    fn force(&self) {
-        self.0.force();
+    fn snooze(&mut self) -> bool {
+        // Every spin is a scheduling point under `loom`, so go straight to
+        // the queue, which is the part worth model-checking.
+        if cfg!(loom) || self.step > Self::YIELD_LIMIT {
+            return false;
+        }
+
+        if self.step <= Self::SPIN_LIMIT {
+            for _ in 0..1 << self.step {
+                hint::spin_loop();
+            }
+        } else {
+            thread::yield_now();
+        }
+
+        self.step += 1;
+        true
     }
 }
 
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// A thread waiting for a running thunk. Nodes are aligned so that the low
+/// bits of a pointer to one are free to hold the state of the thunk.
+#[cfg(feature = "std")]
+#[repr(align(8))]
+struct Waiter {
+    thread: Thread,
+    signaled: AtomicBool,
+    next: AtomicUsize,
+}
 
-    use test::{self, Bencher};
 
-    #[test]
-    fn thunk_computed() {
-        let thunk = AtomicThunk::computed(1 + 1);
+/// Publishes the final state of a running thunk when dropped, even if the
+/// evaluation panicked, and wakes every waiting thread.
+struct Publish<'a> {
+    flag: &'a AtomicUsize,
+    state: usize,
+}
 
-        assert_eq!(*thunk, 2);
+
+impl<'a> Drop for Publish<'a> {
+    fn drop(&mut self) {
+        leave();
+
+        let queue = self.flag.swap(self.state, Ordering::AcqRel);
+        debug_assert_eq!(queue & STATE_MASK, THUNK_RUNNING);
+
+        #[cfg(feature = "std")]
+        {
+            let mut waiter = ptr::with_exposed_provenance::<Waiter>(queue & !STATE_MASK);
+
+            while !waiter.is_null() {
+                // The queue owns a reference to every node in it.
+                let node = unsafe { Arc::from_raw(waiter) };
+                waiter = ptr::with_exposed_provenance(node.next.load(Ordering::Relaxed));
+                node.signaled.store(true, Ordering::Release);
+                node.thread.unpark();
+            }
+        }
     }
+}
 
-    #[test]
-    fn thunk_deferred() {
-        let thunk = AtomicThunk::defer(|| test::black_box(1) + 1);
 
-        assert_eq!(*thunk, 2);
+/// A value which is known to be safe to send to another thread, even though
+/// its type does not say so.
+struct AssertSend<T>(T);
+
+
+unsafe impl<T> Send for AssertSend<T> {}
+
+
+impl<T> AssertSend<T> {
+    /// PRECONDITION: `t` must actually be safe to send to another thread.
+    #[inline]
+    unsafe fn new(t: T) -> AssertSend<T> {
+        AssertSend(t)
     }
 
-    fn ten_thousand_xors_strict(n: usize) -> AtomicThunk<usize> {
-        AtomicThunk::computed((0..test::black_box(10000))
-                                  .fold(test::black_box(n), |old, new| old ^ new))
+
+    #[inline]
+    fn into_inner(self) -> T {
+        self.0
     }
+}
 
-    fn ten_thousand_xors_lazy(n: usize) -> AtomicThunk<usize> {
-        AtomicThunk::defer(move || {
-                               (0..test::black_box(10000))
-                                   .fold(test::black_box(n), |old, new| old ^ new)
-                           })
+
+impl<T> From<T> for AtomicThunk<T> {
+    #[inline]
+    fn from(t: T) -> Self {
+        AtomicThunk::computed_const(t)
     }
+}
 
-    #[bench]
-    fn ten_thousand_xors_threadsafe_strict(b: &mut Bencher) {
-        b.iter(|| {
-                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_strict).collect();
-                   test::black_box(things.pop())
-               })
+
+impl<T> AtomicThunk<T> {
+    const_unless_loom! {
+        /// Construct a thunk with a precomputed value. Unlike
+        /// `LazyRef::computed`, this is a `const fn`, so it can initialize
+        /// `static` items.
+        #[inline]
+        pub fn computed_const(t: T) -> AtomicThunk<T> {
+            AtomicThunk {
+                flag: AtomicUsize::new(THUNK_EVALUATED),
+                data: UnsafeCell::new(MaybeUninit::new(Cache { evaluated: ManuallyDrop::new(t) })),
+            }
+        }
     }
 
-    #[bench]
-    fn ten_thousand_xors_threadsafe_lazy(b: &mut Bencher) {
-        b.iter(|| {
-                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_lazy).collect();
-                   test::black_box(things.pop())
-               })
+
+    const_unless_loom! {
+        #[inline]
+        fn invalidated() -> AtomicThunk<T> {
+            AtomicThunk {
+                flag: AtomicUsize::new(THUNK_INVALIDATED),
+                data: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }
     }
 
 
-    #[test]
-    fn arc_thunk_computed() {
-        let arc_thunk0 = ArcThunk::computed(1 + 1);
-        let arc_thunk1 = arc_thunk0.clone();
+    /// If the thunk is invalidated, lock it and fill it with the result of
+    /// `f`; otherwise, hand `f` back unused.
+    fn try_fill<F: FnOnce() -> T>(&self, f: F) -> Result<(), F> {
+        transaction(|| match self.flag.compare_exchange(THUNK_INVALIDATED,
+                                                        THUNK_RUNNING,
+                                                        Ordering::Acquire,
+                                                        Ordering::Acquire) {
+            Ok(_) => {
+                // This is the same protocol as forcing a deferred thunk,
+                // except that a panic leaves the thunk uninitialized rather
+                // than poisoned, so that it may be filled again.
+                unsafe {
+                    run(&self.flag, || self.cache().write(Cache { evaluated: ManuallyDrop::new(f()) }), THUNK_INVALIDATED);
+                }
 
-        assert_eq!(arc_thunk0.0.flag.load(Ordering::Relaxed), THUNK_EVALUATED);
-        assert_eq!(&*arc_thunk1, &2);
-        assert_eq!(arc_thunk0.0.flag.load(Ordering::Relaxed), THUNK_EVALUATED);
-        assert_eq!(&*arc_thunk0, &2);
+                Ok(())
+            }
+            Err(_) => Err(f),
+        })
     }
 
-    #[test]
-    fn arc_thunk_deferred() {
-        let arc_thunk0 = ArcThunk::defer(move || test::black_box(1) + 1);
-        let arc_thunk1 = arc_thunk0.clone();
 
-        assert_eq!(arc_thunk0.0.flag.load(Ordering::Relaxed), THUNK_DEFERRED);
-        assert_eq!(&*arc_thunk1, &2);
-        assert_eq!(arc_thunk0.0.flag.load(Ordering::Relaxed), THUNK_EVALUATED);
-        assert_eq!(&*arc_thunk0, &2);
+    /// A pointer to the cache, which holds whatever the flag says it does.
+    #[inline]
+    fn cache(&self) -> *mut Cache<T> {
+        self.data.get() as *mut Cache<T>
+    }
+
+
+    /// Move the contents of the cache out, leaving the thunk invalidated.
+    /// Which field of the result is initialized depends on the flag
+    /// beforehand.
+    #[inline]
+    fn take_data(&mut self) -> Cache<T> {
+        self.flag.store(THUNK_INVALIDATED, Ordering::Relaxed);
+        unsafe { ptr::read(self.cache()) }
+    }
+
+
+    /// Defer a computation stored as a `FnOnce` closure. Since any thread may
+    /// end up forcing the thunk, the closure must be `Send`.
+    #[inline]
+    pub fn defer<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> AtomicThunk<T>
+        where T: 'a
+    {
+        AtomicThunk {
+            flag: AtomicUsize::new(THUNK_DEFERRED),
+            data: UnsafeCell::new(MaybeUninit::new(Cache { deferred: ManuallyDrop::new(ErasedClosure::new(f)) })),
+        }
+    }
+
+
+    /// Defer a computation whose closure need not be `Send`.
+    ///
+    /// # Safety
+    ///
+    /// The closure must only be run or dropped on the thread which created it:
+    /// until the thunk has been forced, it must not be forced, dropped, or
+    /// taken apart on any other thread. Once it has been forced, the thunk may
+    /// be shared freely.
+    #[inline]
+    pub unsafe fn defer_local<'a, F: FnOnce() -> T + 'a>(f: F) -> AtomicThunk<T>
+        where T: 'a
+    {
+        let f = AssertSend::new(f);
+        AtomicThunk::defer(move || f.into_inner()())
+    }
+
+
+    /// Defer a computation, allocating its closure with `alloc` if it is too
+    /// large to be stored inline in the thunk.
+    #[cfg(feature = "allocator_api")]
+    #[inline]
+    pub fn defer_in<'a, F, A>(f: F, alloc: A) -> AtomicThunk<T>
+        where F: FnOnce() -> T + Send + 'a, A: Allocator + Send + 'a, T: 'a
+    {
+        AtomicThunk {
+            flag: AtomicUsize::new(THUNK_DEFERRED),
+            data: UnsafeCell::new(MaybeUninit::new(Cache { deferred: ManuallyDrop::new(ErasedClosure::new_in(f, alloc)) })),
+        }
+    }
+
+
+    /// Get a reference to the computed value if the thunk has already been
+    /// evaluated. This performs a single atomic load and never blocks: if the
+    /// thunk is deferred, or another thread is currently evaluating it, `None`
+    /// is returned.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        match self.flag.load(Ordering::Acquire) {
+            THUNK_EVALUATED => unsafe { Some(&(*self.cache()).evaluated) },
+            _ => None,
+        }
+    }
+
+
+    /// Force a pinned thunk, and get a pinned mutable reference to its value.
+    #[inline]
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut T> {
+        // The value is only ever moved out through `&mut AtomicThunk<T>` or by
+        // value, neither of which is available from a pin unless `T: Unpin`.
+        unsafe { self.map_unchecked_mut(|thunk| thunk.force_mut()) }
+    }
+
+
+    /// Get a pinned mutable reference to the computed value if the pinned
+    /// thunk has already been evaluated. This never forces the thunk.
+    #[inline]
+    pub fn try_get_pin_mut(self: Pin<&mut Self>) -> Option<Pin<&mut T>> {
+        let this = unsafe { self.get_unchecked_mut() };
+
+        match get_unique(&mut this.flag) {
+            THUNK_EVALUATED => unsafe { Some(Pin::new_unchecked(&mut (*this.cache()).evaluated)) },
+            _ => None,
+        }
+    }
+
+
+    /// Force the thunk, unless that would mean waiting for another thread. If
+    /// the thunk is deferred, it is evaluated on this thread; if some other
+    /// thread is evaluating it, `WouldBlock` is returned immediately instead.
+    #[inline]
+    pub fn try_force(&self) -> Result<(), WouldBlock> {
+        unsafe { try_force_with(&self.flag, || (*self.cache()).evaluate_thunk()) }
+    }
+
+
+    /// Like `try_force`, but return a reference to the computed value.
+    #[inline]
+    pub fn try_deref(&self) -> Result<&T, WouldBlock> {
+        self.try_force()?;
+        Ok(unsafe { self.get_unchecked() })
+    }
+
+
+    /// Force the thunk, returning `Poisoned` instead of panicking if its
+    /// computation panicked on some earlier force. A panic in the computation
+    /// on this thread still unwinds, but poisons the thunk on the way out.
+    #[inline]
+    pub fn force_checked(&self) -> Result<&T, Poisoned> {
+        unsafe {
+            force_with(&self.flag, || (*self.cache()).evaluate_thunk())?;
+            Ok(self.get_unchecked())
+        }
+    }
+
+
+    /// Force the thunk, waiting at most `timeout` for another thread which is
+    /// already evaluating it. If the thunk is deferred, it is evaluated on
+    /// this thread, and that evaluation is not bounded by `timeout`.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn force_timeout(&self, timeout: Duration) -> Result<&T, Timeout> {
+        unsafe {
+            force_until(&self.flag, || (*self.cache()).evaluate_thunk(), Instant::now() + timeout)?;
+            Ok(self.get_unchecked())
+        }
+    }
+
+
+    /// Block until some other thread has forced the thunk, without ever
+    /// running the deferred computation on this thread.
+    #[inline]
+    pub fn wait(&self) -> &T {
+        unsafe {
+            if wait_until(&self.flag, None).is_err() {
+                unreachable();
+            }
+
+            self.get_unchecked()
+        }
+    }
+
+
+    /// Like `wait`, but give up once `timeout` has passed.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<&T, Timeout> {
+        unsafe {
+            wait_until(&self.flag, Some(Instant::now() + timeout))?;
+            Ok(self.get_unchecked())
+        }
+    }
+
+
+    /// Take a snapshot of the current state of the thunk, without forcing it.
+    #[inline]
+    pub fn state(&self) -> ThunkState {
+        match self.flag.load(Ordering::Acquire) & STATE_MASK {
+            THUNK_DEFERRED => ThunkState::Deferred,
+            THUNK_EVALUATED => ThunkState::Evaluated,
+            THUNK_RUNNING => ThunkState::Evaluating,
+            THUNK_POISONED => ThunkState::Poisoned,
+            THUNK_INVALIDATED => ThunkState::Empty,
+            _ => unsafe { unreachable() },
+        }
+    }
+
+
+    /// Get a reference to the computed value without checking whether the
+    /// thunk has been evaluated. This skips the atomic state machine entirely.
+    ///
+    /// # Safety
+    ///
+    /// The thunk must already be evaluated, and the evaluation must be visible
+    /// to the calling thread - for instance, because the calling thread forced
+    /// the thunk itself, or observed `is_evaluated` returning `true`. Calling
+    /// this otherwise is undefined behavior.
+    #[inline]
+    pub unsafe fn get_unchecked(&self) -> &T {
+        &(*self.cache()).evaluated
+    }
+
+
+    /// Unwrap the computed value without checking whether the thunk has been
+    /// evaluated.
+    ///
+    /// # Safety
+    ///
+    /// The thunk must already be evaluated, and the evaluation must be visible
+    /// to the calling thread. Calling this otherwise is undefined behavior.
+    #[inline]
+    pub unsafe fn assume_evaluated(mut self) -> T {
+        ManuallyDrop::into_inner(self.take_data().evaluated)
+    }
+
+
+    /// Overwrite the thunk with an already computed value. If the thunk was
+    /// still deferred, its closure is dropped without ever being run.
+    #[inline]
+    pub fn set(&mut self, value: T) {
+        *self = AtomicThunk::computed(value);
+    }
+
+
+    /// Overwrite the thunk with an already computed value, returning the old
+    /// value if the thunk had already been evaluated. If the thunk was still
+    /// deferred, its closure is dropped without ever being run and `None` is
+    /// returned.
+    #[inline]
+    pub fn replace(&mut self, value: T) -> Option<T> {
+        mem::replace(self, AtomicThunk::computed(value)).try_into_inner().ok()
+    }
+
+
+    /// Lazily map a function over the thunk's value, producing a new thunk.
+    /// Neither the original computation nor `f` are run until the new thunk is
+    /// forced.
+    pub fn map<'a, U, F: FnOnce(T) -> U + Send + 'a>(self, f: F) -> AtomicThunk<U>
+        where T: Send + 'a, U: 'a
+    {
+        match self.into_deferred() {
+            Ok(thunk) => AtomicThunk::defer(move || f(thunk())),
+            Err(value) => AtomicThunk::defer(move || f(value)),
+        }
+    }
+
+
+    /// Lazily chain a computation producing another thunk onto this one. The
+    /// resulting thunk runs the original computation, `f`, and the thunk
+    /// returned by `f` only when it is forced.
+    pub fn and_then<'a, U, F: FnOnce(T) -> AtomicThunk<U> + Send + 'a>(self, f: F) -> AtomicThunk<U>
+        where T: Send + 'a, U: Send + 'a
+    {
+        self.map(f).flatten()
+    }
+
+
+    /// Lazily combine two thunks into a thunk of a pair. Neither thunk is
+    /// forced until the resulting thunk is.
+    #[inline]
+    pub fn zip<'a, U>(self, other: AtomicThunk<U>) -> AtomicThunk<(T, U)>
+        where T: Send + 'a, U: Send + 'a
+    {
+        self.zip_with(other, |t, u| (t, u))
+    }
+
+
+    /// Lazily combine two thunks with a function. Neither thunk is forced, and
+    /// `f` is not run, until the resulting thunk is.
+    pub fn zip_with<'a, U, V, F>(self, other: AtomicThunk<U>, f: F) -> AtomicThunk<V>
+        where T: Send + 'a, U: Send + 'a, V: 'a, F: FnOnce(T, U) -> V + Send + 'a
+    {
+        AtomicThunk::defer(move || f(self.unwrap(), other.unwrap()))
+    }
+
+
+    /// Disassemble the thunk without forcing it. If the thunk is still
+    /// deferred, the boxed closure is returned so that it can be run elsewhere
+    /// or moved into another thunk; otherwise, the computed value is returned
+    /// in the `Err` variant.
+    pub fn into_deferred(mut self) -> Result<Box<dyn FnOnce() -> T + Send>, T> {
+        self.take_deferred()
+    }
+
+
+    /// Lazily apply a transformation to the thunk's value. If the thunk is
+    /// still deferred, `f` is composed with the deferred closure and will only
+    /// run if the thunk is ever forced; otherwise, `f` is applied immediately.
+    pub fn modify<'a, F: FnOnce(T) -> T + Send + 'a>(&mut self, f: F)
+        where T: 'a
+    {
+        match self.take_deferred() {
+            Ok(thunk) => *self = AtomicThunk::defer(move || f(thunk())),
+            Err(value) => *self = AtomicThunk::computed(f(value)),
+        }
+    }
+
+
+    /// Move the closure or the computed value out of the thunk, leaving it
+    /// invalidated. The thunk must be refilled or forgotten before it is used
+    /// again; dropping it is fine.
+    fn take_deferred(&mut self) -> Result<Box<dyn FnOnce() -> T + Send>, T> {
+        match settle(&mut self.flag) {
+            THUNK_DEFERRED => {
+                // Every closure stored in an `AtomicThunk` is `Send`.
+                let thunk = unsafe { AssertSend::new(ManuallyDrop::into_inner(self.take_data().deferred)) };
+                let f: Box<dyn FnOnce() -> T + Send + '_> = Box::new(move || thunk.into_inner().call());
+
+                // The closure lives as long as `T` does, which is all that
+                // `AtomicThunk` ever promised about it.
+                Ok(unsafe { mem::transmute::<Box<dyn FnOnce() -> T + Send + '_>, Box<dyn FnOnce() -> T + Send>>(f) })
+            }
+            THUNK_EVALUATED => Err(unsafe { ManuallyDrop::into_inner(self.take_data().evaluated) }),
+            THUNK_POISONED => poisoned(),
+            THUNK_INVALIDATED => panic!("attempted to take from an invalidated `AtomicThunk`"),
+            _ => unsafe { unreachable() },
+        }
+    }
+}
+
+
+impl<T> AtomicThunk<AtomicThunk<T>> {
+    /// Lazily flatten a nested thunk. Forcing the resulting thunk forces both
+    /// the outer and the inner thunk.
+    #[inline]
+    pub fn flatten(self) -> AtomicThunk<T>
+        where T: Send
+    {
+        self.map(Lazy::unwrap)
+    }
+}
+
+
+impl<T> LazyRef for AtomicThunk<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        unsafe {
+            if force_with(&self.flag, || (*self.cache()).evaluate_thunk()).is_err() {
+                poisoned();
+            }
+        }
+
+        unsafe { &(*self.cache()).evaluated }
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.flag.load(Ordering::Acquire) == THUNK_EVALUATED
+    }
+}
+
+
+impl<T> LazySend for AtomicThunk<T> {
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> AtomicThunk<T>
+        where T: 'a
+    {
+        AtomicThunk::defer(f)
+    }
+}
+
+
+impl<T> LazyMut for AtomicThunk<T> {
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        // Since we have a unique reference to the thunk, no other thread can be
+        // evaluating it, and we can skip all synchronization.
+        match settle(&mut self.flag) {
+            THUNK_DEFERRED => {
+                // The thunk stays poisoned if the computation panics.
+                set_unique(&mut self.flag, THUNK_POISONED);
+
+                unsafe {
+                    (*self.cache()).evaluate_thunk();
+                }
+
+                set_unique(&mut self.flag, THUNK_EVALUATED);
+            }
+            THUNK_EVALUATED => {}
+            THUNK_POISONED => poisoned(),
+            THUNK_INVALIDATED => panic!("attempted to force an invalidated `AtomicThunk`"),
+            _ => unsafe { unreachable() },
+        }
+
+        unsafe { &mut (*self.cache()).evaluated }
+    }
+}
+
+
+impl<T> Lazy for AtomicThunk<T> {
+    #[inline]
+    fn unwrap(mut self) -> T {
+        self.force();
+
+        unsafe { ManuallyDrop::into_inner(self.take_data().evaluated) }
+    }
+}
+
+
+/// A thread-safe thunk whose computation may be supplied when it is first
+/// accessed, rather than when it is constructed, in the style of a `OnceCell`.
+/// An `AtomicLateThunk` may also be deferred like any other thunk, in which
+/// case it behaves exactly like an `AtomicThunk`.
+pub struct AtomicLateThunk<T>(AtomicThunk<T>);
+
+
+impl<T> AtomicLateThunk<T> {
+    /// Construct an uninitialized `AtomicLateThunk`. Forcing or dereferencing
+    /// it before it is initialized will panic.
+    #[inline]
+    pub fn new() -> AtomicLateThunk<T> {
+        AtomicLateThunk(AtomicThunk::invalidated())
+    }
+
+
+    /// Get a reference to the value, initializing the thunk with `f` if it has
+    /// neither a value nor a deferred computation. If several threads race to
+    /// initialize the thunk, exactly one closure is run, and the others wait
+    /// for its result. If that closure panics, the thunk is left uninitialized
+    /// and one of the waiting threads runs its own closure instead.
+    #[inline]
+    pub fn get_or_init<F: FnOnce() -> T>(&self, mut f: F) -> &T {
+        loop {
+            f = match self.0.try_fill(f) {
+                Ok(()) => break,
+                Err(f) => f,
+            };
+
+            // Some other thread got there first. Wait for its initializer if
+            // it's still running, and try again if it panicked.
+            let flag = &self.0.flag;
+            let state = flag.load(Ordering::Acquire);
+
+            match state & STATE_MASK {
+                THUNK_INVALIDATED => continue,
+                THUNK_RUNNING => match transaction(|| unsafe { besiege(flag, state, None) }) {
+                    Ok(THUNK_INVALIDATED) => continue,
+                    _ => break,
+                },
+                _ => break,
+            }
+        }
+
+        self.0.force()
+    }
+
+
+    /// Initialize the thunk with a value. If the thunk already has a value or
+    /// a deferred computation, the value is handed back in the `Err` variant.
+    #[inline]
+    pub fn set(&self, value: T) -> Result<(), T> {
+        self.0.try_fill(move || value).map_err(|f| f())
+    }
+
+
+    /// Get a reference to the value if the thunk has already been evaluated.
+    /// This never forces the thunk or blocks.
+    #[inline]
+    pub fn get(&self) -> Option<&T> {
+        self.0.try_get()
+    }
+
+
+    /// Check whether the thunk has been initialized, either with a value or a
+    /// deferred computation.
+    #[inline]
+    pub fn is_initialized(&self) -> bool {
+        self.0.flag.load(Ordering::Acquire) != THUNK_INVALIDATED
+    }
+}
+
+
+impl<T> Default for AtomicLateThunk<T> {
+    #[inline]
+    fn default() -> AtomicLateThunk<T> {
+        AtomicLateThunk::new()
+    }
+}
+
+
+impl<T> Deref for AtomicLateThunk<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T> DerefMut for AtomicLateThunk<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+impl<T> From<T> for AtomicLateThunk<T> {
+    #[inline]
+    fn from(t: T) -> AtomicLateThunk<T> {
+        AtomicLateThunk(AtomicThunk::computed(t))
+    }
+}
+
+
+impl<T> LazyRef for AtomicLateThunk<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        assert!(self.is_initialized(), "attempted to force an uninitialized `AtomicLateThunk`");
+        self.0.force()
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<T> LazySend for AtomicLateThunk<T> {
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> AtomicLateThunk<T>
+        where T: 'a
+    {
+        AtomicLateThunk(AtomicThunk::defer(f))
+    }
+}
+
+
+impl<T> LazyMut for AtomicLateThunk<T> {
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        assert!(self.is_initialized(), "attempted to force an uninitialized `AtomicLateThunk`");
+        self.0.force_mut()
+    }
+}
+
+
+impl<T> Lazy for AtomicLateThunk<T> {
+    #[inline]
+    fn unwrap(self) -> T {
+        assert!(self.is_initialized(), "attempted to unwrap an uninitialized `AtomicLateThunk`");
+        self.0.unwrap()
+    }
+}
+
+
+/// An `AtomicThunk` aligned to its own cache line, so that threads forcing
+/// neighbouring thunks in an array do not contend through false sharing. The
+/// alignment is 128 bytes, which covers the adjacent-line prefetching of
+/// modern x86-64 and the larger lines of some ARM cores.
+#[repr(align(128))]
+pub struct CachePaddedThunk<T>(AtomicThunk<T>);
+
+
+impl<T> CachePaddedThunk<T> {
+    /// Defer a computation stored as a `Send` closure; see
+    /// `AtomicThunk::defer`.
+    #[inline]
+    pub fn defer<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> CachePaddedThunk<T>
+        where T: 'a
+    {
+        CachePaddedThunk(AtomicThunk::defer(f))
+    }
+
+
+    /// Get a reference to the computed value if the thunk has already been
+    /// evaluated. This never forces the thunk or blocks.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.0.try_get()
+    }
+
+
+    /// Unwrap the padding, leaving the thunk itself.
+    #[inline]
+    pub fn into_inner(self) -> AtomicThunk<T> {
+        self.0
+    }
+}
+
+
+impl<T> AsRef<AtomicThunk<T>> for CachePaddedThunk<T> {
+    #[inline]
+    fn as_ref(&self) -> &AtomicThunk<T> {
+        &self.0
+    }
+}
+
+
+impl<T> Deref for CachePaddedThunk<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T> DerefMut for CachePaddedThunk<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+impl<T> From<T> for CachePaddedThunk<T> {
+    #[inline]
+    fn from(t: T) -> CachePaddedThunk<T> {
+        CachePaddedThunk(AtomicThunk::computed(t))
+    }
+}
+
+
+impl<T> From<AtomicThunk<T>> for CachePaddedThunk<T> {
+    #[inline]
+    fn from(thunk: AtomicThunk<T>) -> CachePaddedThunk<T> {
+        CachePaddedThunk(thunk)
+    }
+}
+
+
+impl<T> LazyRef for CachePaddedThunk<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        self.0.force()
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<T> LazySend for CachePaddedThunk<T> {
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> CachePaddedThunk<T>
+        where T: 'a
+    {
+        CachePaddedThunk(AtomicThunk::defer(f))
+    }
+}
+
+
+impl<T> LazyMut for CachePaddedThunk<T> {
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        self.0.force_mut()
+    }
+}
+
+
+impl<T> Lazy for CachePaddedThunk<T> {
+    #[inline]
+    fn unwrap(self) -> T {
+        self.0.unwrap()
+    }
+}
+
+
+/// A thread-safe thunk computed by a plain function pointer, optionally applied
+/// to a `Copy` argument. Since nothing needs to be boxed, a `FnPtrThunk` can be
+/// constructed in a `const` context and stored in a `static`:
+///
+/// ```
+/// use thunk::FnPtrThunk;
+///
+/// fn compute() -> u64 {
+///     (1..21).product()
+/// }
+///
+/// static FACTORIAL: FnPtrThunk<u64> = FnPtrThunk::new(compute);
+///
+/// assert_eq!(*FACTORIAL, 2432902008176640000);
+/// ```
+///
+/// Since the computation is a function pointer rather than an arbitrary
+/// closure, a `FnPtrThunk` cannot implement `LazyRef`, and instead provides the
+/// same operations as inherent methods.
+pub struct FnPtrThunk<T, A: Copy = ()> {
+    thunk: AtomicLateThunk<T>,
+    init: FnPtrInit<T, A>,
+}
+
+
+enum FnPtrInit<T, A> {
+    Nullary(fn() -> T),
+    Unary(fn(A) -> T, A),
+}
+
+
+impl<T> FnPtrThunk<T> {
+    const_unless_loom! {
+        /// Construct a `FnPtrThunk` which will compute its value by calling
+        /// `f`.
+        #[inline]
+        pub fn new(f: fn() -> T) -> FnPtrThunk<T> {
+            FnPtrThunk {
+                thunk: AtomicLateThunk(AtomicThunk::invalidated()),
+                init: FnPtrInit::Nullary(f),
+            }
+        }
+    }
+}
+
+
+impl<T, A: Copy> FnPtrThunk<T, A> {
+    const_unless_loom! {
+        /// Construct a `FnPtrThunk` which will compute its value by calling
+        /// `f` with `arg`.
+        #[inline]
+        pub fn with_arg(f: fn(A) -> T, arg: A) -> FnPtrThunk<T, A> {
+            FnPtrThunk {
+                thunk: AtomicLateThunk(AtomicThunk::invalidated()),
+                init: FnPtrInit::Unary(f, arg),
+            }
+        }
+    }
+
+
+    /// Manually force the thunk's computation, returning a reference to the
+    /// computed value. If another thread is already calling the function,
+    /// this waits for it, and calls the function again itself if that call
+    /// panicked.
+    #[inline]
+    pub fn force(&self) -> &T {
+        self.thunk.get_or_init(|| match self.init {
+            FnPtrInit::Nullary(f) => f(),
+            FnPtrInit::Unary(f, arg) => f(arg),
+        })
+    }
+
+
+    /// Check whether the thunk has already been evaluated, without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.thunk.is_evaluated()
+    }
+
+
+    /// Get a reference to the computed value if the thunk has already been
+    /// evaluated. This never forces the thunk or blocks.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.thunk.get()
+    }
+
+
+    /// Unwrap the thunk into its inner value. This forces the thunk.
+    #[inline]
+    pub fn unwrap(self) -> T {
+        self.force();
+        self.thunk.0.unwrap()
+    }
+}
+
+
+impl<T, A: Copy> AsRef<T> for FnPtrThunk<T, A> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T, A: Copy> Deref for FnPtrThunk<T, A> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+/// An atomically reference-counted, thread-safe thunk which implements
+/// `LazyRef`.
+///
+/// Unlike an `Arc<AtomicThunk<T>>`, an `ArcThunk` keeps the reference counts,
+/// the state flag, the cached value, and the deferred closure in a single heap
+/// block, however large the closure is. The closure is stored next to the
+/// value rather than overlapping it, so a block is as large as both together.
+///
+/// Like an `Arc<AtomicThunk<T>>`, an `ArcThunk<T>` is only `Send` and `Sync`
+/// if `T` is both `Send` and `Sync`:
+///
+/// ```compile_fail
+/// use std::cell::Cell;
+/// use thunk::ArcThunk;
+///
+/// fn assert_send<T: Send>() {}
+/// assert_send::<ArcThunk<Cell<u32>>>();
+/// ```
+#[cfg(target_has_atomic = "ptr")]
+pub struct ArcThunk<T>(ArcPtr<T>);
+
+
+/// The heap block behind an `ArcThunk`. `C` is the deferred closure, erased to
+/// a `dyn Deferred<T>` once the block is allocated.
+///
+/// As with `RcInner`, the layout is fixed so that `ArcThunk::from_raw` can find
+/// the start of the block from a pointer to the value.
+#[cfg(target_has_atomic = "ptr")]
+#[repr(C)]
+struct ArcInner<T, C: ?Sized + Deferred<T>> {
+    /// Turns a thin pointer to the block back into a pointer which carries
+    /// the vtable of its closure.
+    unthin: unsafe fn(*const u8) -> *const ArcInner<T, dyn Deferred<T>>,
+
+    /// The number of `ArcThunk`s pointing to the block. This is kept apart
+    /// from the `Arc`'s own strong count so that a unique `ArcThunk` can claim
+    /// the value atomically, which `Arc` only allows for sized contents.
+    owners: AtomicUsize,
+
+    /// The `flag` follows the same protocol as that of an `AtomicThunk`; see
+    /// `force_with`.
+    flag: AtomicUsize,
+
+    value: UnsafeCell<MaybeUninit<T>>,
+    closure: UnsafeCell<C>,
+}
+
+
+// As for `AtomicThunk`.
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<T: Send, C: ?Sized + Deferred<T>> Send for ArcInner<T, C> {}
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<T: Send + Sync, C: ?Sized + Deferred<T>> Sync for ArcInner<T, C> {}
+
+
+#[cfg(target_has_atomic = "ptr")]
+type ArcPtr<T> = Arc<ArcInner<T, dyn Deferred<T>>>;
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T, C: ?Sized + Deferred<T>> Drop for ArcInner<T, C> {
+    fn drop(&mut self) {
+        match settle(&mut self.flag) {
+            THUNK_DEFERRED => unsafe { self.closure.get_mut().drop_in_place() },
+            THUNK_EVALUATED => unsafe { ptr::drop_in_place(self.value.get_mut().as_mut_ptr()) },
+            THUNK_POISONED | THUNK_INVALIDATED => {}
+            _ => unsafe { unreachable() },
+        }
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T, C: Deferred<T>> ArcInner<T, C> {
+    #[inline]
+    fn new(flag: usize, value: MaybeUninit<T>, closure: C) -> ArcInner<T, C> {
+        ArcInner {
+            unthin: ArcInner::<T, C>::unthin,
+            owners: AtomicUsize::new(1),
+            flag: AtomicUsize::new(flag),
+            value: UnsafeCell::new(value),
+            closure: UnsafeCell::new(closure),
+        }
+    }
+
+
+    /// PRECONDITION: `ptr` must point to the start of an `ArcInner<T, C>`.
+    unsafe fn unthin(ptr: *const u8) -> *const ArcInner<T, dyn Deferred<T>> {
+        let ptr: *const ArcInner<T, dyn Deferred<T> + '_> = ptr as *const ArcInner<T, C>;
+
+        // As in `ArcInner::erase`.
+        mem::transmute::<*const ArcInner<T, dyn Deferred<T> + '_>, *const ArcInner<T, dyn Deferred<T>>>(ptr)
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> ArcInner<T, dyn Deferred<T>> {
+    #[inline]
+    fn deferred<'a, F: FnOnce() -> T + 'a>(f: F) -> ArcPtr<T>
+        where T: 'a
+    {
+        ArcInner::erase(Arc::new(ArcInner::new(THUNK_DEFERRED, MaybeUninit::uninit(), ManuallyDrop::new(f))))
+    }
+
+
+    #[inline]
+    fn computed(t: T) -> ArcPtr<T> {
+        ArcInner::erase(Arc::new(ArcInner::new(THUNK_EVALUATED, MaybeUninit::new(t), ManuallyDrop::new(never_called::<T>))))
+    }
+
+
+    /// Erase the lifetime of the closure, as `AtomicThunk` does; the closure
+    /// must live as long as the type which the thunk computes.
+    #[inline]
+    fn erase<'a>(arc: Arc<ArcInner<T, dyn Deferred<T> + 'a>>) -> ArcPtr<T> {
+        unsafe { mem::transmute::<Arc<ArcInner<T, dyn Deferred<T> + 'a>>, ArcPtr<T>>(arc) }
+    }
+
+
+    /// PRECONDITION: `value` must point to the value of an `ArcInner<T, _>`.
+    ///
+    /// Find the block which `value` belongs to.
+    #[inline]
+    unsafe fn from_value(value: *const T) -> *const ArcInner<T, dyn Deferred<T>> {
+        // As in `RcInner::from_value`.
+        let start = (value as *const u8).sub(mem::offset_of!(ArcInner<T, ManuallyDrop<fn() -> T>>, value));
+        ((*(start as *const ArcInner<T, ManuallyDrop<fn() -> T>>)).unthin)(start)
+    }
+
+
+    /// PRECONDITION: this thread must have just moved the flag to
+    /// THUNK_RUNNING.
+    #[inline]
+    unsafe fn evaluate(&self) {
+        let value = (*self.closure.get()).call_in_place();
+        (*self.value.get()).as_mut_ptr().write(value);
+    }
+
+
+    #[inline]
+    fn force(&self) -> &T {
+        match self.force_checked() {
+            Ok(value) => value,
+            Err(_) => poisoned(),
+        }
+    }
+
+
+    #[inline]
+    fn force_checked(&self) -> Result<&T, Poisoned> {
+        unsafe {
+            force_with(&self.flag, || self.evaluate())?;
+            Ok(&*(*self.value.get()).as_ptr())
+        }
+    }
+
+
+    #[inline]
+    fn wait_until(&self, deadline: Option<Deadline>) -> Result<&T, Timeout> {
+        unsafe {
+            wait_until(&self.flag, deadline)?;
+            Ok(&*(*self.value.get()).as_ptr())
+        }
+    }
+
+
+    #[cfg(feature = "std")]
+    #[inline]
+    fn force_until(&self, deadline: Instant) -> Result<&T, Timeout> {
+        unsafe {
+            force_until(&self.flag, || self.evaluate(), deadline)?;
+            Ok(&*(*self.value.get()).as_ptr())
+        }
+    }
+
+
+    #[inline]
+    fn try_force(&self) -> Result<&T, WouldBlock> {
+        unsafe {
+            try_force_with(&self.flag, || self.evaluate())?;
+            Ok(&*(*self.value.get()).as_ptr())
+        }
+    }
+
+
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        self.force();
+        unsafe { &mut *self.value.get_mut().as_mut_ptr() }
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.flag.load(Ordering::Acquire) == THUNK_EVALUATED
+    }
+
+
+    /// PRECONDITION: no `ArcThunk` may point to the block any more, and the
+    /// value must not be taken twice.
+    ///
+    /// Force the thunk, and move its value out.
+    #[inline]
+    unsafe fn take_value(&self) -> T {
+        self.force();
+        self.flag.store(THUNK_INVALIDATED, Ordering::Relaxed);
+        ptr::read((*self.value.get()).as_ptr())
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> Drop for ArcThunk<T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.owners.fetch_sub(1, Ordering::Release);
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> ArcThunk<T> {
+    /// Defer a computation stored as a `FnOnce` closure. Since any thread may
+    /// end up forcing the thunk, the closure must be `Send`.
+    #[inline]
+    pub fn defer<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> ArcThunk<T>
+        where T: 'a
+    {
+        ArcThunk(ArcInner::deferred(f))
+    }
+
+
+    /// Defer a computation whose closure need not be `Send`.
+    ///
+    /// # Safety
+    ///
+    /// As for `AtomicThunk::defer_local`, the closure must only be run or
+    /// dropped on the thread which created it: until the thunk has been
+    /// forced, it must not be forced, nor its last handle dropped, on any
+    /// other thread.
+    #[inline]
+    pub unsafe fn defer_local<'a, F: FnOnce() -> T + 'a>(f: F) -> ArcThunk<T>
+        where T: 'a
+    {
+        let f = AssertSend::new(f);
+        ArcThunk::defer(move || f.into_inner()())
+    }
+
+
+    /// If the `ArcThunk` is unevaluated, this will force it. If the `ArcThunk` is
+    /// the sole, unique owner of the underlying thunk, this will return the forced
+    /// value; otherwise, it will return an `Err` containing the original `ArcThunk`.
+    pub fn try_unwrap(this: ArcThunk<T>) -> Result<T, ArcThunk<T>> {
+        match this.0.owners.compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Ok(unsafe { ArcThunk::into_arc(this).take_value() }),
+            Err(_) => Err(this),
+        }
+    }
+
+
+    /// If the `ArcThunk` is the sole, unique owner of the underlying thunk,
+    /// this will return that thunk without forcing it; otherwise, it will
+    /// return an `Err` containing the original `ArcThunk`.
+    pub fn try_unwrap_lazy(this: ArcThunk<T>) -> Result<AtomicThunk<T>, ArcThunk<T>> {
+        if this.0.owners.compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return Err(this);
+        }
+
+        let arc = ArcThunk::into_arc(this);
+
+        if arc.is_evaluated() {
+            Ok(AtomicThunk::computed(unsafe { arc.take_value() }))
+        } else {
+            // This is the last handle, so only the value will cross threads.
+            let arc = unsafe { AssertSend::new(arc) };
+            Ok(AtomicThunk::defer(move || unsafe { arc.into_inner().take_value() }))
+        }
+    }
+
+
+    /// Consume the `ArcThunk`, returning the forced value if it was the last
+    /// strong handle to the underlying thunk, and `None` otherwise. This
+    /// mirrors `Arc::into_inner`: if several threads drop their handles this
+    /// way at once, exactly one of them gets the value.
+    pub fn into_inner(this: ArcThunk<T>) -> Option<T> {
+        let arc = ArcThunk::into_arc(this);
+
+        if arc.owners.fetch_sub(1, Ordering::AcqRel) == 1 {
+            Some(unsafe { arc.take_value() })
+        } else {
+            None
+        }
+    }
+
+
+    /// Consume the `ArcThunk`, forcing it if it is unevaluated. If it is the
+    /// sole owner of the underlying thunk, the value is moved out; otherwise,
+    /// it is cloned. This mirrors `Arc::unwrap_or_clone`.
+    #[inline]
+    pub fn unwrap_or_clone(this: ArcThunk<T>) -> T
+        where T: Clone
+    {
+        ArcThunk::try_unwrap(this).unwrap_or_else(|arc_thunk| (*arc_thunk).clone())
+    }
+
+
+    /// If the `ArcThunk` is unevaluated, this will force it. If the `ArcThunk` is
+    /// the sole, unique owner of the underlying thunk, this will return a
+    /// mutable reference to the forced value; otherwise, it will return `None`.
+    pub fn get_mut(this: &mut ArcThunk<T>) -> Option<&mut T> {
+        Arc::get_mut(&mut this.0).map(ArcInner::force_mut)
+    }
+
+
+    /// Lazily map a function over the shared value, producing a new `ArcThunk`
+    /// which holds onto this one. Neither the original computation nor `f` are
+    /// run until the new thunk is forced.
+    pub fn map<'a, U, F: FnOnce(&T) -> U + Send + 'a>(this: &ArcThunk<T>, f: F) -> ArcThunk<U>
+        where T: Send + Sync + 'a, U: 'a
+    {
+        let this = this.clone();
+        ArcThunk::defer(move || f(&this))
+    }
+
+
+    /// Lazily project a borrowed part of the shared value, producing an
+    /// `ArcThunkRef` which keeps the underlying thunk alive. Neither the
+    /// original computation nor `f` are run until the projection is forced,
+    /// and the underlying thunk is forced only once across all threads.
+    pub fn project<U: ?Sized, F: FnOnce(&T) -> &U + Send + 'static>(this: &ArcThunk<T>, f: F) -> ArcThunkRef<U>
+        where T: Send + Sync + 'static
+    {
+        let parent = this.clone();
+        ArcThunkRef {
+            target: ArcThunk::defer(move || NonNull::from(f(&parent))),
+            owner: Arc::new(this.clone()),
+        }
+    }
+
+
+    /// If the `ArcThunk` is unevaluated, this will force it. If the `ArcThunk`
+    /// is the sole, unique owner of the underlying thunk, this will return a
+    /// mutable reference to the forced value; if it is not, then it will clone
+    /// the forced value and return a mutable reference to the newly cloned
+    /// value. The `&mut ArcThunk` passed in will be updated to reference the
+    /// newly cloned value.
+    pub fn make_mut(this: &mut ArcThunk<T>) -> &mut T
+        where T: Clone
+    {
+        if Arc::get_mut(&mut this.0).is_none() {
+            let value = this.0.force().clone();
+            *this = ArcThunk::computed(value);
+        }
+
+        ArcThunk::get_mut(this).unwrap()
+    }
+
+
+    /// Spawn a thread which forces the thunk in the background, so that its
+    /// evaluation starts early. Dereferencing any handle to the thunk blocks
+    /// until the value is ready; the returned `JoinHandle` may be joined to
+    /// wait for it, or to observe a panic from the deferred computation.
+    #[cfg(feature = "std")]
+    pub fn spawn_force(this: &ArcThunk<T>) -> JoinHandle<()>
+        where T: Send + Sync + 'static
+    {
+        let this = this.clone();
+        thread::spawn(move || {
+            this.force();
+        })
+    }
+
+
+    /// Create a weak handle to the underlying thunk, which does not keep it
+    /// alive. This never forces the thunk.
+    #[inline]
+    pub fn downgrade(this: &ArcThunk<T>) -> WeakArcThunk<T> {
+        WeakArcThunk(Arc::downgrade(&this.0))
+    }
+
+
+    /// Check whether two handles point to the same underlying thunk, without
+    /// forcing either. Like `Arc::ptr_eq`, this is an associated function, so
+    /// that it does not shadow methods of the value.
+    #[inline]
+    pub fn ptr_eq(this: &ArcThunk<T>, other: &ArcThunk<T>) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+
+
+    /// The address of the underlying thunk, which identifies it for as long
+    /// as any handle to it is alive.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn addr(this: &ArcThunk<T>) -> usize {
+        Arc::as_ptr(&this.0) as *const u8 as usize
+    }
+
+
+    /// The number of strong handles to the underlying thunk. Other threads may
+    /// change the count at any time, so this is only a snapshot.
+    #[inline]
+    pub fn strong_count(this: &ArcThunk<T>) -> usize {
+        this.0.owners.load(Ordering::Acquire)
+    }
+
+
+    /// The number of weak handles to the underlying thunk.
+    #[inline]
+    pub fn weak_count(this: &ArcThunk<T>) -> usize {
+        Arc::weak_count(&this.0)
+    }
+
+
+    /// Check whether this is the only handle, strong or weak, to the
+    /// underlying thunk. If it is, `get_mut` will succeed and `make_mut` will
+    /// not clone the value.
+    #[inline]
+    pub fn is_unique(this: &mut ArcThunk<T>) -> bool {
+        Arc::get_mut(&mut this.0).is_some()
+    }
+
+
+    /// Consume the handle, returning a pointer to the slot of the value in
+    /// the underlying thunk. This never forces the thunk, so the slot only
+    /// holds a value if the thunk has been evaluated. As with `Arc::into_raw`,
+    /// the strong count is left untouched, so the thunk leaks unless the
+    /// pointer is passed back to `from_raw`.
+    #[inline]
+    pub fn into_raw(this: ArcThunk<T>) -> *const T {
+        let this = ManuallyDrop::new(this);
+        let inner = Arc::into_raw(unsafe { ptr::read(&this.0) });
+        unsafe { ptr::addr_of!((*inner).value) as *const T }
+    }
+
+
+    /// Reconstruct a handle from a pointer returned by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `ArcThunk::<T>::into_raw`, and each
+    /// pointer may be converted back only once, since it owns the strong
+    /// count the original handle held.
+    #[inline]
+    pub unsafe fn from_raw(ptr: *const T) -> ArcThunk<T> {
+        ArcThunk(Arc::from_raw(ArcInner::from_value(ptr)))
+    }
+
+
+    /// Block until some other thread has forced the underlying thunk, without
+    /// ever running the deferred computation on this thread.
+    #[inline]
+    pub fn wait(this: &ArcThunk<T>) -> &T {
+        match this.0.wait_until(None) {
+            Ok(value) => value,
+            Err(_) => unsafe { unreachable() },
+        }
+    }
+
+
+    /// Like `wait`, but give up once `timeout` has passed.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn wait_timeout(this: &ArcThunk<T>, timeout: Duration) -> Result<&T, Timeout> {
+        this.0.wait_until(Some(Instant::now() + timeout))
+    }
+
+
+    /// Force the underlying thunk, waiting at most `timeout` for another
+    /// thread which is already evaluating it; see `AtomicThunk::force_timeout`.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn force_timeout(this: &ArcThunk<T>, timeout: Duration) -> Result<&T, Timeout> {
+        this.0.force_until(Instant::now() + timeout)
+    }
+
+
+    /// Force the underlying thunk, unless that would mean waiting for another
+    /// thread; see `AtomicThunk::try_force`.
+    #[inline]
+    pub fn try_force(this: &ArcThunk<T>) -> Result<(), WouldBlock> {
+        this.0.try_force().map(|_| ())
+    }
+
+
+    /// Like `try_force`, but return a reference to the computed value.
+    #[inline]
+    pub fn try_deref(this: &ArcThunk<T>) -> Result<&T, WouldBlock> {
+        this.0.try_force()
+    }
+
+
+    /// Force the underlying thunk, returning `Poisoned` instead of panicking if
+    /// its computation panicked; see `AtomicThunk::force_checked`.
+    #[inline]
+    pub fn force_checked(this: &ArcThunk<T>) -> Result<&T, Poisoned> {
+        this.0.force_checked()
+    }
+
+
+    /// Take a snapshot of the current state of the underlying thunk, without
+    /// forcing it. Like `ptr_eq`, this is an associated function, so that it
+    /// does not shadow methods of the value.
+    #[inline]
+    pub fn state(this: &ArcThunk<T>) -> ThunkState {
+        match this.0.flag.load(Ordering::Acquire) & STATE_MASK {
+            THUNK_DEFERRED => ThunkState::Deferred,
+            THUNK_EVALUATED => ThunkState::Evaluated,
+            THUNK_RUNNING => ThunkState::Evaluating,
+            THUNK_POISONED => ThunkState::Poisoned,
+            THUNK_INVALIDATED => ThunkState::Empty,
+            _ => unsafe { unreachable() },
+        }
+    }
+
+
+    /// Give up this handle's ownership of the block without decrementing the
+    /// owner count.
+    #[inline]
+    fn into_arc(this: ArcThunk<T>) -> ArcPtr<T> {
+        let this = ManuallyDrop::new(this);
+        unsafe { ptr::read(&this.0) }
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> Clone for ArcThunk<T> {
+    fn clone(&self) -> Self {
+        self.0.owners.fetch_add(1, Ordering::Relaxed);
+        ArcThunk(self.0.clone())
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> AsRef<T> for ArcThunk<T> {
+    fn as_ref(&self) -> &T {
+        self.0.force()
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> Deref for ArcThunk<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.0.force()
+    }
+}
+
+
+// The value lives in the shared allocation and is never moved out while any
+// handle remains, so its address is stable across moves and clones.
+#[cfg(target_has_atomic = "ptr")]
+#[cfg(feature = "stable_deref")]
+unsafe impl<T> StableDeref for ArcThunk<T> {}
+
+
+#[cfg(target_has_atomic = "ptr")]
+#[cfg(feature = "stable_deref")]
+unsafe impl<T> CloneStableDeref for ArcThunk<T> {}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> From<T> for ArcThunk<T> {
+    fn from(t: T) -> ArcThunk<T> {
+        ArcThunk(ArcInner::computed(t))
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> LazyRef for ArcThunk<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        self.0.force()
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> LazySend for ArcThunk<T> {
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> ArcThunk<T>
+        where T: 'a
+    {
+        ArcThunk::defer(f)
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> LazyShared for ArcThunk<T> {
+    #[inline]
+    fn try_unwrap(this: ArcThunk<T>) -> Result<T, ArcThunk<T>> {
+        ArcThunk::try_unwrap(this)
+    }
+
+
+    #[inline]
+    fn get_mut(this: &mut ArcThunk<T>) -> Option<&mut T> {
+        ArcThunk::get_mut(this)
+    }
+
+
+    #[inline]
+    fn make_mut(this: &mut ArcThunk<T>) -> &mut T
+        where T: Clone
+    {
+        ArcThunk::make_mut(this)
+    }
+
+
+    #[inline]
+    fn ptr_eq(this: &ArcThunk<T>, other: &ArcThunk<T>) -> bool {
+        ArcThunk::ptr_eq(this, other)
+    }
+}
+
+
+/// A weak handle to the thunk behind an `ArcThunk`, which does not keep the
+/// thunk or its value alive. This is the `ArcThunk` counterpart of `Weak`.
+#[cfg(target_has_atomic = "ptr")]
+pub struct WeakArcThunk<T>(Weak<ArcInner<T, dyn Deferred<T>>>);
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> WeakArcThunk<T> {
+    /// Create a weak handle which points to nothing; upgrading it always
+    /// fails.
+    #[inline]
+    pub fn new() -> WeakArcThunk<T> {
+        let weak: Weak<ArcInner<T, dyn Deferred<T> + '_>> =
+            Weak::<ArcInner<T, ManuallyDrop<fn() -> T>>>::new();
+
+        // As in `ArcInner::erase`; the weak handle never points to a closure.
+        WeakArcThunk(unsafe { mem::transmute::<Weak<ArcInner<T, dyn Deferred<T> + '_>>, Weak<ArcInner<T, dyn Deferred<T>>>>(weak) })
+    }
+
+
+    /// Attempt to get a strong handle to the thunk, returning `None` if it has
+    /// already been dropped. This never forces the thunk.
+    pub fn upgrade(&self) -> Option<ArcThunk<T>> {
+        let arc = self.0.upgrade()?;
+        let mut owners = arc.owners.load(Ordering::Relaxed);
+
+        // Like `Weak::upgrade`, only take ownership if some `ArcThunk` still
+        // owns the block; once the count reaches zero, the value belongs to
+        // whoever brought it there.
+        loop {
+            if owners == 0 {
+                return None;
+            }
+
+            match arc.owners.compare_exchange_weak(owners,
+                                                   owners + 1,
+                                                   Ordering::Acquire,
+                                                   Ordering::Relaxed) {
+                Ok(_) => return Some(ArcThunk(arc)),
+                Err(current) => owners = current,
+            }
+        }
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> Clone for WeakArcThunk<T> {
+    #[inline]
+    fn clone(&self) -> WeakArcThunk<T> {
+        WeakArcThunk(self.0.clone())
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> Default for WeakArcThunk<T> {
+    #[inline]
+    fn default() -> WeakArcThunk<T> {
+        WeakArcThunk::new()
+    }
+}
+
+
+/// A lazily computed reference into the value of an `ArcThunk`, produced by
+/// `ArcThunk::project`. Forcing it forces the underlying thunk; clones share
+/// both the underlying thunk and the projection, and may be sent to other
+/// threads.
+#[cfg(target_has_atomic = "ptr")]
+pub struct ArcThunkRef<U: ?Sized> {
+    /// The projected pointer, which points into the value owned by `owner`.
+    target: ArcThunk<NonNull<U>>,
+
+    /// Keeps the value `target` points into alive.
+    owner: Arc<dyn Any + Send + Sync>,
+}
+
+
+// The owner is `Send + Sync`, and the projecting closure is `Send`; the only
+// access to the projected value is through `&U`.
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<U: ?Sized + Sync> Send for ArcThunkRef<U> {}
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<U: ?Sized + Sync> Sync for ArcThunkRef<U> {}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<U: ?Sized> ArcThunkRef<U> {
+    /// Manually force the projection, returning a reference to the projected
+    /// value.
+    #[inline]
+    pub fn force(&self) -> &U {
+        unsafe { self.target.force().as_ref() }
+    }
+
+
+    /// Check whether the projection has already been computed, without
+    /// forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.target.is_evaluated()
+    }
+
+
+    /// Lazily project further into the projected value. The new projection
+    /// keeps the same underlying thunk alive.
+    pub fn project<V: ?Sized, F: FnOnce(&U) -> &V + Send + 'static>(this: &ArcThunkRef<U>, f: F) -> ArcThunkRef<V>
+        where U: Sync + 'static
+    {
+        let parent = this.clone();
+        ArcThunkRef {
+            target: ArcThunk::defer(move || NonNull::from(f(&parent))),
+            owner: this.owner.clone(),
+        }
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<U: ?Sized> Clone for ArcThunkRef<U> {
+    #[inline]
+    fn clone(&self) -> ArcThunkRef<U> {
+        ArcThunkRef {
+            target: self.target.clone(),
+            owner: self.owner.clone(),
+        }
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<U: ?Sized> AsRef<U> for ArcThunkRef<U> {
+    #[inline]
+    fn as_ref(&self) -> &U {
+        self.force()
+    }
+}
+
+
+#[cfg(target_has_atomic = "ptr")]
+impl<U: ?Sized> Deref for ArcThunkRef<U> {
+    type Target = U;
+
+    #[inline]
+    fn deref(&self) -> &U {
+        self.force()
+    }
+}
+
+
+/// A thread-safe thunk which lazily computes a borrowed reference. The deferred
+/// closure produces a `&'a T`, and the `AtomicThunkRef` dereferences to the `T`
+/// behind it; the `AtomicThunkRef` never takes ownership of the target.
+pub struct AtomicThunkRef<'a, T: ?Sized + 'a>(AtomicThunk<&'a T>);
+
+
+impl<'a, T: ?Sized + 'a> AtomicThunkRef<'a, T> {
+    /// Construct an `AtomicThunkRef` from an already known reference. Forcing
+    /// it is a no-op.
+    #[inline]
+    pub fn computed(t: &'a T) -> AtomicThunkRef<'a, T> {
+        AtomicThunkRef(AtomicThunk::computed(t))
+    }
+
+
+    /// Defer the computation of a reference.
+    #[inline]
+    pub fn defer<F: FnOnce() -> &'a T + Send + 'a>(f: F) -> AtomicThunkRef<'a, T> {
+        AtomicThunkRef(AtomicThunk::defer(f))
+    }
+
+
+    /// Manually force the computation of the reference, returning it.
+    #[inline]
+    pub fn force(&self) -> &'a T {
+        self.0.force()
+    }
+
+
+    /// Check whether the reference has already been computed, without forcing
+    /// it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<'a, T: ?Sized + 'a> AsRef<T> for AtomicThunkRef<'a, T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<'a, T: ?Sized + 'a> Borrow<T> for AtomicThunkRef<'a, T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+
+impl<'a, T: ?Sized + 'a> Deref for AtomicThunkRef<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        *self.0
+    }
+}
+
+
+impl<'a, T: ?Sized + 'a> From<&'a T> for AtomicThunkRef<'a, T> {
+    #[inline]
+    fn from(t: &'a T) -> AtomicThunkRef<'a, T> {
+        AtomicThunkRef::computed(t)
+    }
+}
+
+
+/// A thread-safe thunk with a possibly unsized target, such as `str`,
+/// `[T]`, or a trait object. The computed value is stored boxed, and the
+/// `AtomicUnsizedThunk` dereferences to the unsized value behind the box.
+pub struct AtomicUnsizedThunk<T: ?Sized>(AtomicThunk<Box<T>>);
+
+
+impl<T: ?Sized> AtomicUnsizedThunk<T> {
+    /// Construct an `AtomicUnsizedThunk` from an already computed, boxed value.
+    #[inline]
+    pub fn computed(t: Box<T>) -> AtomicUnsizedThunk<T> {
+        AtomicUnsizedThunk(AtomicThunk::computed(t))
+    }
+
+
+    /// Defer the computation of a boxed value.
+    #[inline]
+    pub fn defer<'a, F: FnOnce() -> Box<T> + Send + 'a>(f: F) -> AtomicUnsizedThunk<T>
+        where T: 'a
+    {
+        AtomicUnsizedThunk(AtomicThunk::defer(f))
+    }
+
+
+    /// Manually force the computation, returning a reference to the value.
+    #[inline]
+    pub fn force(&self) -> &T {
+        self.0.force()
+    }
+
+
+    /// Manually force the computation, returning a mutable reference to the
+    /// value.
+    #[inline]
+    pub fn force_mut(&mut self) -> &mut T {
+        self.0.force_mut()
+    }
+
+
+    /// Check whether the value has already been computed, without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+
+
+    /// Force the thunk and take ownership of the boxed value.
+    #[inline]
+    pub fn unwrap(self) -> Box<T> {
+        self.0.unwrap()
+    }
+}
+
+
+impl<T: ?Sized> AsRef<T> for AtomicUnsizedThunk<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T: ?Sized> AsMut<T> for AtomicUnsizedThunk<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<T: ?Sized> Borrow<T> for AtomicUnsizedThunk<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T: ?Sized> BorrowMut<T> for AtomicUnsizedThunk<T> {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<T: ?Sized> Deref for AtomicUnsizedThunk<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T: ?Sized> DerefMut for AtomicUnsizedThunk<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+impl<T: ?Sized> From<Box<T>> for AtomicUnsizedThunk<T> {
+    #[inline]
+    fn from(t: Box<T>) -> AtomicUnsizedThunk<T> {
+        AtomicUnsizedThunk::computed(t)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(not(feature = "std"))]
+    use std::{string::String, vec::Vec};
+    use std::hint::black_box;
+    #[cfg(feature = "nightly")]
+    use test::Bencher;
+
+    #[test]
+    fn thunk_computed() {
+        let thunk = AtomicThunk::computed(1 + 1);
+
+        assert_eq!(*thunk, 2);
+    }
+
+    #[test]
+    fn thunk_deferred() {
+        let thunk = AtomicThunk::defer(|| black_box(1) + 1);
+
+        assert_eq!(*thunk, 2);
+    }
+
+    #[test]
+    fn thunk_is_evaluated() {
+        let thunk = ArcThunk::defer(|| black_box(1) + 1);
+
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 2);
+        assert!(thunk.is_evaluated());
+    }
+
+    #[test]
+    fn thunk_try_get() {
+        let thunk = AtomicThunk::defer(|| black_box(1) + 1);
+
+        assert_eq!(thunk.try_get(), None);
+        thunk.force();
+        assert_eq!(thunk.try_get(), Some(&2));
+    }
+
+    #[test]
+    fn thunk_state() {
+        let thunk = ArcThunk::defer(|| black_box(1) + 1);
+
+        assert_eq!(ArcThunk::state(&thunk), ThunkState::Deferred);
+        thunk.force();
+        assert_eq!(ArcThunk::state(&thunk), ThunkState::Evaluated);
+    }
+
+    #[cfg(feature = "nightly")]
+    fn ten_thousand_xors_strict(n: usize) -> AtomicThunk<usize> {
+        AtomicThunk::computed((0..black_box(10000))
+                                  .fold(black_box(n), |old, new| old ^ new))
+    }
+
+    #[cfg(feature = "nightly")]
+    fn ten_thousand_xors_lazy(n: usize) -> AtomicThunk<usize> {
+        AtomicThunk::defer(move || {
+                               (0..black_box(10000))
+                                   .fold(black_box(n), |old, new| old ^ new)
+                           })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn ten_thousand_xors_threadsafe_strict(b: &mut Bencher) {
+        b.iter(|| {
+                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_strict).collect();
+                   black_box(things.pop())
+               })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn ten_thousand_xors_threadsafe_lazy(b: &mut Bencher) {
+        b.iter(|| {
+                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_lazy).collect();
+                   black_box(things.pop())
+               })
+    }
+
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn atomic_thunk_read_heavy(b: &mut Bencher) {
+        use std::sync::Barrier;
+        use std::thread;
+
+        // Four threads repeatedly dereference one long since evaluated thunk.
+        let thunk = Arc::new(AtomicThunk::computed(1u64));
+
+        b.iter(|| {
+            let barrier = Arc::new(Barrier::new(4));
+
+            let handles: Vec<_> = (0..4).map(|_| {
+                let thunk = thunk.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    (0..10000).map(|_| *black_box(&**thunk)).sum::<u64>()
+                })
+            }).collect();
+
+            for handle in handles {
+                black_box(handle.join().unwrap());
+            }
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn atomic_thunk_contended_short_evaluation(b: &mut Bencher) {
+        use std::sync::Barrier;
+        use std::thread;
+
+        // Four threads race to force a thunk whose evaluation is short, as is
+        // typical; the losers should mostly spin rather than park.
+        b.iter(|| {
+            let thunk = Arc::new(AtomicThunk::defer(|| (0..1000u64).map(black_box).sum::<u64>()));
+            let barrier = Arc::new(Barrier::new(4));
+
+            let handles: Vec<_> = (0..4).map(|_| {
+                let thunk = thunk.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    **thunk
+                })
+            }).collect();
+
+            for handle in handles {
+                black_box(handle.join().unwrap());
+            }
+        })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn arc_thunk_large_closures(b: &mut Bencher) {
+        b.iter(|| {
+                   let things: Vec<_> = (0..1000usize)
+                       .map(|i| ArcThunk::defer(move || black_box([i; 4]).len()))
+                       .collect();
+                   black_box(things.iter().map(|thing| **thing).sum::<usize>())
+               })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn arc_of_atomic_thunk_large_closures(b: &mut Bencher) {
+        b.iter(|| {
+                   let things: Vec<_> = (0..1000usize)
+                       .map(|i| Arc::new(AtomicThunk::defer(move || black_box([i; 4]).len())))
+                       .collect();
+                   black_box(things.iter().map(|thing| ***thing).sum::<usize>())
+               })
+    }
+
+
+    #[test]
+    fn arc_thunk_computed() {
+        let arc_thunk0 = ArcThunk::computed(1 + 1);
+        let arc_thunk1 = arc_thunk0.clone();
+
+        assert_eq!(arc_thunk0.0.flag.load(Ordering::Relaxed), THUNK_EVALUATED);
+        assert_eq!(&*arc_thunk1, &2);
+        assert_eq!(arc_thunk0.0.flag.load(Ordering::Relaxed), THUNK_EVALUATED);
+        assert_eq!(&*arc_thunk0, &2);
+    }
+
+    #[test]
+    fn arc_thunk_deferred() {
+        let arc_thunk0 = ArcThunk::defer(move || black_box(1) + 1);
+        let arc_thunk1 = arc_thunk0.clone();
+
+        assert_eq!(arc_thunk0.0.flag.load(Ordering::Relaxed), THUNK_DEFERRED);
+        assert_eq!(&*arc_thunk1, &2);
+        assert_eq!(arc_thunk0.0.flag.load(Ordering::Relaxed), THUNK_EVALUATED);
+        assert_eq!(&*arc_thunk0, &2);
+    }
+
+    #[test]
+    fn arc_thunk_lazy_shared() {
+        fn bump<L: LazyShared<Target = u32>>(thunk: &mut L) -> u32
+            where u32: Into<L>
+        {
+            *L::make_mut(thunk) += 1;
+            **thunk
+        }
+
+        let mut thunk0 = ArcThunk::defer(|| black_box(1) + 1);
+        let thunk1 = thunk0.clone();
+
+        assert!(LazyShared::ptr_eq(&thunk0, &thunk1));
+        assert_eq!(bump(&mut thunk0), 3);
+        assert!(!LazyShared::ptr_eq(&thunk0, &thunk1));
+        assert_eq!(LazyShared::try_unwrap(thunk1).ok(), Some(2));
+    }
+
+    #[test]
+    fn arc_thunk_downgrade() {
+        use std::thread;
+
+        let thunk = ArcThunk::defer(|| black_box(1) + 1);
+        let weak = ArcThunk::downgrade(&thunk);
+
+        let handle = {
+            let weak = weak.clone();
+            thread::spawn(move || weak.upgrade().map(|thunk| *thunk))
+        };
+        assert_eq!(handle.join().unwrap(), Some(2));
+        assert!(thunk.is_evaluated());
+
+        drop(thunk);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn arc_thunk_ptr_eq() {
+        let thunk0 = ArcThunk::defer(|| black_box(1) + 1);
+        let thunk1 = thunk0.clone();
+        let thunk2 = ArcThunk::computed(2);
+
+        assert!(ArcThunk::ptr_eq(&thunk0, &thunk1));
+        assert!(!ArcThunk::ptr_eq(&thunk0, &thunk2));
+        assert!(!thunk0.is_evaluated());
+    }
+
+    #[test]
+    fn arc_thunk_project() {
+        use std::thread;
+
+        let forced = Arc::new(AtomicUsize::new(0));
+        let thunk = {
+            let forced = forced.clone();
+            ArcThunk::defer(move || {
+                forced.fetch_add(1, Ordering::SeqCst);
+                (vec![1, 2, 3], String::from("abc"))
+            })
+        };
+
+        let numbers = ArcThunk::project(&thunk, |pair| &pair.0[..]);
+        let letters = ArcThunk::project(&thunk, |pair| &pair.1[..]);
+        let last = ArcThunkRef::project(&numbers, |numbers| &numbers[2]);
+        drop(thunk);
+
+        let handles = (0..4).map(|_| {
+            let last = last.clone();
+            thread::spawn(move || *last)
+        }).collect::<Vec<_>>();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 3);
+        }
+
+        assert_eq!(&*numbers, &[1, 2, 3]);
+        assert_eq!(&*letters, "abc");
+        assert_eq!(forced.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn atomic_thunk_contended() {
+        use std::thread;
+        use std::time::Duration;
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let thunk = {
+            let runs = runs.clone();
+            Arc::new(AtomicThunk::defer(move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(50));
+                42
+            }))
+        };
+
+        let handles: Vec<_> = (0..8).map(|_| {
+            let thunk = thunk.clone();
+            thread::spawn(move || **thunk)
+        }).collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 42);
+        }
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(thunk.state(), ThunkState::Evaluated);
+    }
+
+    #[test]
+    fn atomic_thunk_panic_wakes_waiters() {
+        use std::thread;
+        use std::time::Duration;
+
+        let thunk = Arc::new(AtomicThunk::<u32>::defer(|| {
+            thread::sleep(Duration::from_millis(50));
+            panic!("boom")
+        }));
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let thunk = thunk.clone();
+            thread::spawn(move || **thunk)
+        }).collect();
+
+        for handle in handles {
+            assert!(handle.join().is_err());
+        }
+    }
+
+    #[test]
+    fn atomic_thunk_poisoned() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let thunk = AtomicThunk::<u32>::defer(|| panic!("boom"));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| *thunk)).is_err());
+
+        assert_eq!(thunk.state(), ThunkState::Poisoned);
+        assert_eq!(thunk.force_checked(), Err(Poisoned));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| *thunk)).is_err());
+
+        let arc = ArcThunk::<u32>::defer(|| panic!("boom"));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| *arc)).is_err());
+        assert_eq!(ArcThunk::force_checked(&arc), Err(Poisoned));
+    }
+
+    #[test]
+    fn atomic_thunk_drops_in_every_state() {
+        let counter = Arc::new(());
+
+        let deferred = {
+            let captured = counter.clone();
+            AtomicThunk::defer(move || captured)
+        };
+        let evaluated = {
+            let captured = counter.clone();
+            AtomicThunk::defer(move || captured)
+        };
+        evaluated.force();
+        let unwrapped = {
+            let captured = counter.clone();
+            AtomicThunk::defer(move || captured)
+        }.unwrap();
+
+        assert_eq!(Arc::strong_count(&counter), 4);
+
+        drop((deferred, evaluated, unwrapped));
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn cache_padded_thunk() {
+        let thunks: Vec<_> = (0..4u64).map(|i| CachePaddedThunk::defer(move || i * i)).collect();
+
+        assert_eq!(mem::align_of::<CachePaddedThunk<u64>>(), 128);
+        assert_eq!(&thunks[1] as *const _ as usize - &thunks[0] as *const _ as usize, 128);
+        assert_eq!(thunks.iter().map(|thunk| **thunk).sum::<u64>(), 14);
+    }
+
+    // Without `std`, a thunk which forces itself spins forever instead.
+    #[cfg(feature = "std")]
+    #[test]
+    fn atomic_thunk_self_force_panics() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let thunk = Arc::new_cyclic(|this: &Weak<AtomicThunk<u32>>| {
+            let this = this.clone();
+            AtomicThunk::defer(move || *this.upgrade().unwrap().force() + 1)
+        });
+
+        let looped = panic::catch_unwind(AssertUnwindSafe(|| **thunk)).unwrap_err();
+        assert!(looped.downcast_ref::<&str>().unwrap().starts_with("<<loop>>"));
+        assert_eq!(thunk.force_checked(), Err(Poisoned));
+    }
+
+    #[test]
+    fn atomic_thunk_try_force() {
+        use std::sync::mpsc;
+        use std::thread;
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+        let thunk = Arc::new(AtomicThunk::defer(move || {
+            started_tx.send(()).unwrap();
+            finish_rx.recv().unwrap();
+            42
+        }));
+
+        let handle = {
+            let thunk = thunk.clone();
+            thread::spawn(move || **thunk)
+        };
+
+        started_rx.recv().unwrap();
+        assert_eq!(thunk.try_force(), Err(WouldBlock));
+        assert_eq!(thunk.try_deref(), Err(WouldBlock));
+
+        finish_tx.send(()).unwrap();
+        assert_eq!(handle.join().unwrap(), 42);
+        assert_eq!(thunk.try_deref(), Ok(&42));
+
+        let arc_thunk = ArcThunk::defer(|| black_box(1) + 1);
+        assert_eq!(ArcThunk::try_deref(&arc_thunk), Ok(&2));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn atomic_thunk_force_timeout() {
+        use std::sync::mpsc;
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+        let thunk = ArcThunk::defer(move || {
+            started_tx.send(()).unwrap();
+            finish_rx.recv().unwrap();
+            42
+        });
+
+        let handle = ArcThunk::spawn_force(&thunk);
+        started_rx.recv().unwrap();
+
+        assert_eq!(ArcThunk::force_timeout(&thunk, Duration::from_millis(10)), Err(Timeout));
+
+        finish_tx.send(()).unwrap();
+        assert_eq!(ArcThunk::force_timeout(&thunk, Duration::from_secs(10)), Ok(&42));
+        handle.join().unwrap();
+
+        let thunk = AtomicThunk::defer(|| black_box(1) + 1);
+        assert_eq!(thunk.force_timeout(Duration::from_millis(0)), Ok(&2));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn atomic_thunk_wait() {
+        use std::thread;
+
+        let thunk = ArcThunk::defer(|| black_box(1) + 1);
+        assert_eq!(ArcThunk::wait_timeout(&thunk, Duration::from_millis(10)), Err(Timeout));
+        assert!(!thunk.is_evaluated());
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let thunk = thunk.clone();
+            thread::spawn(move || *ArcThunk::wait(&thunk))
+        }).collect();
+
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(*thunk, 2);
+
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), 2);
+        }
+
+        let thunk = AtomicThunk::computed(3);
+        assert_eq!(*thunk.wait(), 3);
+
+        // A waiter which gives up on a thunk which is never forced.
+        let mut thunk = AtomicThunk::defer(|| black_box(4));
+        assert_eq!(thunk.wait_timeout(Duration::from_millis(1)), Err(Timeout));
+        assert_eq!(*thunk.force_mut(), 4);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn atomic_thunk_wait_timeout_polling() {
+        let thunk = AtomicThunk::defer(|| black_box(5));
+
+        // Each waiter which gives up takes itself back off the queue.
+        for _ in 0..100 {
+            assert_eq!(thunk.wait_timeout(Duration::from_micros(10)), Err(Timeout));
+            assert_eq!(thunk.flag.load(Ordering::Acquire), THUNK_DEFERRED);
+        }
+
+        assert_eq!(*thunk, 5);
+    }
+
+    #[test]
+    fn atomic_thunk_defer_local() {
+        use std::rc::Rc;
+        use std::thread;
+
+        let local = Rc::new(1);
+        let captured = local.clone();
+        let thunk = unsafe { ArcThunk::defer_local(move || *captured + 1) };
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 2);
+        assert_eq!(Rc::strong_count(&local), 1);
+
+        let remote = thunk.clone();
+        assert_eq!(thread::spawn(move || *remote).join().unwrap(), 2);
+
+        let sent = Arc::new(<AtomicThunk<i32> as LazySend>::defer_send(|| 1 + 1));
+        assert!(!sent.is_evaluated());
+        let remote = sent.clone();
+        assert_eq!(thread::spawn(move || **remote).join().unwrap(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn atomic_thunk_lazy_ext_stays_deferred() {
+        use crate::LazyExt;
+        use crate::combinators::sequence_send;
+
+        let seen = AtomicBool::new(false);
+        let thunk = AtomicThunk::defer(|| black_box(1) + 1)
+            .inspect_send(|_| seen.store(true, Ordering::SeqCst));
+        assert!(!thunk.is_evaluated());
+        assert!(!seen.load(Ordering::SeqCst));
+
+        let sequenced: CachePaddedThunk<Vec<i32>> =
+            sequence_send(vec![thunk, AtomicThunk::defer(|| black_box(3))]);
+        assert!(!sequenced.is_evaluated());
+        assert!(!seen.load(Ordering::SeqCst));
+
+        let mapped: ArcThunk<Vec<i32>> = sequenced.map_into_send();
+        assert!(!mapped.is_evaluated());
+
+        let late: AtomicLateThunk<i32> = AtomicThunk::defer(|| black_box(4)).map_into_send();
+        assert!(!late.is_evaluated());
+
+        assert_eq!(*mapped, vec![2, 3]);
+        assert!(seen.load(Ordering::SeqCst));
+        assert_eq!(*late, 4);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn arc_thunk_spawn_force() {
+        let thunk = ArcThunk::defer(|| black_box(20) + 22);
+        let handle = ArcThunk::spawn_force(&thunk);
+
+        handle.join().unwrap();
+        assert!(thunk.is_evaluated());
+        assert_eq!(*thunk, 42);
+
+        let panicking = ArcThunk::<u32>::defer(|| panic!("boom"));
+        assert!(ArcThunk::spawn_force(&panicking).join().is_err());
+    }
+
+    #[test]
+    fn arc_thunk_counts() {
+        let mut thunk0 = ArcThunk::defer(|| black_box(1) + 1);
+        assert!(ArcThunk::is_unique(&mut thunk0));
+
+        let thunk1 = thunk0.clone();
+        let weak = ArcThunk::downgrade(&thunk0);
+
+        assert_eq!(ArcThunk::strong_count(&thunk0), 2);
+        assert_eq!(ArcThunk::weak_count(&thunk0), 1);
+        assert!(!ArcThunk::is_unique(&mut thunk0));
+
+        drop(thunk1);
+        assert!(!ArcThunk::is_unique(&mut thunk0));
+
+        drop(weak);
+        assert!(ArcThunk::is_unique(&mut thunk0));
+        assert!(!thunk0.is_evaluated());
+    }
+
+    #[test]
+    fn arc_thunk_into_raw() {
+        let thunk0 = ArcThunk::defer(|| black_box(1) + 1);
+        let thunk1 = thunk0.clone();
+
+        // The pointer is thin, so it survives a trip through `c_void`.
+        let raw = ArcThunk::into_raw(thunk0) as *const ::core::ffi::c_void;
+        assert_eq!(ArcThunk::strong_count(&thunk1), 2);
+
+        let mut thunk0 = unsafe { ArcThunk::<usize>::from_raw(raw as *const usize) };
+        assert!(ArcThunk::ptr_eq(&thunk0, &thunk1));
+        assert!(!thunk0.is_evaluated());
+        assert_eq!(*thunk1, 2);
+
+        drop(thunk1);
+        assert!(ArcThunk::is_unique(&mut thunk0));
+        assert_eq!(ArcThunk::into_inner(thunk0), Some(2));
+
+        let raw = ArcThunk::into_raw(ArcThunk::from(3));
+        assert_eq!(unsafe { *raw }, 3);
+        drop(unsafe { ArcThunk::from_raw(raw) });
+    }
+
+    #[test]
+    fn arc_thunk_unwrap_or_clone() {
+        let thunk0 = ArcThunk::defer(|| vec![black_box(1), 2]);
+        let thunk1 = thunk0.clone();
+
+        assert_eq!(ArcThunk::unwrap_or_clone(thunk0), vec![1, 2]);
+        assert!(thunk1.is_evaluated());
+        assert_eq!(ArcThunk::unwrap_or_clone(thunk1), vec![1, 2]);
+    }
+
+    #[test]
+    fn arc_thunk_into_inner() {
+        use std::thread;
+
+        for _ in 0..16 {
+            let thunk = ArcThunk::defer(|| black_box(1) + 1);
+            let handles: Vec<_> = (0..4).map(|_| {
+                let thunk = thunk.clone();
+                thread::spawn(move || ArcThunk::into_inner(thunk))
+            }).collect();
+            let mut results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            results.push(ArcThunk::into_inner(thunk));
+
+            assert_eq!(results.iter().filter(|r| r.is_some()).count(), 1);
+            assert!(results.contains(&Some(2)));
+        }
+    }
+
+    #[test]
+    fn arc_thunk_try_unwrap_lazy() {
+        let thunk0 = ArcThunk::defer(|| black_box(1) + 1);
+        let thunk1 = thunk0.clone();
+
+        let thunk0 = ArcThunk::try_unwrap_lazy(thunk0).err().unwrap();
+        drop(thunk1);
+
+        let inner = ArcThunk::try_unwrap_lazy(thunk0).ok().unwrap();
+        assert!(!inner.is_evaluated());
+        assert_eq!(inner.unwrap(), 2);
+    }
+
+    #[test]
+    fn arc_thunk_drops_large_closures() {
+        let counter = Arc::new(());
+        let big = [7usize; 16];
+
+        let thunk0 = {
+            let captured = counter.clone();
+            ArcThunk::defer(move || (captured, big.iter().sum::<usize>()))
+        };
+        let thunk1 = {
+            let captured = counter.clone();
+            ArcThunk::defer(move || (captured, big.len()))
+        };
+        let lazy = {
+            let captured = counter.clone();
+            ArcThunk::try_unwrap_lazy(ArcThunk::defer(move || (captured, 0))).ok().unwrap()
+        };
+
+        assert_eq!(thunk0.1, 112);
+        assert_eq!(Arc::strong_count(&counter), 4);
+
+        drop((thunk0, thunk1, lazy));
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn arc_thunk_try_unwrap_with_weak() {
+        let thunk = ArcThunk::defer(|| String::from("value"));
+        let weak = ArcThunk::downgrade(&thunk);
+
+        assert_eq!(ArcThunk::strong_count(&thunk), 1);
+        assert_eq!(ArcThunk::try_unwrap(thunk).ok(), Some(String::from("value")));
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn thunk_ref_deferred() {
+        let arena = [String::from("foo"), String::from("bar")];
+        let thunk_ref: AtomicThunkRef<str> =
+            AtomicThunkRef::defer(|| &arena[black_box(1)][..]);
+
+        assert!(!thunk_ref.is_evaluated());
+        assert_eq!(&*thunk_ref, "bar");
+        assert!(thunk_ref.is_evaluated());
+    }
+
+    #[test]
+    fn thunk_set_replace() {
+        let mut thunk = AtomicThunk::defer(|| -> usize { panic!("should never be forced") });
+
+        assert_eq!(thunk.replace(1), None);
+        assert_eq!(thunk.replace(2), Some(1));
+        thunk.set(3);
+        assert_eq!(*thunk, 3);
+    }
+
+    #[test]
+    fn thunk_force_mut() {
+        let mut thunk = AtomicThunk::defer(|| black_box(1) + 1);
+
+        *thunk.force_mut() += 1;
+        assert!(thunk.is_evaluated());
+        assert_eq!(*thunk, 3);
+    }
+
+    #[test]
+    fn thunk_modify() {
+        let mut thunk = AtomicThunk::defer(|| black_box(1) + 1);
+
+        thunk.modify(|x| x * 10);
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 20);
+        thunk.modify(|x| x + 1);
+        assert_eq!(thunk.try_get(), Some(&21));
+    }
+
+    #[test]
+    fn thunk_map() {
+        let thunk = AtomicThunk::defer(|| black_box(1) + 1).map(|x| x * 10);
+
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 20);
+
+        let arc_thunk = ArcThunk::defer(|| black_box(1) + 1);
+        let mapped = ArcThunk::map(&arc_thunk, |x| x * 10);
+
+        assert_eq!(*mapped, 20);
+        assert!(arc_thunk.is_evaluated());
+    }
+
+    #[test]
+    fn thunk_and_then_flatten() {
+        let chained = AtomicThunk::defer(|| black_box(1) + 1)
+            .and_then(|x| AtomicThunk::defer(move || x * 10));
+
+        assert!(!chained.is_evaluated());
+        assert_eq!(*chained, 20);
+        assert_eq!(*AtomicThunk::computed(AtomicThunk::computed(2)).flatten(), 2);
+    }
+
+    #[test]
+    fn thunk_zip() {
+        let a = AtomicThunk::defer(|| black_box(1) + 1);
+        let zipped = a.zip(AtomicThunk::defer(|| "two"));
+
+        assert!(!zipped.is_evaluated());
+        assert_eq!(*zipped, (2, "two"));
+    }
+
+    #[test]
+    fn late_thunk_get_or_init() {
+        let late = Arc::new(AtomicLateThunk::<usize>::new());
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let late = late.clone();
+                ::std::thread::spawn(move || *late.get_or_init(|| i))
+            })
+            .collect();
+        let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        assert!(results.iter().all(|&r| r == results[0]));
+        assert_eq!(late.set(5), Err(5));
+        assert_eq!(late.get(), Some(&results[0]));
+    }
+
+    #[test]
+    fn late_thunk_get_or_init_retries_after_panic() {
+        let late = Arc::new(AtomicLateThunk::<usize>::new());
+        let (tx, rx) = ::std::sync::mpsc::channel();
+
+        let first = {
+            let late = late.clone();
+            ::std::thread::spawn(move || {
+                *late.get_or_init(|| {
+                    tx.send(()).unwrap();
+                    ::std::thread::sleep(::std::time::Duration::from_millis(50));
+                    panic!("first initializer")
+                })
+            })
+        };
+
+        rx.recv().unwrap();
+        let second = {
+            let late = late.clone();
+            ::std::thread::spawn(move || *late.get_or_init(|| 2))
+        };
+
+        assert!(first.join().is_err());
+        assert_eq!(second.join().unwrap(), 2);
+        assert_eq!(late.get(), Some(&2));
+    }
+
+    #[test]
+    fn late_thunk_state() {
+        let late = AtomicLateThunk::<usize>::new();
+        assert_eq!(late.0.state(), ThunkState::Empty);
+
+        late.set(1).unwrap();
+        assert_eq!(late.0.state(), ThunkState::Evaluated);
+    }
+
+    #[cfg(not(loom))]
+    fn plus_one(n: usize) -> usize {
+        black_box(n) + 1
+    }
+
+    #[cfg(not(loom))]
+    static PLUS_ONE: FnPtrThunk<usize, usize> = FnPtrThunk::with_arg(plus_one, 1);
+
+    #[cfg(not(loom))]
+    #[test]
+    fn fn_ptr_thunk_static() {
+        assert!(!PLUS_ONE.is_evaluated() || PLUS_ONE.try_get() == Some(&2));
+        assert_eq!(*PLUS_ONE, 2);
+        assert_eq!(PLUS_ONE.try_get(), Some(&2));
+        assert_eq!(FnPtrThunk::new(|| 3).unwrap(), 3);
+    }
+
+    #[cfg(not(loom))]
+    static PANICKY_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[cfg(not(loom))]
+    fn panicky() -> usize {
+        if PANICKY_STARTED.fetch_add(1, Ordering::SeqCst) == 0 {
+            ::std::thread::sleep(::std::time::Duration::from_millis(50));
+            panic!("first call");
+        }
+
+        black_box(2)
+    }
+
+    #[cfg(not(loom))]
+    static PANICKY: FnPtrThunk<usize> = FnPtrThunk::new(panicky);
+
+    #[cfg(not(loom))]
+    #[test]
+    fn fn_ptr_thunk_retries_after_racing_panic() {
+        let first = ::std::thread::spawn(|| *PANICKY);
+
+        while PANICKY_STARTED.load(Ordering::SeqCst) == 0 {
+            ::std::thread::yield_now();
+        }
+
+        let second = ::std::thread::spawn(|| *PANICKY);
+
+        assert!(first.join().is_err());
+        assert_eq!(second.join().unwrap(), 2);
+        assert_eq!(PANICKY.try_get(), Some(&2));
+    }
+
+
+    #[cfg(not(loom))]
+    #[test]
+    fn atomic_thunk_computed_const() {
+        static TWO: AtomicThunk<u32> = AtomicThunk::computed_const(2);
+
+        assert!(TWO.is_evaluated());
+        assert_eq!(*TWO, 2);
+    }
+
+
+    #[test]
+    fn atomic_thunk_get_pin_mut() {
+        use std::marker::PhantomPinned;
+        use std::pin::pin;
+
+        struct Counter(u32, PhantomPinned);
+
+        let mut thunk = pin!(AtomicThunk::defer(|| Counter(black_box(0), PhantomPinned)));
+
+        assert!(thunk.as_mut().try_get_pin_mut().is_none());
+        unsafe { thunk.as_mut().get_pin_mut().get_unchecked_mut().0 += 1 };
+        assert!(thunk.as_mut().try_get_pin_mut().is_some());
+        assert_eq!(thunk.0, 1);
+    }
+
+
+    #[test]
+    fn atomic_unsized_thunk_slice() {
+        use std::thread;
+
+        let thunk: AtomicUnsizedThunk<[usize]> =
+            AtomicUnsizedThunk::defer(|| vec![1, 2, 3].into_boxed_slice());
+        let thunk = Arc::new(thunk);
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let thunk = thunk.clone();
+            thread::spawn(move || thunk.iter().sum::<usize>())
+        }).collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 6);
+        }
+    }
+}
+
+
+/// Exhaustive checks of the state machine behind `AtomicThunk` and `ArcThunk`.
+/// Run them with `RUSTFLAGS="--cfg loom" cargo test --release --lib loom`.
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::*;
+
+    use loom::sync::Arc;
+    use loom::sync::atomic::AtomicUsize;
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn loom_concurrent_force() {
+        loom::model(|| {
+            let runs = Arc::new(AtomicUsize::new(0));
+            let thunk = Arc::new(AtomicThunk::defer_send({
+                let runs = runs.clone();
+                move || runs.fetch_add(1, Ordering::Relaxed) + 7
+            }));
+
+            let remote = thunk.clone();
+            let handle = thread::spawn(move || *remote.force());
+
+            assert_eq!(*thunk.force(), 7);
+            assert_eq!(handle.join().unwrap(), 7);
+            assert_eq!(runs.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn loom_three_way_force() {
+        loom::model(|| {
+            let thunk = Arc::new(AtomicThunk::defer_send(|| 7));
+
+            let handles: Vec<_> = (0..2).map(|_| {
+                let thunk = thunk.clone();
+                thread::spawn(move || *thunk.force())
+            }).collect();
+
+            assert_eq!(*thunk.force(), 7);
+
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), 7);
+            }
+        });
+    }
+
+    #[test]
+    fn loom_force_and_unwrap() {
+        loom::model(|| {
+            let thunk = ArcThunk::defer_send(|| 7);
+            let remote = thunk.clone();
+            let handle = thread::spawn(move || ArcThunk::into_inner(remote));
+
+            let local = ArcThunk::into_inner(thunk);
+            let remote = handle.join().unwrap();
+
+            // Exactly one handle was the last, and it got the value.
+            assert_eq!(local.xor(remote), Some(7));
+        });
+    }
+
+    #[test]
+    fn loom_force_and_drop() {
+        loom::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let thunk = ArcThunk::defer_send({
+                let drops = drops.clone();
+                move || Counted(drops)
+            });
+
+            let remote = thunk.clone();
+            let handle = thread::spawn(move || {
+                remote.force();
+            });
+
+            // The block is freed through `alloc::sync::Arc`, which `loom`
+            // cannot see, so only the last handle waits for the other thread.
+            let last = thunk.clone();
+            drop(thunk);
+            handle.join().unwrap();
+            drop(last);
+
+            assert_eq!(drops.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn loom_drop_unforced() {
+        loom::model(|| {
+            let drops = Arc::new(AtomicUsize::new(0));
+            let thunk = ArcThunk::defer_send({
+                let drops = drops.clone();
+                move || Counted(drops)
+            });
+
+            let remote = thunk.clone();
+            let handle = thread::spawn(move || drop(remote));
+
+            drop(thunk);
+            handle.join().unwrap();
+
+            assert_eq!(drops.load(Ordering::Relaxed), 0);
+        });
+    }
+
+    #[test]
+    fn loom_late_thunk_race() {
+        loom::model(|| {
+            let late = Arc::new(AtomicLateThunk::<usize>::new());
+
+            let remote = late.clone();
+            let handle = thread::spawn(move || *remote.get_or_init(|| 1));
+
+            let local = *late.get_or_init(|| 2);
+
+            assert_eq!(handle.join().unwrap(), local);
+            assert_eq!(late.get(), Some(&local));
+        });
     }
 }
@@ -1,53 +1,117 @@
-use std::borrow::{Borrow, BorrowMut};
-use std::cell::{Cell, UnsafeCell};
-use std::mem;
-use std::ops::{Deref, DerefMut};
-use std::rc::Rc;
-
-use unreachable::{unreachable, UncheckedOptionExt};
-
-use crate::{LazyRef, LazyMut, Lazy};
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+#[cfg(feature = "allocator_api")]
+use core::alloc::Allocator;
+use core::any::Any;
+use core::borrow::{Borrow, BorrowMut};
+use core::cell::{Cell, UnsafeCell};
+use core::mem::{self, ManuallyDrop, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+
+#[cfg(feature = "stable_deref")]
+use stable_deref_trait::{CloneStableDeref, StableDeref};
+
+use crate::{LazyRef, LazyMut, Lazy, LazyLocal, LazySend, LazyShared};
+use crate::erased::{never_called, Deferred, ErasedClosure};
 
 
 /// A non-thread-safe `Thunk`, representing a lazily computed value.
+///
+/// An `Option<Thunk<T>>` takes up no more space than a `Thunk<T>`.
+///
+/// Pinning a thunk pins its value: once computed, the value stays where it is
+/// until the thunk is dropped, and `get_pin_mut` projects a `Pin<&mut Thunk<T>>`
+/// to a `Pin<&mut T>`. Hence a `Thunk<T>` is only `Unpin` if `T` is:
+///
+/// ```compile_fail
+/// use std::marker::PhantomPinned;
+/// use thunk::Thunk;
+///
+/// fn assert_unpin<T: Unpin>() {}
+/// assert_unpin::<Thunk<PhantomPinned>>();
+/// ```
 pub struct Thunk<T> {
-    /// The `Flag` value is used to represent the state of the thunk. Ordinarily
-    /// it would be idiomatic Rust to simply store the `Cache` value as an enum,
-    /// and carry this `Flag` data as part of the enum discriminant; however,
-    /// here, it remains simpler to use an untagged union for the enum value in
-    /// order to avoid the need to check discriminants.
+    /// The `Flag` value is used to represent the state of the thunk, and says
+    /// which field of `data`, if any, is initialized. Ordinarily it would be
+    /// idiomatic Rust to simply store the `Cache` value as an enum, and carry
+    /// this `Flag` data as part of the enum discriminant; however, here, it
+    /// remains simpler to use an untagged union for the enum value in order to
+    /// avoid the need to check discriminants.
     flag: Cell<Flag>,
 
     /// Interior mutability is used here so that the fact that dereferencing a
     /// `Thunk` may cause a mutation is abstracted away.
-    data: UnsafeCell<Cache<T>>,
+    data: UnsafeCell<MaybeUninit<Cache<T>>>,
+
+    _niche: Niche,
 }
 
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Flag {
+    /// The `deferred` field of the cache holds the closure.
     Deferred,
+
+    /// The `evaluated` field of the cache holds the value.
     Evaluated,
+
+    /// The closure or value has been taken, and the cache is uninitialized.
     Empty,
+
+    /// The computation is running. The closure has been moved out, so the
+    /// cache is uninitialized. Forcing the thunk again from inside its own
+    /// computation would otherwise loop forever, or worse.
+    Evaluating,
+
+    /// The computation panicked, leaving neither a closure nor a value; the
+    /// cache is uninitialized.
+    Poisoned,
 }
 
 
-#[allow(unions_with_drop_fields)]
-union Cache<T> {
-    deferred: Box<dyn FnOnce() -> ()>,
-    evaluated: T,
+/// A byte with a single valid value. Since the flag of a thunk sits in a
+/// `Cell`, the compiler cannot use its invalid values to store the
+/// discriminant of an `Option<Thunk<T>>`; this byte's invalid values serve
+/// instead. It fits in the padding after the flag, so it costs nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum Niche {
+    Niche = 0,
+}
+
+
+/// Poisons a thunk if its computation unwinds, including when it unwinds
+/// because it forced its own thunk. On success, the guard is forgotten and
+/// the thunk marked evaluated instead.
+struct PoisonOnUnwind<'a>(&'a Cell<Flag>);
+
+
+impl<'a> Drop for PoisonOnUnwind<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.set(Flag::Poisoned);
+    }
+}
 
-    #[allow(dead_code)]
-    evaluating: (),
+
+/// The storage of a `Thunk`. Which field is initialized is recorded in the
+/// thunk's `Flag`; in the states with neither, the surrounding `MaybeUninit`
+/// is left uninitialized.
+union Cache<T> {
+    deferred: ManuallyDrop<ErasedClosure<T>>,
+    evaluated: ManuallyDrop<T>,
 }
 
 
 impl<T> Drop for Thunk<T> {
     fn drop(&mut self) {
         match self.flag.get() {
-            Flag::Deferred => mem::drop(unsafe { self.take_data().deferred }),
-            Flag::Evaluated => mem::drop(unsafe { self.take_data().evaluated }),
-            Flag::Empty => {}
+            Flag::Deferred => mem::drop(unsafe { ManuallyDrop::into_inner(self.take_data().deferred) }),
+            Flag::Evaluated => mem::drop(unsafe { ManuallyDrop::into_inner(self.take_data().evaluated) }),
+            Flag::Empty | Flag::Evaluating | Flag::Poisoned => {}
         }
     }
 }
@@ -57,14 +121,13 @@ impl<T> Cache<T> {
     /// PRECONDITION: `Cache` must be `Deferred`! UB results otherwise.
     ///
     /// Evaluate the thunk and replace the `Cache` with an `Evaluated` value
-    /// containing the computed result.
+    /// containing the computed result. While the closure runs, and if it
+    /// panics, the cache holds nothing.
     #[inline]
     unsafe fn evaluate_thunk(&mut self) {
-        let Cache { deferred: thunk } = mem::replace(self, Cache { evaluating: () });
+        let thunk = ManuallyDrop::take(&mut self.deferred);
 
-        let thunk_cast = Box::from_raw(Box::into_raw(thunk) as *mut dyn FnOnce() -> T);
-
-        mem::replace(self, Cache { evaluated: thunk_cast() });
+        *self = Cache { evaluated: ManuallyDrop::new(thunk.call()) };
     }
 }
 
@@ -106,9 +169,7 @@ impl<T> Deref for Thunk<T> {
 
     #[inline]
     fn deref(&self) -> &T {
-        self.force();
-
-        unsafe { &self.data.get().as_ref().unchecked_unwrap().evaluated }
+        self.force()
     }
 }
 
@@ -116,9 +177,7 @@ impl<T> Deref for Thunk<T> {
 impl<T> DerefMut for Thunk<T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut T {
-        self.force();
-
-        unsafe { &mut self.data.get().as_mut().unchecked_unwrap().evaluated }
+        self.force_mut()
     }
 }
 
@@ -126,229 +185,2476 @@ impl<T> DerefMut for Thunk<T> {
 impl<T> From<T> for Thunk<T> {
     #[inline]
     fn from(t: T) -> Thunk<T> {
+        Thunk::computed(t)
+    }
+}
+
+
+impl<T> Thunk<T> {
+    /// Construct a thunk with a precomputed value. Unlike `LazyRef::computed`,
+    /// this is a `const fn`, so it can initialize `const` items.
+    #[inline]
+    pub const fn computed(t: T) -> Thunk<T> {
         Thunk {
             flag: Cell::new(Flag::Evaluated),
-            data: UnsafeCell::new(Cache { evaluated: t }),
+            _niche: Niche::Niche,
+            data: UnsafeCell::new(MaybeUninit::new(Cache { evaluated: ManuallyDrop::new(t) })),
         }
     }
-}
 
 
-impl<T> Thunk<T> {
+    #[inline]
+    fn empty() -> Thunk<T> {
+        Thunk {
+            flag: Cell::new(Flag::Empty),
+            _niche: Niche::Niche,
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+
+    /// A pointer to the cache, which holds whatever the flag says it does.
+    #[inline]
+    fn cache(&self) -> *mut Cache<T> {
+        self.data.get() as *mut Cache<T>
+    }
+
+
+    /// Move the contents of the cache out, leaving the thunk empty. Which
+    /// field of the result is initialized depends on the flag beforehand.
     #[inline]
     fn take_data(&mut self) -> Cache<T> {
         self.flag.set(Flag::Empty);
-        mem::replace(&mut self.data, UnsafeCell::new(Cache { evaluating: () })).into_inner()
+        unsafe { ptr::read(self.cache()) }
     }
-}
 
 
-impl<T> LazyRef for Thunk<T> {
+    /// Defer a computation, allocating its closure with `alloc` if it is too
+    /// large to be stored inline in the thunk.
+    #[cfg(feature = "allocator_api")]
     #[inline]
-    fn defer<'a, F: FnOnce() -> T + 'a>(f: F) -> Thunk<T>
-        where T: 'a
+    pub fn defer_in<'a, F, A>(f: F, alloc: A) -> Thunk<T>
+        where F: FnOnce() -> T + 'a, A: Allocator + 'a, T: 'a
     {
-        let thunk = {
-            unsafe {
-                let thunk_raw: *mut dyn FnOnce() -> T = Box::into_raw(Box::new(f));
-                Box::from_raw(thunk_raw as *mut (dyn FnOnce() -> () + 'static))
-            }
-        };
-
         Thunk {
             flag: Cell::new(Flag::Deferred),
-            data: UnsafeCell::new(Cache { deferred: thunk }),
+            _niche: Niche::Niche,
+            data: UnsafeCell::new(MaybeUninit::new(Cache { deferred: ManuallyDrop::new(ErasedClosure::new_in(f, alloc)) })),
         }
     }
 
 
+    /// Get a reference to the computed value if the thunk has already been
+    /// evaluated. This never forces the thunk; if it is still deferred, `None`
+    /// is returned.
     #[inline]
-    fn force(&self) {
+    pub fn try_get(&self) -> Option<&T> {
         match self.flag.get() {
-            Flag::Deferred => {
-                unsafe {
-                    (*self.data.get()).evaluate_thunk();
-                }
+            Flag::Evaluated => unsafe { Some(&(*self.cache()).evaluated) },
+            Flag::Deferred | Flag::Empty | Flag::Evaluating | Flag::Poisoned => None,
+        }
+    }
 
-                self.flag.set(Flag::Evaluated);
-            }
-            Flag::Evaluated => {}
-            Flag::Empty => unsafe { unreachable() },
+
+    /// Get a mutable reference to the computed value if the thunk has already
+    /// been evaluated. This never forces the thunk; if it is still deferred,
+    /// `None` is returned.
+    #[inline]
+    pub fn try_get_mut(&mut self) -> Option<&mut T> {
+        match self.flag.get() {
+            Flag::Evaluated => unsafe { Some(&mut (*self.cache()).evaluated) },
+            Flag::Deferred | Flag::Empty | Flag::Evaluating | Flag::Poisoned => None,
         }
     }
-}
 
 
-impl<T> LazyMut for Thunk<T> {}
+    /// Force a pinned thunk, and get a pinned mutable reference to its value.
+    #[inline]
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut T> {
+        // The value is only ever moved out through `&mut Thunk<T>` or by
+        // value, neither of which is available from a pin unless `T: Unpin`.
+        unsafe { self.map_unchecked_mut(|thunk| thunk.force_mut()) }
+    }
+
+
+    /// Like `try_get_mut`, but for a pinned thunk. This never forces the
+    /// thunk.
+    #[inline]
+    pub fn try_get_pin_mut(self: Pin<&mut Self>) -> Option<Pin<&mut T>> {
+        unsafe { self.get_unchecked_mut().try_get_mut().map(|value| Pin::new_unchecked(value)) }
+    }
 
 
-impl<T> Lazy for Thunk<T> {
+    /// Get a reference to the computed value without checking whether the
+    /// thunk has been evaluated.
+    ///
+    /// # Safety
+    ///
+    /// The thunk must already be evaluated, e.g. by a previous call to
+    /// `force`. Calling this on a deferred or empty thunk is undefined
+    /// behavior.
     #[inline]
-    fn unwrap(mut self) -> T {
-        self.force();
+    pub unsafe fn get_unchecked(&self) -> &T {
+        &(*self.cache()).evaluated
+    }
+
 
-        unsafe { self.take_data().evaluated }
+    /// Unwrap the computed value without checking whether the thunk has been
+    /// evaluated.
+    ///
+    /// # Safety
+    ///
+    /// The thunk must already be evaluated, e.g. by a previous call to
+    /// `force`. Calling this on a deferred or empty thunk is undefined
+    /// behavior.
+    #[inline]
+    pub unsafe fn assume_evaluated(mut self) -> T {
+        ManuallyDrop::into_inner(self.take_data().evaluated)
     }
-}
 
 
-/// An `Rc`-wrapped `Thunk` which implements `LazyRef`.
-pub struct RcThunk<T>(Rc<Thunk<T>>);
+    /// Overwrite the thunk with an already computed value. If the thunk was
+    /// still deferred, its closure is dropped without ever being run.
+    #[inline]
+    pub fn set(&mut self, value: T) {
+        *self = Thunk::computed(value);
+    }
 
 
-impl<T> RcThunk<T> {
-    /// If the `RcThunk` is unevaluated, this will force it. If the `RcThunk` is
-    /// the sole, unique owner of the underlying thunk, this will return the forced
-    /// value; otherwise, it will return an `Err` containing the original `RcThunk`.
-    pub fn try_unwrap(this: RcThunk<T>) -> Result<T, RcThunk<T>> {
-        match Rc::try_unwrap(this.0) {
-            Ok(thunk) => Ok(thunk.unwrap()),
-            Err(rc) => Err(RcThunk(rc)),
+    /// Overwrite the thunk with an already computed value, returning the old
+    /// value if the thunk had already been evaluated. If the thunk was still
+    /// deferred, its closure is dropped without ever being run and `None` is
+    /// returned.
+    #[inline]
+    pub fn replace(&mut self, value: T) -> Option<T> {
+        mem::replace(self, Thunk::computed(value)).try_into_inner().ok()
+    }
+
+
+    /// Lazily map a function over the thunk's value, producing a new thunk.
+    /// Neither the original computation nor `f` are run until the new thunk is
+    /// forced.
+    pub fn map<'a, U, F: FnOnce(T) -> U + 'a>(self, f: F) -> Thunk<U>
+        where T: 'a, U: 'a
+    {
+        match self.into_deferred() {
+            Ok(thunk) => Thunk::defer(move || f(thunk())),
+            Err(value) => Thunk::defer(move || f(value)),
         }
     }
 
 
-    /// If the `RcThunk` is unevaluated, this will force it. If the `RcThunk` is
-    /// the sole, unique owner of the underlying thunk, this will return a
-    /// mutable reference to the forced value; otherwise, it will return `None`.
-    pub fn get_mut(this: &mut RcThunk<T>) -> Option<&mut T> {
-        Rc::get_mut(&mut this.0).map(DerefMut::deref_mut)
+    /// Lazily chain a computation producing another thunk onto this one. The
+    /// resulting thunk runs the original computation, `f`, and the thunk
+    /// returned by `f` only when it is forced.
+    pub fn and_then<'a, U, F: FnOnce(T) -> Thunk<U> + 'a>(self, f: F) -> Thunk<U>
+        where T: 'a, U: 'a
+    {
+        self.map(f).flatten()
     }
 
 
-    /// If the `RcThunk` is unevaluated, this will force it. If the `RcThunk`
-    /// is the sole, unique owner of the underlying thunk, this will return a
-    /// mutable reference to the forced value; if it is not, then it will clone
-    /// the forced value and return a mutable reference to the newly cloned
-    /// value. The `&mut RcThunk` passed in will be updated to reference the
-    /// newly cloned value.
-    pub fn make_mut(this: &mut RcThunk<T>) -> &mut T
-        where T: Clone
+    /// Lazily combine two thunks into a thunk of a pair. Neither thunk is
+    /// forced until the resulting thunk is.
+    #[inline]
+    pub fn zip<'a, U>(self, other: Thunk<U>) -> Thunk<(T, U)>
+        where T: 'a, U: 'a
+    {
+        self.zip_with(other, |t, u| (t, u))
+    }
+
+
+    /// Lazily combine two thunks with a function. Neither thunk is forced, and
+    /// `f` is not run, until the resulting thunk is.
+    pub fn zip_with<'a, U, V, F>(self, other: Thunk<U>, f: F) -> Thunk<V>
+        where T: 'a, U: 'a, V: 'a, F: FnOnce(T, U) -> V + 'a
+    {
+        Thunk::defer(move || f(self.unwrap(), other.unwrap()))
+    }
+
+
+    /// Disassemble the thunk without forcing it. If the thunk is still
+    /// deferred, the boxed closure is returned so that it can be run elsewhere
+    /// or moved into another thunk; otherwise, the computed value is returned
+    /// in the `Err` variant.
+    ///
+    /// Panics if the thunk is empty.
+    pub fn into_deferred(mut self) -> Result<Box<dyn FnOnce() -> T>, T> {
+        Thunk::take(&mut self).expect("attempted to disassemble an empty `Thunk`")
+    }
+
+
+    /// Lazily apply a transformation to the thunk's value. If the thunk is
+    /// still deferred, `f` is composed with the deferred closure and will only
+    /// run if the thunk is ever forced; otherwise, `f` is applied immediately.
+    ///
+    /// Panics if the thunk is empty.
+    pub fn modify<'a, F: FnOnce(T) -> T + 'a>(&mut self, f: F)
+        where T: 'a
     {
-        // No, moving it into a temp doesn't help. We just have to trust the CSE
-        // pass here. This is a known borrowchecking issue.
-        if Rc::get_mut(&mut this.0).is_some() {
-            return &mut **Rc::get_mut(&mut this.0)
-                              .expect("We know it's `some` - this won't change.");
+        match Thunk::take(self) {
+            Some(Ok(thunk)) => *self = Thunk::defer(move || f(thunk())),
+            Some(Err(value)) => *self = Thunk::computed(f(value)),
+            None => panic!("attempted to modify an empty `Thunk`"),
         }
+    }
 
-        let new_rc = Rc::new(Thunk::computed((*this.0).clone()));
-        this.0 = new_rc;
-        RcThunk::get_mut(this).unwrap()
+
+    /// Move the closure or the computed value out of the thunk without forcing
+    /// it, leaving the thunk empty, much like `Option::take`. An empty thunk
+    /// may be refilled with `refill_computed` or `refill_deferred`; forcing or
+    /// dereferencing it in the meantime will panic. If the thunk was already
+    /// empty, `None` is returned.
+    ///
+    /// Like `RcThunk::ptr_eq`, this is an associated function, so that it does
+    /// not shadow methods of the value, such as `Option::take`.
+    pub fn take(this: &mut Thunk<T>) -> Option<Result<Box<dyn FnOnce() -> T>, T>> {
+        match this.flag.get() {
+            Flag::Deferred => {
+                let thunk = unsafe { ManuallyDrop::into_inner(this.take_data().deferred) };
+                let f: Box<dyn FnOnce() -> T + '_> = Box::new(move || thunk.call());
+
+                // The closure lives as long as `T` does, which is all that
+                // `Thunk` ever promised about it.
+                Some(Ok(unsafe { mem::transmute::<Box<dyn FnOnce() -> T + '_>, Box<dyn FnOnce() -> T>>(f) }))
+            }
+            Flag::Evaluated => Some(Err(unsafe { ManuallyDrop::into_inner(this.take_data().evaluated) })),
+            Flag::Empty => None,
+            Flag::Evaluating => unreachable!("a `Thunk` was taken while being forced"),
+            Flag::Poisoned => panic!("attempted to take from a poisoned `Thunk`"),
+        }
     }
-}
 
 
-impl<T> Clone for RcThunk<T> {
-    fn clone(&self) -> RcThunk<T> {
-        RcThunk(self.0.clone())
+    /// Check whether the thunk has been emptied by `take`. This is an
+    /// associated function, so that it does not shadow methods of the value,
+    /// such as `Vec::is_empty`.
+    #[inline]
+    pub fn is_empty(this: &Thunk<T>) -> bool {
+        this.flag.get() == Flag::Empty
+    }
+
+
+    /// Refill an empty thunk with an already computed value.
+    ///
+    /// Panics if the thunk is not empty.
+    #[inline]
+    pub fn refill_computed(&mut self, value: T) {
+        assert!(Thunk::is_empty(self), "attempted to refill a non-empty `Thunk`");
+        *self = Thunk::computed(value);
+    }
+
+
+    /// Refill an empty thunk with a deferred computation.
+    ///
+    /// Panics if the thunk is not empty.
+    #[inline]
+    pub fn refill_deferred<'a, F: FnOnce() -> T + 'a>(&mut self, f: F)
+        where T: 'a
+    {
+        assert!(Thunk::is_empty(self), "attempted to refill a non-empty `Thunk`");
+        *self = Thunk::defer(f);
     }
 }
 
 
-impl<T> AsRef<T> for RcThunk<T> {
-    fn as_ref(&self) -> &T {
-        &self.0
+impl<T> Thunk<Thunk<T>> {
+    /// Lazily flatten a nested thunk. Forcing the resulting thunk forces both
+    /// the outer and the inner thunk.
+    #[inline]
+    pub fn flatten(self) -> Thunk<T> {
+        self.map(Lazy::unwrap)
     }
 }
 
 
-impl<T> Deref for RcThunk<T> {
-    type Target = T;
+impl<T> LazyRef for Thunk<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        match self.flag.get() {
+            Flag::Deferred => {
+                self.flag.set(Flag::Evaluating);
+                let poison = PoisonOnUnwind(&self.flag);
 
-    fn deref(&self) -> &T {
-        &self.0
+                unsafe {
+                    (*self.cache()).evaluate_thunk();
+                }
+
+                mem::forget(poison);
+                self.flag.set(Flag::Evaluated);
+            }
+            Flag::Evaluated => {}
+            Flag::Empty => panic!("attempted to force an empty `Thunk`"),
+            Flag::Evaluating => panic!("<<loop>>: attempted to force a `Thunk` from within its own computation"),
+            Flag::Poisoned => panic!("attempted to force a `Thunk` whose computation panicked"),
+        }
+
+        unsafe { &(*self.cache()).evaluated }
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.flag.get() == Flag::Evaluated
     }
 }
 
 
-impl<T> From<T> for RcThunk<T> {
-    fn from(t: T) -> RcThunk<T> {
-        RcThunk(Rc::new(Thunk::computed(t)))
+impl<T> LazyLocal for Thunk<T> {
+    #[inline]
+    fn defer_local<'a, F: FnOnce() -> T + 'a>(f: F) -> Thunk<T>
+        where T: 'a
+    {
+        Thunk {
+            flag: Cell::new(Flag::Deferred),
+            _niche: Niche::Niche,
+            data: UnsafeCell::new(MaybeUninit::new(Cache { deferred: ManuallyDrop::new(ErasedClosure::new(f)) })),
+        }
     }
 }
 
 
-impl<T> LazyRef for RcThunk<T> {
+impl<T> LazySend for Thunk<T> {
     #[inline]
-    fn defer<'a, F: FnOnce() -> T + 'a>(f: F) -> RcThunk<T> where T: 'a {
-        RcThunk(Rc::new(Thunk::defer(f)))
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> Thunk<T>
+        where T: 'a
+    {
+        Thunk::defer(f)
     }
+}
 
 
+impl<T> LazyMut for Thunk<T> {
     #[inline]
-    fn force(&self) {
-        self.0.force();
+    fn force_mut(&mut self) -> &mut T {
+        self.force();
+
+        unsafe { &mut (*self.cache()).evaluated }
     }
 }
 
 
-#[cfg(test)]
-mod test {
-    use super::*;
+impl<T> Lazy for Thunk<T> {
+    #[inline]
+    fn unwrap(mut self) -> T {
+        self.force();
 
-    use test::{self, Bencher};
+        unsafe { ManuallyDrop::into_inner(self.take_data().evaluated) }
+    }
+}
 
-    #[test]
-    fn thunk_computed() {
-        let thunk = Thunk::computed(1 + 1);
 
-        assert_eq!(*thunk, 2);
+/// A reusable, non-thread-safe thunk slot. A `ThunkCell` behaves like a
+/// `Thunk`, but it may be emptied and handed a new computation at any time, so
+/// that a single slot can be reused across iterations of a loop rather than
+/// constructing fresh thunks.
+///
+/// Like `RcThunk::ptr_eq`, `set`, `take`, `clear`, and `is_empty` are
+/// associated functions, so that they do not shadow methods of the value, such
+/// as `Vec::clear`.
+pub struct ThunkCell<T>(Thunk<T>);
+
+
+impl<T> ThunkCell<T> {
+    /// Construct an empty `ThunkCell`. Forcing or dereferencing it before it
+    /// is given a value or a computation will panic.
+    #[inline]
+    pub fn new() -> ThunkCell<T> {
+        ThunkCell(Thunk::empty())
     }
 
-    #[test]
-    fn thunk_deferred() {
-        let thunk = Thunk::defer(|| test::black_box(1) + 1);
 
-        assert_eq!(*thunk, 2);
+    /// Replace the contents of the cell with a new deferred computation,
+    /// whatever state it is in. Any previous value or unevaluated closure is
+    /// dropped.
+    #[inline]
+    pub fn redefer<'a, F: FnOnce() -> T + 'a>(&mut self, f: F)
+        where T: 'a
+    {
+        self.0 = Thunk::defer(f);
     }
 
-    fn ten_thousand_xors_strict(n: usize) -> Thunk<usize> {
-        Thunk::computed((0..test::black_box(10000)).fold(test::black_box(n), |old, new| old ^ new))
+
+    /// Replace the contents of the cell with an already computed value.
+    #[inline]
+    pub fn set(this: &mut ThunkCell<T>, value: T) {
+        this.0.set(value);
     }
 
-    fn ten_thousand_xors_lazy(n: usize) -> Thunk<usize> {
-        Thunk::defer(move || {
-                         (0..test::black_box(10000)).fold(test::black_box(n), |old, new| old ^ new)
-                     })
+
+    /// Take the value out of the cell, forcing it if necessary, and leave the
+    /// cell empty. If the cell was already empty, `None` is returned.
+    #[inline]
+    pub fn take(this: &mut ThunkCell<T>) -> Option<T> {
+        match Thunk::take(&mut this.0) {
+            Some(Ok(thunk)) => Some(thunk()),
+            Some(Err(value)) => Some(value),
+            None => None,
+        }
     }
 
-    #[bench]
-    fn ten_thousand_xors_threadsafe_strict(b: &mut Bencher) {
-        b.iter(|| {
-                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_strict).collect();
-                   test::black_box(things.pop())
-               })
+
+    /// Empty the cell, dropping its value or unevaluated closure.
+    #[inline]
+    pub fn clear(this: &mut ThunkCell<T>) {
+        Thunk::take(&mut this.0);
     }
 
-    #[bench]
-    fn ten_thousand_xors_threadsafe_lazy(b: &mut Bencher) {
-        b.iter(|| {
-                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_lazy).collect();
-                   test::black_box(things.pop())
-               })
+
+    /// Check whether the cell is empty.
+    #[inline]
+    pub fn is_empty(this: &ThunkCell<T>) -> bool {
+        Thunk::is_empty(&this.0)
     }
+}
 
 
-    #[test]
-    fn rc_thunk_computed() {
-        let rc_thunk0 = RcThunk::computed(1 + 1);
-        let rc_thunk1 = rc_thunk0.clone();
+impl<T> Default for ThunkCell<T> {
+    #[inline]
+    fn default() -> ThunkCell<T> {
+        ThunkCell::new()
+    }
+}
 
-        assert_eq!(rc_thunk0.0.flag.get(), Flag::Evaluated);
-        assert_eq!(&*rc_thunk1, &2);
-        assert_eq!(rc_thunk0.0.flag.get(), Flag::Evaluated);
-        assert_eq!(&*rc_thunk0, &2);
+
+impl<T> AsRef<T> for ThunkCell<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
     }
+}
 
-    #[test]
-    fn rc_thunk_deferred() {
-        let rc_thunk0 = RcThunk::defer(move || test::black_box(1) + 1);
-        let rc_thunk1 = rc_thunk0.clone();
 
-        assert_eq!(rc_thunk0.0.flag.get(), Flag::Deferred);
-        assert_eq!(&*rc_thunk1, &2);
-        assert_eq!(rc_thunk0.0.flag.get(), Flag::Evaluated);
-        assert_eq!(&*rc_thunk0, &2);
+impl<T> AsMut<T> for ThunkCell<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<T> Deref for ThunkCell<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.0.force()
+    }
+}
+
+
+impl<T> DerefMut for ThunkCell<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.0.force_mut()
+    }
+}
+
+
+impl<T> From<T> for ThunkCell<T> {
+    #[inline]
+    fn from(t: T) -> ThunkCell<T> {
+        ThunkCell(Thunk::computed(t))
+    }
+}
+
+
+impl<T> LazyRef for ThunkCell<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        self.0.force()
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<T> LazyLocal for ThunkCell<T> {
+    #[inline]
+    fn defer_local<'a, F: FnOnce() -> T + 'a>(f: F) -> ThunkCell<T>
+        where T: 'a
+    {
+        ThunkCell(Thunk::defer(f))
+    }
+}
+
+
+impl<T> LazySend for ThunkCell<T> {
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> ThunkCell<T>
+        where T: 'a
+    {
+        ThunkCell::defer(f)
+    }
+}
+
+
+impl<T> LazyMut for ThunkCell<T> {
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        self.0.force_mut()
+    }
+}
+
+
+impl<T> Lazy for ThunkCell<T> {
+    #[inline]
+    fn unwrap(self) -> T {
+        self.0.unwrap()
+    }
+}
+
+
+/// A non-thread-safe thunk whose computation may be supplied when it is first
+/// accessed, rather than when it is constructed, in the style of a `OnceCell`.
+/// A `LateThunk` may also be deferred like any other thunk, in which case it
+/// behaves exactly like a `Thunk`.
+pub struct LateThunk<T>(Thunk<T>);
+
+
+impl<T> LateThunk<T> {
+    /// Construct an uninitialized `LateThunk`. Forcing or dereferencing it
+    /// before it is initialized will panic.
+    #[inline]
+    pub fn new() -> LateThunk<T> {
+        LateThunk(Thunk::empty())
+    }
+
+
+    /// Get a reference to the value, initializing the thunk with `f` if it has
+    /// neither a value nor a deferred computation.
+    ///
+    /// Panics if `f` itself initializes the thunk.
+    pub fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        if self.0.flag.get() == Flag::Empty {
+            let value = f();
+
+            assert!(self.0.flag.get() == Flag::Empty, "reentrant init of a `LateThunk`");
+
+            unsafe {
+                self.0.cache().write(Cache { evaluated: ManuallyDrop::new(value) });
+            }
+
+            self.0.flag.set(Flag::Evaluated);
+        }
+
+        self.0.force()
+    }
+
+
+    /// Initialize the thunk with a value. If the thunk already has a value or
+    /// a deferred computation, the value is handed back in the `Err` variant.
+    #[inline]
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.0.flag.get() == Flag::Empty {
+            self.get_or_init(move || value);
+            Ok(())
+        } else {
+            Err(value)
+        }
+    }
+
+
+    /// Get a reference to the value if the thunk has already been evaluated.
+    /// This never forces the thunk.
+    #[inline]
+    pub fn get(&self) -> Option<&T> {
+        self.0.try_get()
+    }
+
+
+    /// Check whether the thunk has been initialized, either with a value or a
+    /// deferred computation.
+    #[inline]
+    pub fn is_initialized(&self) -> bool {
+        !Thunk::is_empty(&self.0)
+    }
+}
+
+
+impl<T> Default for LateThunk<T> {
+    #[inline]
+    fn default() -> LateThunk<T> {
+        LateThunk::new()
+    }
+}
+
+
+impl<T> Deref for LateThunk<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T> DerefMut for LateThunk<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+impl<T> From<T> for LateThunk<T> {
+    #[inline]
+    fn from(t: T) -> LateThunk<T> {
+        LateThunk(Thunk::computed(t))
+    }
+}
+
+
+impl<T> LazyRef for LateThunk<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        assert!(self.is_initialized(), "attempted to force an uninitialized `LateThunk`");
+        self.0.force()
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<T> LazyLocal for LateThunk<T> {
+    #[inline]
+    fn defer_local<'a, F: FnOnce() -> T + 'a>(f: F) -> LateThunk<T>
+        where T: 'a
+    {
+        LateThunk(Thunk::defer(f))
+    }
+}
+
+
+impl<T> LazySend for LateThunk<T> {
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> LateThunk<T>
+        where T: 'a
+    {
+        LateThunk::defer(f)
+    }
+}
+
+
+impl<T> LazyMut for LateThunk<T> {
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        assert!(self.is_initialized(), "attempted to force an uninitialized `LateThunk`");
+        self.0.force_mut()
+    }
+}
+
+
+impl<T> Lazy for LateThunk<T> {
+    #[inline]
+    fn unwrap(self) -> T {
+        assert!(self.is_initialized(), "attempted to unwrap an uninitialized `LateThunk`");
+        self.0.unwrap()
+    }
+}
+
+
+/// A non-thread-safe thunk which stores its closure inline instead of boxing
+/// it, and so never allocates. This makes it well suited to small, hot thunks
+/// in inner loops.
+///
+/// Since the type of the closure is part of the type of the thunk, an
+/// `InlineThunk` cannot implement `LazyRef` - whose `defer` accepts any closure
+/// at all - and instead provides the same operations as inherent methods.
+pub struct InlineThunk<T, F: FnOnce() -> T> {
+    flag: Cell<Flag>,
+    data: UnsafeCell<MaybeUninit<InlineCache<T, F>>>,
+    _niche: Niche,
+}
+
+
+/// The storage of an `InlineThunk`, which follows the same rules as `Cache`.
+union InlineCache<T, F> {
+    deferred: ManuallyDrop<F>,
+    evaluated: ManuallyDrop<T>,
+}
+
+
+impl<T, F: FnOnce() -> T> Drop for InlineThunk<T, F> {
+    fn drop(&mut self) {
+        match self.flag.get() {
+            Flag::Deferred => mem::drop(unsafe { ManuallyDrop::into_inner(self.take_data().deferred) }),
+            Flag::Evaluated => mem::drop(unsafe { ManuallyDrop::into_inner(self.take_data().evaluated) }),
+            Flag::Empty | Flag::Evaluating | Flag::Poisoned => {}
+        }
+    }
+}
+
+
+impl<T, F: FnOnce() -> T> InlineCache<T, F> {
+    /// PRECONDITION: `InlineCache` must be `Deferred`! UB results otherwise.
+    ///
+    /// Evaluate the closure and replace the `InlineCache` with an `Evaluated`
+    /// value containing the computed result.
+    #[inline]
+    unsafe fn evaluate_thunk(&mut self) {
+        let f = ManuallyDrop::take(&mut self.deferred);
+
+        *self = InlineCache { evaluated: ManuallyDrop::new(f()) };
+    }
+}
+
+
+impl<T, F: FnOnce() -> T> InlineThunk<T, F> {
+    /// Defer a computation, storing the closure inline.
+    #[inline]
+    pub fn defer(f: F) -> InlineThunk<T, F> {
+        InlineThunk {
+            flag: Cell::new(Flag::Deferred),
+            _niche: Niche::Niche,
+            data: UnsafeCell::new(MaybeUninit::new(InlineCache { deferred: ManuallyDrop::new(f) })),
+        }
+    }
+
+
+    /// Construct an `InlineThunk` with a precomputed value.
+    #[inline]
+    pub const fn computed(t: T) -> InlineThunk<T, F> {
+        InlineThunk {
+            flag: Cell::new(Flag::Evaluated),
+            _niche: Niche::Niche,
+            data: UnsafeCell::new(MaybeUninit::new(InlineCache { evaluated: ManuallyDrop::new(t) })),
+        }
+    }
+
+
+    #[inline]
+    fn cache(&self) -> *mut InlineCache<T, F> {
+        self.data.get() as *mut InlineCache<T, F>
+    }
+
+
+    #[inline]
+    fn take_data(&mut self) -> InlineCache<T, F> {
+        self.flag.set(Flag::Empty);
+        unsafe { ptr::read(self.cache()) }
+    }
+
+
+    /// Manually force the thunk's computation, returning a reference to the
+    /// computed value.
+    #[inline]
+    pub fn force(&self) -> &T {
+        match self.flag.get() {
+            Flag::Deferred => {
+                self.flag.set(Flag::Evaluating);
+                let poison = PoisonOnUnwind(&self.flag);
+
+                unsafe {
+                    (*self.cache()).evaluate_thunk();
+                }
+
+                mem::forget(poison);
+                self.flag.set(Flag::Evaluated);
+            }
+            Flag::Evaluated => {}
+            Flag::Empty => panic!("attempted to force an empty `InlineThunk`"),
+            Flag::Evaluating => panic!("<<loop>>: attempted to force an `InlineThunk` from within its own computation"),
+            Flag::Poisoned => panic!("attempted to force an `InlineThunk` whose computation panicked"),
+        }
+
+        unsafe { &(*self.cache()).evaluated }
+    }
+
+
+    /// Manually force the thunk's computation, returning a mutable reference
+    /// to the computed value.
+    #[inline]
+    pub fn force_mut(&mut self) -> &mut T {
+        self.force();
+
+        unsafe { &mut (*self.cache()).evaluated }
+    }
+
+
+    /// Check whether the thunk has already been evaluated, without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.flag.get() == Flag::Evaluated
+    }
+
+
+    /// Get a reference to the computed value if the thunk has already been
+    /// evaluated. This never forces the thunk.
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        match self.flag.get() {
+            Flag::Evaluated => unsafe { Some(&(*self.cache()).evaluated) },
+            Flag::Deferred | Flag::Empty | Flag::Evaluating | Flag::Poisoned => None,
+        }
+    }
+
+
+    /// Unwrap the thunk into its inner value. This forces the thunk.
+    #[inline]
+    pub fn unwrap(mut self) -> T {
+        self.force();
+
+        unsafe { ManuallyDrop::into_inner(self.take_data().evaluated) }
+    }
+}
+
+
+impl<T, F: FnOnce() -> T> AsRef<T> for InlineThunk<T, F> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T, F: FnOnce() -> T> AsMut<T> for InlineThunk<T, F> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<T, F: FnOnce() -> T> Deref for InlineThunk<T, F> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T, F: FnOnce() -> T> DerefMut for InlineThunk<T, F> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+impl<T, F: FnOnce() -> T> From<T> for InlineThunk<T, F> {
+    #[inline]
+    fn from(t: T) -> InlineThunk<T, F> {
+        InlineThunk::computed(t)
+    }
+}
+
+
+/// A reference-counted, non-thread-safe thunk which implements `LazyRef`.
+///
+/// Unlike an `Rc<Thunk<T>>`, an `RcThunk` keeps the reference counts, the
+/// state flag, the cached value, and the deferred closure in a single heap
+/// block, however large the closure is, so that creating one costs a single
+/// allocation and forcing one a single indirection. The closure is stored next
+/// to the value rather than overlapping it, so a block is as large as both
+/// together.
+///
+/// The closure of an `RcThunk` created with `defer_cloneable` also knows how to
+/// make fresh copies of itself, which `make_mut` uses to avoid forcing a shared
+/// thunk which has not been evaluated yet.
+pub struct RcThunk<T>(RcPtr<T>);
+
+
+/// The heap block behind an `RcThunk`. `C` is the deferred closure, erased to
+/// a `dyn RcDeferred<T>` once the block is allocated.
+///
+/// The layout is fixed so that the value sits at the same offset whatever the
+/// closure, and `RcThunk::from_raw` can find the start of the block from a
+/// pointer to the value.
+#[repr(C)]
+struct RcInner<T, C: ?Sized + RcDeferred<T>> {
+    /// Turns a thin pointer to the block back into a pointer which carries
+    /// the vtable of its closure.
+    unthin: unsafe fn(*const u8) -> *const RcInner<T, dyn RcDeferred<T>>,
+
+    /// `Empty` while the thunk is being evaluated, and after its value has
+    /// been moved out or its closure has panicked.
+    flag: Cell<Flag>,
+    value: UnsafeCell<MaybeUninit<T>>,
+    closure: UnsafeCell<C>,
+}
+
+
+type RcPtr<T> = Rc<RcInner<T, dyn RcDeferred<T>>>;
+
+
+/// The deferred closure of an `RcThunk`, which may also be able to make a
+/// fresh, deferred copy of the thunk it belongs to.
+trait RcDeferred<T>: Deferred<T> {
+    /// PRECONDITION: must never be called after `call_in_place` or
+    /// `drop_in_place`.
+    unsafe fn respawn(&self) -> Option<RcPtr<T>>;
+}
+
+
+impl<T, F: FnOnce() -> T> RcDeferred<T> for ManuallyDrop<F> {
+    #[inline]
+    unsafe fn respawn(&self) -> Option<RcPtr<T>> {
+        None
+    }
+}
+
+
+/// The closure of an `RcThunk` created with `defer_cloneable`.
+struct Cloneable<F>(ManuallyDrop<F>);
+
+
+impl<T, F: FnOnce() -> T> Deferred<T> for Cloneable<F> {
+    #[inline]
+    unsafe fn call_in_place(&mut self) -> T {
+        self.0.call_in_place()
+    }
+
+
+    #[inline]
+    unsafe fn drop_in_place(&mut self) {
+        Deferred::<T>::drop_in_place(&mut self.0)
+    }
+}
+
+
+impl<T, F: FnOnce() -> T + Clone> RcDeferred<T> for Cloneable<F> {
+    #[inline]
+    unsafe fn respawn(&self) -> Option<RcPtr<T>> {
+        Some(RcInner::new_deferred(Cloneable(self.0.clone())))
+    }
+}
+
+
+impl<T, C: ?Sized + RcDeferred<T>> Drop for RcInner<T, C> {
+    fn drop(&mut self) {
+        match self.flag.get() {
+            Flag::Deferred => unsafe { self.closure.get_mut().drop_in_place() },
+            Flag::Evaluated => unsafe { ptr::drop_in_place(self.value.get_mut().as_mut_ptr()) },
+            Flag::Empty | Flag::Evaluating | Flag::Poisoned => {}
+        }
+    }
+}
+
+
+impl<T, C: RcDeferred<T>> RcInner<T, C> {
+    #[inline]
+    fn new(flag: Flag, value: MaybeUninit<T>, closure: C) -> RcInner<T, C> {
+        RcInner {
+            unthin: RcInner::<T, C>::unthin,
+            flag: Cell::new(flag),
+            value: UnsafeCell::new(value),
+            closure: UnsafeCell::new(closure),
+        }
+    }
+
+
+    /// PRECONDITION: `ptr` must point to the start of an `RcInner<T, C>`.
+    unsafe fn unthin(ptr: *const u8) -> *const RcInner<T, dyn RcDeferred<T>> {
+        let ptr: *const RcInner<T, dyn RcDeferred<T> + '_> = ptr as *const RcInner<T, C>;
+
+        // As in `RcInner::erase`.
+        mem::transmute::<*const RcInner<T, dyn RcDeferred<T> + '_>, *const RcInner<T, dyn RcDeferred<T>>>(ptr)
+    }
+}
+
+
+impl<T> RcInner<T, dyn RcDeferred<T>> {
+    #[inline]
+    fn deferred<'a, F: FnOnce() -> T + 'a>(f: F) -> RcPtr<T>
+        where T: 'a
+    {
+        RcInner::new_deferred(ManuallyDrop::new(f))
+    }
+
+
+    #[inline]
+    fn new_deferred<'a, C: RcDeferred<T> + 'a>(closure: C) -> RcPtr<T>
+        where T: 'a
+    {
+        RcInner::erase(Rc::new(RcInner::new(Flag::Deferred, MaybeUninit::uninit(), closure)))
+    }
+
+
+    /// Like `deferred`, but the closure is built by `make` from a weak
+    /// handle to the block being allocated.
+    #[inline]
+    fn cyclic<'a, G, M>(make: M) -> RcPtr<T>
+        where T: 'a,
+              G: FnOnce() -> T + 'a,
+              M: FnOnce(Weak<RcInner<T, dyn RcDeferred<T>>>) -> G
+    {
+        RcInner::erase(Rc::new_cyclic(|weak: &Weak<RcInner<T, ManuallyDrop<G>>>| {
+            let weak: Weak<RcInner<T, dyn RcDeferred<T> + 'a>> = weak.clone();
+
+            // As in `erase`; the weak handle is owned by the closure.
+            let closure = make(unsafe { mem::transmute::<Weak<RcInner<T, dyn RcDeferred<T> + 'a>>, Weak<RcInner<T, dyn RcDeferred<T>>>>(weak) });
+            RcInner::new(Flag::Deferred, MaybeUninit::uninit(), ManuallyDrop::new(closure))
+        }))
+    }
+
+
+    #[inline]
+    fn computed(t: T) -> RcPtr<T> {
+        RcInner::erase(Rc::new(RcInner::new(Flag::Evaluated, MaybeUninit::new(t), ManuallyDrop::new(never_called::<T>))))
+    }
+
+
+    /// Erase the lifetime of the closure, as `Thunk` does; the closure must
+    /// live as long as the type which the thunk computes.
+    #[inline]
+    fn erase<'a>(rc: Rc<RcInner<T, dyn RcDeferred<T> + 'a>>) -> RcPtr<T> {
+        unsafe { mem::transmute::<Rc<RcInner<T, dyn RcDeferred<T> + 'a>>, RcPtr<T>>(rc) }
+    }
+
+
+    /// PRECONDITION: `value` must point to the value of an `RcInner<T, _>`.
+    ///
+    /// Find the block which `value` belongs to.
+    #[inline]
+    unsafe fn from_value(value: *const T) -> *const RcInner<T, dyn RcDeferred<T>> {
+        // Every `RcInner<T, _>` is laid out the same way up to its closure, so
+        // any closure type will do to find the value and the `unthin` hook.
+        let start = (value as *const u8).sub(mem::offset_of!(RcInner<T, ManuallyDrop<fn() -> T>>, value));
+        ((*(start as *const RcInner<T, ManuallyDrop<fn() -> T>>)).unthin)(start)
+    }
+
+
+    #[inline]
+    fn force(&self) -> &T {
+        match self.flag.get() {
+            Flag::Deferred => {
+                self.flag.set(Flag::Evaluating);
+                let poison = PoisonOnUnwind(&self.flag);
+
+                unsafe {
+                    let value = (*self.closure.get()).call_in_place();
+                    (*self.value.get()).as_mut_ptr().write(value);
+                }
+
+                mem::forget(poison);
+                self.flag.set(Flag::Evaluated);
+            }
+            Flag::Evaluated => {}
+            Flag::Empty => panic!("attempted to force an empty `RcThunk`"),
+            Flag::Evaluating => {
+                panic!("<<loop>>: attempted to force an `RcThunk` from within its own computation")
+            }
+            Flag::Poisoned => panic!("attempted to force an `RcThunk` whose computation panicked"),
+        }
+
+        unsafe { &*(*self.value.get()).as_ptr() }
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.flag.get() == Flag::Evaluated
+    }
+
+
+    /// Make a fresh, deferred copy of the thunk if its closure can be cloned
+    /// and has not been run yet.
+    #[inline]
+    fn respawn(&self) -> Option<RcPtr<T>> {
+        match self.flag.get() {
+            Flag::Deferred => unsafe { (*self.closure.get()).respawn() },
+            Flag::Evaluated | Flag::Empty | Flag::Evaluating | Flag::Poisoned => None,
+        }
+    }
+
+
+    #[inline]
+    fn force_mut(&mut self) -> &mut T {
+        self.force();
+        unsafe { &mut *self.value.get_mut().as_mut_ptr() }
+    }
+}
+
+
+impl<T> RcThunk<T> {
+    /// Defer a computation whose closure can be cloned. Unlike an `RcThunk`
+    /// created with `defer`, calling `make_mut` on a shared, unevaluated
+    /// `RcThunk` created this way clones the closure rather than forcing the
+    /// shared thunk and cloning its value.
+    pub fn defer_cloneable<'a, F: Fn() -> T + Clone + 'a>(f: F) -> RcThunk<T>
+        where T: 'a
+    {
+        RcThunk(RcInner::new_deferred(Cloneable(ManuallyDrop::new(f))))
+    }
+
+
+    /// Defer a computation which refers to the thunk it defines. The closure
+    /// is given a weak handle to the new thunk, which it may stash in the
+    /// value it computes (for example, in the tail of a lazy stream) without
+    /// creating a reference cycle. Forcing the thunk from within the closure
+    /// panics.
+    pub fn recursive<'a, F: FnOnce(WeakRcThunk<T>) -> T + 'a>(f: F) -> RcThunk<T>
+        where T: 'a
+    {
+        RcThunk(RcInner::cyclic(move |weak| move || f(WeakRcThunk(weak))))
+    }
+
+
+    /// If the `RcThunk` is unevaluated, this will force it. If the `RcThunk` is
+    /// the sole, unique owner of the underlying thunk, this will return the forced
+    /// value; otherwise, it will return an `Err` containing the original `RcThunk`.
+    pub fn try_unwrap(this: RcThunk<T>) -> Result<T, RcThunk<T>> {
+        if Rc::strong_count(&this.0) != 1 {
+            return Err(this);
+        }
+
+        this.0.force();
+        this.0.flag.set(Flag::Empty);
+
+        // The flag is now `Empty`, so the value will not be dropped again when
+        // the block is, and any weak handles can no longer be upgraded.
+        Ok(unsafe { ptr::read((*this.0.value.get()).as_ptr()) })
+    }
+
+
+    /// If the `RcThunk` is unevaluated, this will force it. If the `RcThunk` is
+    /// the sole, unique owner of the underlying thunk, this will return a
+    /// mutable reference to the forced value; otherwise, it will return `None`.
+    pub fn get_mut(this: &mut RcThunk<T>) -> Option<&mut T> {
+        Rc::get_mut(&mut this.0).map(RcInner::force_mut)
+    }
+
+
+    /// Lazily map a function over the shared value, producing a new `RcThunk`
+    /// which holds onto this one. Neither the original computation nor `f` are
+    /// run until the new thunk is forced.
+    pub fn map<'a, U, F: FnOnce(&T) -> U + 'a>(this: &RcThunk<T>, f: F) -> RcThunk<U>
+        where T: 'a, U: 'a
+    {
+        let this = this.clone();
+        RcThunk::defer(move || f(&this))
+    }
+
+
+    /// Lazily project a borrowed part of the shared value, producing an
+    /// `RcThunkRef` which keeps the underlying thunk alive. Neither the
+    /// original computation nor `f` are run until the projection is forced.
+    pub fn project<U: ?Sized, F: FnOnce(&T) -> &U + 'static>(this: &RcThunk<T>, f: F) -> RcThunkRef<U>
+        where T: 'static
+    {
+        let parent = this.clone();
+        RcThunkRef {
+            target: RcThunk::defer(move || NonNull::from(f(&parent))),
+            owner: Rc::new(this.clone()),
+        }
+    }
+
+
+    /// If the `RcThunk` is unevaluated, this will force it. If the `RcThunk`
+    /// is the sole, unique owner of the underlying thunk, this will return a
+    /// mutable reference to the forced value; if it is not, then it will clone
+    /// the forced value and return a mutable reference to the newly cloned
+    /// value. The `&mut RcThunk` passed in will be updated to reference the
+    /// newly cloned value.
+    ///
+    /// If the `RcThunk` was created with `defer_cloneable` and the shared
+    /// thunk has not been evaluated yet, the deferred closure is cloned
+    /// instead of the value, so the shared thunk is left unevaluated.
+    pub fn make_mut(this: &mut RcThunk<T>) -> &mut T
+        where T: Clone
+    {
+        if Rc::get_mut(&mut this.0).is_none() {
+            this.0 = match this.0.respawn() {
+                Some(respawn) => respawn,
+                None => RcInner::computed(this.0.force().clone()),
+            };
+        }
+
+        RcThunk::get_mut(this).unwrap()
+    }
+
+
+    /// Create a weak handle to the underlying thunk, which does not keep it
+    /// alive. This never forces the thunk.
+    #[inline]
+    pub fn downgrade(this: &RcThunk<T>) -> WeakRcThunk<T> {
+        WeakRcThunk(Rc::downgrade(&this.0))
+    }
+
+
+    /// Check whether two handles point to the same underlying thunk, without
+    /// forcing either. Like `Rc::ptr_eq`, this is an associated function, so
+    /// that it does not shadow methods of the value.
+    #[inline]
+    pub fn ptr_eq(this: &RcThunk<T>, other: &RcThunk<T>) -> bool {
+        Rc::ptr_eq(&this.0, &other.0)
+    }
+
+
+    /// The number of strong handles to the underlying thunk.
+    #[inline]
+    pub fn strong_count(this: &RcThunk<T>) -> usize {
+        Rc::strong_count(&this.0)
+    }
+
+
+    /// The number of weak handles to the underlying thunk.
+    #[inline]
+    pub fn weak_count(this: &RcThunk<T>) -> usize {
+        Rc::weak_count(&this.0)
+    }
+
+
+    /// Check whether this is the only handle, strong or weak, to the
+    /// underlying thunk. If it is, `get_mut` will succeed and `make_mut` will
+    /// not clone the value.
+    #[inline]
+    pub fn is_unique(this: &mut RcThunk<T>) -> bool {
+        Rc::get_mut(&mut this.0).is_some()
+    }
+
+
+    /// Consume the handle, returning a pointer to the slot of the value in
+    /// the underlying thunk. This never forces the thunk, so the slot only
+    /// holds a value if the thunk has been evaluated. As with `Rc::into_raw`,
+    /// the strong count is left untouched, so the thunk leaks unless the
+    /// pointer is passed back to `from_raw`.
+    #[inline]
+    pub fn into_raw(this: RcThunk<T>) -> *const T {
+        let inner = Rc::into_raw(this.0);
+        unsafe { ptr::addr_of!((*inner).value) as *const T }
+    }
+
+
+    /// Reconstruct a handle from a pointer returned by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `RcThunk::<T>::into_raw`, and each
+    /// pointer may be converted back only once, since it owns the strong
+    /// count the original handle held.
+    #[inline]
+    pub unsafe fn from_raw(ptr: *const T) -> RcThunk<T> {
+        RcThunk(Rc::from_raw(RcInner::from_value(ptr)))
+    }
+}
+
+
+impl<T> Clone for RcThunk<T> {
+    fn clone(&self) -> RcThunk<T> {
+        RcThunk(self.0.clone())
+    }
+}
+
+
+impl<T> AsRef<T> for RcThunk<T> {
+    fn as_ref(&self) -> &T {
+        self.0.force()
+    }
+}
+
+
+impl<T> Deref for RcThunk<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.0.force()
+    }
+}
+
+
+// The value lives in the shared allocation and is never moved out while any
+// handle remains, so its address is stable across moves and clones.
+#[cfg(feature = "stable_deref")]
+unsafe impl<T> StableDeref for RcThunk<T> {}
+
+
+#[cfg(feature = "stable_deref")]
+unsafe impl<T> CloneStableDeref for RcThunk<T> {}
+
+
+impl<T> From<T> for RcThunk<T> {
+    fn from(t: T) -> RcThunk<T> {
+        RcThunk(RcInner::computed(t))
+    }
+}
+
+
+impl<T> LazyRef for RcThunk<T> {
+    #[inline]
+    fn force(&self) -> &T {
+        self.0.force()
+    }
+
+
+    #[inline]
+    fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<T> LazyLocal for RcThunk<T> {
+    #[inline]
+    fn defer_local<'a, F: FnOnce() -> T + 'a>(f: F) -> RcThunk<T> where T: 'a {
+        RcThunk(RcInner::deferred(f))
+    }
+}
+
+
+impl<T> LazySend for RcThunk<T> {
+    #[inline]
+    fn defer_send<'a, F: FnOnce() -> T + Send + 'a>(f: F) -> RcThunk<T>
+        where T: 'a
+    {
+        RcThunk::defer(f)
+    }
+}
+
+
+impl<T> LazyShared for RcThunk<T> {
+    #[inline]
+    fn try_unwrap(this: RcThunk<T>) -> Result<T, RcThunk<T>> {
+        RcThunk::try_unwrap(this)
+    }
+
+
+    #[inline]
+    fn get_mut(this: &mut RcThunk<T>) -> Option<&mut T> {
+        RcThunk::get_mut(this)
+    }
+
+
+    #[inline]
+    fn make_mut(this: &mut RcThunk<T>) -> &mut T
+        where T: Clone
+    {
+        RcThunk::make_mut(this)
+    }
+
+
+    #[inline]
+    fn ptr_eq(this: &RcThunk<T>, other: &RcThunk<T>) -> bool {
+        RcThunk::ptr_eq(this, other)
+    }
+}
+
+
+/// A weak handle to the thunk behind an `RcThunk`, which does not keep the
+/// thunk or its value alive. This is the `RcThunk` counterpart of `Weak`.
+pub struct WeakRcThunk<T>(Weak<RcInner<T, dyn RcDeferred<T>>>);
+
+
+impl<T> WeakRcThunk<T> {
+    /// Create a weak handle which points to nothing; upgrading it always
+    /// fails.
+    #[inline]
+    pub fn new() -> WeakRcThunk<T> {
+        let weak: Weak<RcInner<T, dyn RcDeferred<T> + '_>> =
+            Weak::<RcInner<T, ManuallyDrop<fn() -> T>>>::new();
+
+        // As in `RcInner::erase`; the weak handle never points to a closure.
+        WeakRcThunk(unsafe { mem::transmute::<Weak<RcInner<T, dyn RcDeferred<T> + '_>>, Weak<RcInner<T, dyn RcDeferred<T>>>>(weak) })
+    }
+
+
+    /// Attempt to get a strong handle to the thunk, returning `None` if it has
+    /// already been dropped. This never forces the thunk.
+    #[inline]
+    pub fn upgrade(&self) -> Option<RcThunk<T>> {
+        self.0.upgrade().map(RcThunk)
+    }
+}
+
+
+impl<T> Clone for WeakRcThunk<T> {
+    #[inline]
+    fn clone(&self) -> WeakRcThunk<T> {
+        WeakRcThunk(self.0.clone())
+    }
+}
+
+
+impl<T> Default for WeakRcThunk<T> {
+    #[inline]
+    fn default() -> WeakRcThunk<T> {
+        WeakRcThunk::new()
+    }
+}
+
+
+/// A lazily computed reference into the value of an `RcThunk`, produced by
+/// `RcThunk::project`. Forcing it forces the underlying thunk; clones share
+/// both the underlying thunk and the projection.
+pub struct RcThunkRef<U: ?Sized> {
+    /// The projected pointer, which points into the value owned by `owner`.
+    target: RcThunk<NonNull<U>>,
+
+    /// Keeps the value `target` points into alive.
+    owner: Rc<dyn Any>,
+}
+
+
+impl<U: ?Sized> RcThunkRef<U> {
+    /// Manually force the projection, returning a reference to the projected
+    /// value.
+    #[inline]
+    pub fn force(&self) -> &U {
+        unsafe { self.target.force().as_ref() }
+    }
+
+
+    /// Check whether the projection has already been computed, without
+    /// forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.target.is_evaluated()
+    }
+
+
+    /// Lazily project further into the projected value. The new projection
+    /// keeps the same underlying thunk alive.
+    pub fn project<V: ?Sized, F: FnOnce(&U) -> &V + 'static>(this: &RcThunkRef<U>, f: F) -> RcThunkRef<V>
+        where U: 'static
+    {
+        let parent = this.clone();
+        RcThunkRef {
+            target: RcThunk::defer(move || NonNull::from(f(&parent))),
+            owner: this.owner.clone(),
+        }
+    }
+}
+
+
+impl<U: ?Sized> Clone for RcThunkRef<U> {
+    #[inline]
+    fn clone(&self) -> RcThunkRef<U> {
+        RcThunkRef {
+            target: self.target.clone(),
+            owner: self.owner.clone(),
+        }
+    }
+}
+
+
+impl<U: ?Sized> AsRef<U> for RcThunkRef<U> {
+    #[inline]
+    fn as_ref(&self) -> &U {
+        self.force()
+    }
+}
+
+
+impl<U: ?Sized> Deref for RcThunkRef<U> {
+    type Target = U;
+
+    #[inline]
+    fn deref(&self) -> &U {
+        self.force()
+    }
+}
+
+
+/// A non-thread-safe thunk which lazily computes a borrowed reference. The
+/// deferred closure produces a `&'a T`, and the `ThunkRef` dereferences to the
+/// `T` behind it; the `ThunkRef` never takes ownership of the target.
+pub struct ThunkRef<'a, T: ?Sized + 'a>(Thunk<&'a T>);
+
+
+impl<'a, T: ?Sized + 'a> ThunkRef<'a, T> {
+    /// Construct a `ThunkRef` from an already known reference. Forcing it is a
+    /// no-op.
+    #[inline]
+    pub fn computed(t: &'a T) -> ThunkRef<'a, T> {
+        ThunkRef(Thunk::computed(t))
+    }
+
+
+    /// Defer the computation of a reference.
+    #[inline]
+    pub fn defer<F: FnOnce() -> &'a T + 'a>(f: F) -> ThunkRef<'a, T> {
+        ThunkRef(Thunk::defer(f))
+    }
+
+
+    /// Manually force the computation of the reference, returning it.
+    #[inline]
+    pub fn force(&self) -> &'a T {
+        self.0.force()
+    }
+
+
+    /// Check whether the reference has already been computed, without forcing
+    /// it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+}
+
+
+impl<'a, T: ?Sized + 'a> AsRef<T> for ThunkRef<'a, T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<'a, T: ?Sized + 'a> Borrow<T> for ThunkRef<'a, T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+
+impl<'a, T: ?Sized + 'a> Deref for ThunkRef<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        *self.0
+    }
+}
+
+
+impl<'a, T: ?Sized + 'a> From<&'a T> for ThunkRef<'a, T> {
+    #[inline]
+    fn from(t: &'a T) -> ThunkRef<'a, T> {
+        ThunkRef::computed(t)
+    }
+}
+
+
+/// A non-thread-safe thunk with a possibly unsized target, such as `str`,
+/// `[T]`, or a trait object. The computed value is stored boxed, and the
+/// `UnsizedThunk` dereferences to the unsized value behind the box.
+pub struct UnsizedThunk<T: ?Sized>(Thunk<Box<T>>);
+
+
+impl<T: ?Sized> UnsizedThunk<T> {
+    /// Construct an `UnsizedThunk` from an already computed, boxed value.
+    #[inline]
+    pub fn computed(t: Box<T>) -> UnsizedThunk<T> {
+        UnsizedThunk(Thunk::computed(t))
+    }
+
+
+    /// Defer the computation of a boxed value.
+    #[inline]
+    pub fn defer<'a, F: FnOnce() -> Box<T> + 'a>(f: F) -> UnsizedThunk<T>
+        where T: 'a
+    {
+        UnsizedThunk(Thunk::defer(f))
+    }
+
+
+    /// Manually force the computation, returning a reference to the value.
+    #[inline]
+    pub fn force(&self) -> &T {
+        self.0.force()
+    }
+
+
+    /// Manually force the computation, returning a mutable reference to the
+    /// value.
+    #[inline]
+    pub fn force_mut(&mut self) -> &mut T {
+        self.0.force_mut()
+    }
+
+
+    /// Check whether the value has already been computed, without forcing it.
+    #[inline]
+    pub fn is_evaluated(&self) -> bool {
+        self.0.is_evaluated()
+    }
+
+
+    /// Force the thunk and take ownership of the boxed value.
+    #[inline]
+    pub fn unwrap(self) -> Box<T> {
+        self.0.unwrap()
+    }
+}
+
+
+impl<T: ?Sized> AsRef<T> for UnsizedThunk<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T: ?Sized> AsMut<T> for UnsizedThunk<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<T: ?Sized> Borrow<T> for UnsizedThunk<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+
+impl<T: ?Sized> BorrowMut<T> for UnsizedThunk<T> {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+
+impl<T: ?Sized> Deref for UnsizedThunk<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
+
+
+impl<T: ?Sized> DerefMut for UnsizedThunk<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        self.force_mut()
+    }
+}
+
+
+impl<T: ?Sized> From<Box<T>> for UnsizedThunk<T> {
+    #[inline]
+    fn from(t: Box<T>) -> UnsizedThunk<T> {
+        UnsizedThunk::computed(t)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(not(feature = "std"))]
+    use std::{boxed::Box, string::{String, ToString}, vec::Vec};
+    use std::hint::black_box;
+    #[cfg(feature = "nightly")]
+    use test::Bencher;
+
+    #[test]
+    fn thunk_computed() {
+        let thunk = Thunk::computed(1 + 1);
+
+        assert_eq!(*thunk, 2);
+    }
+
+    #[test]
+    fn thunk_computed_const() {
+        const fn two() -> Thunk<u32> {
+            Thunk::computed(2)
+        }
+
+        let mut thunk = two();
+        *thunk += 1;
+
+        assert!(two().is_evaluated());
+        assert_eq!(*two(), 2);
+        assert_eq!(*thunk, 3);
+    }
+
+    #[test]
+    fn thunk_get_pin_mut() {
+        use std::marker::PhantomPinned;
+        use std::pin::pin;
+
+        struct Counter(u32, PhantomPinned);
+
+        impl Counter {
+            fn bump(self: Pin<&mut Self>) -> u32 {
+                let counter = unsafe { self.get_unchecked_mut() };
+                counter.0 += 1;
+                counter.0
+            }
+        }
+
+        let mut thunk = pin!(Thunk::defer(|| Counter(black_box(0), PhantomPinned)));
+
+        assert!(thunk.as_mut().try_get_pin_mut().is_none());
+        assert_eq!(thunk.as_mut().get_pin_mut().bump(), 1);
+        assert_eq!(thunk.as_mut().try_get_pin_mut().map(Counter::bump), Some(2));
+        assert_eq!(thunk.0, 2);
+
+        let mut unpin = Thunk::defer(|| 1);
+        *Pin::new(&mut unpin).get_pin_mut() += 1;
+        assert_eq!(*unpin, 2);
+    }
+
+    #[test]
+    fn thunk_deferred() {
+        let thunk = Thunk::defer(|| black_box(1) + 1);
+
+        assert_eq!(*thunk, 2);
+    }
+
+    #[test]
+    fn thunk_is_evaluated() {
+        let thunk = Thunk::defer(|| black_box(1) + 1);
+
+        assert!(!thunk.is_evaluated());
+        thunk.force();
+        assert!(thunk.is_evaluated());
+        assert!(Thunk::computed(2).is_evaluated());
+    }
+
+    #[test]
+    fn thunk_try_get() {
+        let mut thunk = Thunk::defer(|| black_box(1) + 1);
+
+        assert_eq!(thunk.try_get(), None);
+        assert_eq!(thunk.try_get_mut(), None);
+        thunk.force();
+        *thunk.try_get_mut().unwrap() += 1;
+        assert_eq!(thunk.try_get(), Some(&3));
+    }
+
+    #[test]
+    fn thunk_into_deferred() {
+        let thunk = Thunk::defer(|| black_box(1) + 1);
+        let closure = thunk.into_deferred().ok().unwrap();
+
+        assert_eq!(closure(), 2);
+        assert_eq!(Thunk::computed(2).into_deferred().err(), Some(2));
+    }
+
+    #[cfg(feature = "nightly")]
+    fn ten_thousand_xors_strict(n: usize) -> Thunk<usize> {
+        Thunk::computed((0..black_box(10000)).fold(black_box(n), |old, new| old ^ new))
+    }
+
+    #[cfg(feature = "nightly")]
+    fn ten_thousand_xors_lazy(n: usize) -> Thunk<usize> {
+        Thunk::defer(move || {
+                         (0..black_box(10000)).fold(black_box(n), |old, new| old ^ new)
+                     })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn ten_thousand_xors_threadsafe_strict(b: &mut Bencher) {
+        b.iter(|| {
+                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_strict).collect();
+                   black_box(things.pop())
+               })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn ten_thousand_xors_threadsafe_lazy(b: &mut Bencher) {
+        b.iter(|| {
+                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_lazy).collect();
+                   black_box(things.pop())
+               })
+    }
+
+    #[test]
+    fn rc_thunk_computed() {
+        let rc_thunk0 = RcThunk::computed(1 + 1);
+        let rc_thunk1 = rc_thunk0.clone();
+
+        assert_eq!(rc_thunk0.0.flag.get(), Flag::Evaluated);
+        assert_eq!(&*rc_thunk1, &2);
+        assert_eq!(rc_thunk0.0.flag.get(), Flag::Evaluated);
+        assert_eq!(&*rc_thunk0, &2);
+    }
+
+    #[test]
+    fn rc_thunk_deferred() {
+        let rc_thunk0 = RcThunk::defer(move || black_box(1) + 1);
+        let rc_thunk1 = rc_thunk0.clone();
+
+        assert_eq!(rc_thunk0.0.flag.get(), Flag::Deferred);
+        assert_eq!(&*rc_thunk1, &2);
+        assert_eq!(rc_thunk0.0.flag.get(), Flag::Evaluated);
+        assert_eq!(&*rc_thunk0, &2);
+    }
+
+    #[test]
+    fn rc_thunk_lazy_shared() {
+        fn bump<L: LazyShared<Target = u32>>(thunk: &mut L) -> u32
+            where u32: Into<L>
+        {
+            *L::make_mut(thunk) += 1;
+            **thunk
+        }
+
+        let mut thunk0 = RcThunk::defer(|| black_box(1) + 1);
+        let thunk1 = thunk0.clone();
+
+        assert!(LazyShared::ptr_eq(&thunk0, &thunk1));
+        assert_eq!(bump(&mut thunk0), 3);
+        assert!(!LazyShared::ptr_eq(&thunk0, &thunk1));
+        assert_eq!(LazyShared::try_unwrap(thunk1).ok(), Some(2));
+    }
+
+    #[test]
+    fn rc_thunk_downgrade() {
+        let thunk = RcThunk::defer(|| black_box(1) + 1);
+        let weak = RcThunk::downgrade(&thunk);
+
+        assert_eq!(weak.upgrade().map(|thunk| *thunk), Some(2));
+        assert!(thunk.is_evaluated());
+
+        drop(thunk);
+        assert!(weak.upgrade().is_none());
+        assert!(WeakRcThunk::<u32>::new().upgrade().is_none());
+    }
+
+    #[test]
+    fn rc_thunk_ptr_eq() {
+        let thunk0 = RcThunk::defer(|| black_box(1) + 1);
+        let thunk1 = thunk0.clone();
+        let thunk2 = RcThunk::computed(2);
+
+        assert!(RcThunk::ptr_eq(&thunk0, &thunk1));
+        assert!(!RcThunk::ptr_eq(&thunk0, &thunk2));
+        assert!(!thunk0.is_evaluated());
+    }
+
+    #[test]
+    fn rc_thunk_project() {
+        let forced = Rc::new(Cell::new(0));
+        let thunk = {
+            let forced = forced.clone();
+            RcThunk::defer(move || {
+                forced.set(forced.get() + 1);
+                (vec![1, 2, 3], String::from("abc"))
+            })
+        };
+
+        let numbers = RcThunk::project(&thunk, |pair| &pair.0[..]);
+        let letters = RcThunk::project(&thunk, |pair| &pair.1[..]);
+        let last = RcThunkRef::project(&numbers, |numbers| &numbers[2]);
+        drop(thunk);
+
+        assert!(!last.is_evaluated());
+        assert_eq!(*last, 3);
+        assert_eq!(&*numbers, &[1, 2, 3]);
+        assert_eq!(&*letters.clone(), "abc");
+        assert_eq!(forced.get(), 1);
+    }
+
+    #[test]
+    fn rc_thunk_recursive() {
+        struct Stream {
+            head: u64,
+            tail: Option<RcThunk<Stream>>,
+        }
+
+        fn fibs(a: u64, b: u64) -> RcThunk<Stream> {
+            RcThunk::defer(move || Stream { head: a, tail: Some(fibs(b, a + b)) })
+        }
+
+        struct Cycle(WeakRcThunk<Cycle>);
+
+        let cycle = RcThunk::recursive(Cycle);
+        let Cycle(ref this) = *cycle;
+        assert!(RcThunk::ptr_eq(&cycle, &this.upgrade().unwrap()));
+
+        let thunk = RcThunk::recursive(|this: WeakRcThunk<Stream>| {
+            assert!(this.upgrade().is_some());
+            Stream { head: 0, tail: Some(fibs(1, 1)) }
+        });
+
+        let mut stream = &thunk;
+        let mut taken = Vec::new();
+        for _ in 0..6 {
+            taken.push(stream.head);
+            stream = stream.tail.as_ref().unwrap();
+        }
+        assert_eq!(taken, vec![0, 1, 1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn rc_thunk_counts() {
+        let mut thunk0 = RcThunk::defer(|| black_box(1) + 1);
+        assert!(RcThunk::is_unique(&mut thunk0));
+
+        let thunk1 = thunk0.clone();
+        let weak = RcThunk::downgrade(&thunk0);
+
+        assert_eq!(RcThunk::strong_count(&thunk0), 2);
+        assert_eq!(RcThunk::weak_count(&thunk0), 1);
+        assert!(!RcThunk::is_unique(&mut thunk0));
+
+        drop(thunk1);
+        assert!(!RcThunk::is_unique(&mut thunk0));
+
+        drop(weak);
+        assert!(RcThunk::is_unique(&mut thunk0));
+        assert!(!thunk0.is_evaluated());
+    }
+
+    #[test]
+    fn rc_thunk_into_raw() {
+        let forced = Rc::new(Cell::new(0));
+        let counter = forced.clone();
+
+        let thunk0 = RcThunk::defer_cloneable(move || {
+            counter.set(counter.get() + 1);
+            vec![1, 2, 3]
+        });
+        let thunk1 = thunk0.clone();
+
+        // The pointer is thin, so it survives a trip through `c_void`.
+        let raw = RcThunk::into_raw(thunk0) as *const ::core::ffi::c_void;
+        assert_eq!(RcThunk::strong_count(&thunk1), 2);
+
+        let mut thunk0 = unsafe { RcThunk::<Vec<i32>>::from_raw(raw as *const Vec<i32>) };
+        assert!(RcThunk::ptr_eq(&thunk0, &thunk1));
+        assert!(!thunk0.is_evaluated());
+
+        RcThunk::make_mut(&mut thunk0).push(4);
+        assert!(!thunk1.is_evaluated());
+        assert_eq!(*thunk0, vec![1, 2, 3, 4]);
+        assert_eq!(forced.get(), 1);
+
+        let raw = RcThunk::into_raw(RcThunk::from(3));
+        assert_eq!(unsafe { *raw }, 3);
+        drop(unsafe { RcThunk::from_raw(raw) });
+    }
+
+    #[test]
+    fn rc_thunk_make_mut_cloneable() {
+        let forced = Rc::new(Cell::new(0));
+        let counter = forced.clone();
+
+        let mut thunk0 = RcThunk::defer_cloneable(move || {
+            counter.set(counter.get() + 1);
+            vec![1, 2, 3]
+        });
+        let thunk1 = thunk0.clone();
+
+        RcThunk::make_mut(&mut thunk0).push(4);
+
+        assert!(!RcThunk::ptr_eq(&thunk0, &thunk1));
+        assert!(!thunk1.is_evaluated());
+        assert_eq!(forced.get(), 1);
+        assert_eq!(*thunk0, vec![1, 2, 3, 4]);
+        assert_eq!(*thunk1, vec![1, 2, 3]);
+        assert_eq!(forced.get(), 2);
+    }
+
+    #[test]
+    fn rc_thunk_make_mut_cloneable_borrowed() {
+        let forced = Cell::new(0);
+        let base = vec![1, 2, 3];
+
+        let mut thunk0 = RcThunk::defer_cloneable(|| {
+            forced.set(forced.get() + 1);
+            base.clone()
+        });
+        let thunk1 = thunk0.clone();
+
+        RcThunk::make_mut(&mut thunk0).push(4);
+
+        assert!(!thunk1.is_evaluated());
+        assert_eq!(*thunk0, vec![1, 2, 3, 4]);
+        assert_eq!(*thunk1, vec![1, 2, 3]);
+        assert_eq!(forced.get(), 2);
+        assert_eq!(mem::size_of::<RcThunk<Vec<i32>>>(), mem::size_of::<Rc<dyn Fn()>>());
+    }
+
+    #[test]
+    fn rc_thunk_large_closure() {
+        let counter = Rc::new(());
+        let captured = counter.clone();
+        let big = [7usize; 16];
+
+        let thunk0 = RcThunk::defer(move || big.iter().sum::<usize>());
+        let thunk1 = RcThunk::defer(move || (captured, big));
+        let weak = RcThunk::downgrade(&thunk1);
+
+        assert_eq!(*thunk0, 112);
+        assert_eq!(Rc::strong_count(&counter), 2);
+        drop(thunk1);
+        assert_eq!(Rc::strong_count(&counter), 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn rc_thunk_try_unwrap_with_weak() {
+        let thunk = RcThunk::defer(|| String::from("value"));
+        let weak = RcThunk::downgrade(&thunk);
+
+        assert_eq!(RcThunk::try_unwrap(thunk).ok(), Some(String::from("value")));
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn rc_thunk_reentrant_force() {
+        let cell: Rc<Cell<Option<RcThunk<u32>>>> = Rc::new(Cell::new(None));
+        let captured = cell.clone();
+        let thunk = RcThunk::defer(move || {
+            let thunk = captured.take().unwrap();
+            *thunk + 1
+        });
+
+        cell.set(Some(thunk.clone()));
+        thunk.force();
+    }
+
+    #[test]
+    fn thunk_ref_deferred() {
+        let arena = [String::from("foo"), String::from("bar")];
+        let thunk_ref: ThunkRef<str> = ThunkRef::defer(|| &arena[black_box(1)][..]);
+
+        assert!(!thunk_ref.is_evaluated());
+        assert_eq!(&*thunk_ref, "bar");
+        assert!(thunk_ref.is_evaluated());
+    }
+
+    #[test]
+    fn thunk_try_into_inner() {
+        let thunk = Thunk::defer(|| black_box(1) + 1);
+        let thunk = thunk.try_into_inner().err().unwrap();
+
+        assert!(!thunk.is_evaluated());
+        thunk.force();
+        assert_eq!(thunk.try_into_inner().ok(), Some(2));
+    }
+
+    #[test]
+    fn thunk_set_replace() {
+        let mut thunk = Thunk::defer(|| -> usize { panic!("should never be forced") });
+
+        assert_eq!(thunk.replace(1), None);
+        assert_eq!(thunk.replace(2), Some(1));
+        thunk.set(3);
+        assert_eq!(*thunk, 3);
+    }
+
+    #[test]
+    fn thunk_take_refill() {
+        let mut thunk = Thunk::defer(|| black_box(1) + 1);
+
+        assert_eq!(Thunk::take(&mut thunk).unwrap().ok().unwrap()(), 2);
+        assert!(Thunk::is_empty(&thunk));
+        assert!(Thunk::take(&mut thunk).is_none());
+        thunk.refill_computed(3);
+        assert_eq!(Thunk::take(&mut thunk).unwrap().err(), Some(3));
+        thunk.refill_deferred(|| 4);
+        assert_eq!(*thunk, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty `Thunk`")]
+    fn thunk_take_deref_panics() {
+        let mut thunk = Thunk::computed(1);
+
+        Thunk::take(&mut thunk);
+        let _ = *thunk;
+    }
+
+    #[test]
+    fn thunk_take_and_is_empty_do_not_shadow() {
+        let vec = Thunk::defer(Vec::<u32>::new);
+        let mut option = Thunk::computed(Some(1));
+
+        assert!(vec.is_empty());
+        assert!(!Thunk::is_empty(&vec));
+        assert_eq!(option.take(), Some(1));
+        assert_eq!(*option, None);
+    }
+
+    #[test]
+    fn thunk_panic_poisons() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let captured = Rc::new(());
+        let thunk = {
+            let captured = captured.clone();
+            Thunk::<u32>::defer(move || {
+                let _captured = captured;
+                panic!("boom")
+            })
+        };
+
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| *thunk)).is_err());
+        assert_eq!(Rc::strong_count(&captured), 1);
+        assert!(!thunk.is_evaluated());
+        assert_eq!(thunk.try_get(), None);
+
+        let again = panic::catch_unwind(AssertUnwindSafe(|| *thunk)).unwrap_err();
+        assert_eq!(again.downcast_ref::<&str>(),
+                   Some(&"attempted to force a `Thunk` whose computation panicked"));
+
+        mem::drop(thunk);
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn thunk_self_force_panics() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let thunk = Rc::new_cyclic(|this: &Weak<Thunk<u32>>| {
+            let this = this.clone();
+            Thunk::defer(move || *this.upgrade().unwrap().force() + 1)
+        });
+
+        let looped = panic::catch_unwind(AssertUnwindSafe(|| **thunk)).unwrap_err();
+        assert!(looped.downcast_ref::<&str>().unwrap().starts_with("<<loop>>"));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| **thunk)).is_err());
+    }
+
+    #[test]
+    fn thunk_force_returns_value() {
+        let thunk = Thunk::defer(|| black_box(1) + 1);
+
+        assert_eq!(thunk.force(), &2);
+        assert_eq!(RcThunk::defer(|| black_box(1) + 1).force(), &2);
+    }
+
+    #[test]
+    fn thunk_unchecked_access() {
+        let thunk = Thunk::defer(|| black_box(1) + 1);
+
+        thunk.force();
+        assert_eq!(unsafe { thunk.get_unchecked() }, &2);
+        assert_eq!(unsafe { thunk.assume_evaluated() }, 2);
+    }
+
+    #[test]
+    fn thunk_modify() {
+        let mut thunk = Thunk::defer(|| black_box(1) + 1);
+
+        thunk.modify(|x| x * 10);
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 20);
+        thunk.modify(|x| x + 1);
+        assert_eq!(thunk.try_get(), Some(&21));
+    }
+
+    #[test]
+    fn thunk_map() {
+        let thunk = Thunk::defer(|| black_box(1) + 1).map(|x| x * 10);
+
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 20);
+        assert_eq!(*Thunk::computed(2).map(|x| x + 1), 3);
+
+        let rc_thunk = RcThunk::defer(|| black_box(1) + 1);
+        let mapped = RcThunk::map(&rc_thunk, |x| x * 10);
+
+        assert_eq!(*mapped, 20);
+        assert!(rc_thunk.is_evaluated());
+    }
+
+    #[test]
+    fn thunk_and_then_flatten() {
+        let nested = Thunk::defer(|| Thunk::defer(|| black_box(1) + 1));
+
+        assert_eq!(*nested.flatten(), 2);
+
+        let chained = Thunk::defer(|| black_box(1) + 1)
+            .and_then(|x| Thunk::defer(move || x * 10));
+
+        assert!(!chained.is_evaluated());
+        assert_eq!(*chained, 20);
+    }
+
+    #[test]
+    fn thunk_zip() {
+        let a = Thunk::defer(|| black_box(1) + 1);
+        let b = Thunk::defer(|| "two");
+        let zipped = a.zip(b);
+
+        assert!(!zipped.is_evaluated());
+        assert_eq!(*zipped, (2, "two"));
+
+        let summed = Thunk::computed(1).zip_with(Thunk::defer(|| 2), |a, b| a + b);
+
+        assert_eq!(*summed, 3);
+    }
+
+    #[test]
+    fn thunk_lazy_ext() {
+        use std::cell::Cell;
+
+        use crate::LazyExt;
+
+        let seen = Cell::new(None);
+        let thunk = Thunk::defer(|| black_box(1) + 1).inspect(|x| seen.set(Some(*x)));
+
+        thunk.tap_forced(|_| panic!("should not run before forcing"));
+        assert_eq!(seen.get(), None);
+        assert_eq!(*thunk, 2);
+        assert_eq!(seen.get(), Some(2));
+
+        let mut tapped = 0;
+        thunk.tap_forced(|x| tapped = *x);
+        assert_eq!(tapped, 2);
+
+        assert!(thunk.map_into::<RcThunk<_>>().is_evaluated());
+        assert!(!Thunk::defer(|| black_box(1)).map_into::<RcThunk<_>>().is_evaluated());
+    }
+
+    #[test]
+    fn thunk_cell_reuse() {
+        let mut cell: ThunkCell<i32> = ThunkCell::new();
+        let mut total = 0;
+
+        assert!(ThunkCell::is_empty(&cell));
+        for i in 0..4 {
+            cell.redefer(move || black_box(i) * 10);
+            assert!(!cell.is_evaluated());
+            total += ThunkCell::take(&mut cell).unwrap();
+            assert!(ThunkCell::is_empty(&cell));
+        }
+
+        assert_eq!(total, 60);
+        ThunkCell::set(&mut cell, 1);
+        assert_eq!(*cell, 1);
+        cell.redefer(|| 2);
+        assert_eq!(cell.unwrap(), 2);
+    }
+
+    #[test]
+    fn thunk_cell_does_not_shadow() {
+        let mut cell = ThunkCell::from(vec![1, 2]);
+
+        cell.clear();
+        assert!(cell.is_empty());
+        assert!(!ThunkCell::is_empty(&cell));
+
+        cell.push(3);
+        ThunkCell::clear(&mut cell);
+        assert!(ThunkCell::is_empty(&cell));
+    }
+
+    #[test]
+    fn late_thunk_get_or_init() {
+        let late: LateThunk<i32> = LateThunk::new();
+
+        assert!(!late.is_initialized());
+        assert_eq!(late.get(), None);
+        assert_eq!(late.get_or_init(|| black_box(1) + 1), &2);
+        assert_eq!(late.get_or_init(|| 3), &2);
+        assert_eq!(late.set(4), Err(4));
+
+        let deferred = LateThunk::defer(|| 5);
+
+        assert_eq!(deferred.set(6), Err(6));
+        assert_eq!(deferred.get_or_init(|| 7), &5);
+
+        let set: LateThunk<i32> = LateThunk::new();
+
+        assert_eq!(set.set(8), Ok(()));
+        assert_eq!(set.unwrap(), 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "uninitialized `LateThunk`")]
+    fn late_thunk_force_uninitialized() {
+        let late: LateThunk<i32> = LateThunk::new();
+
+        late.force();
+    }
+
+    #[test]
+    fn inline_thunk_deferred() {
+        let thunk = InlineThunk::defer(|| black_box(1) + 1);
+
+        assert!(!thunk.is_evaluated());
+        assert_eq!(thunk.try_get(), None);
+        assert_eq!(*thunk, 2);
+        assert_eq!(thunk.unwrap(), 2);
+        assert!(mem::size_of::<InlineThunk<u64, fn() -> u64>>() <= 2 * mem::size_of::<u64>());
+    }
+
+    #[cfg(feature = "nightly")]
+    fn ten_thousand_xors_inline(n: usize) -> InlineThunk<usize, impl FnOnce() -> usize> {
+        InlineThunk::defer(move || {
+                               (0..black_box(10000))
+                                   .fold(black_box(n), |old, new| old ^ new)
+                           })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn ten_thousand_xors_inline_lazy(b: &mut Bencher) {
+        b.iter(|| {
+                   let mut things: Vec<_> = (0..1000).map(ten_thousand_xors_inline).collect();
+                   black_box(things.pop())
+               })
+    }
+
+    #[test]
+    fn thunk_deferred_large_closure() {
+        let big = [1usize; 8];
+        let thunk = Thunk::defer(move || big.iter().sum::<usize>());
+        assert!(!thunk.is_evaluated());
+        assert_eq!(*thunk, 8);
+    }
+
+    #[test]
+    fn thunk_drop_unforced_closures() {
+        let counter = Rc::new(());
+        let small = counter.clone();
+        let big = (counter.clone(), [0usize; 8]);
+
+        mem::drop(Thunk::defer(move || Rc::strong_count(&small)));
+        mem::drop(Thunk::defer(move || Rc::strong_count(&big.0) + big.1.len()));
+
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn thunk_option_is_free() {
+        assert_eq!(mem::size_of::<Option<Thunk<u64>>>(), mem::size_of::<Thunk<u64>>());
+        assert_eq!(mem::size_of::<Option<Thunk<String>>>(), mem::size_of::<Thunk<String>>());
+        assert_eq!(mem::size_of::<Option<LateThunk<u8>>>(), mem::size_of::<LateThunk<u8>>());
+        assert_eq!(mem::size_of::<Option<InlineThunk<u64, fn() -> u64>>>(),
+                   mem::size_of::<InlineThunk<u64, fn() -> u64>>());
+    }
+
+    #[test]
+    fn thunk_drops_in_every_state() {
+        let counter = Rc::new(());
+
+        let deferred = {
+            let captured = counter.clone();
+            Thunk::defer(move || captured)
+        };
+        let evaluated = {
+            let captured = counter.clone();
+            Thunk::defer(move || captured)
+        };
+        evaluated.force();
+        let mut taken = Thunk::computed(counter.clone());
+        let value = Thunk::take(&mut taken);
+
+        assert_eq!(Rc::strong_count(&counter), 4);
+
+        mem::drop((deferred, evaluated, taken, value));
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn thunk_take_large_closure() {
+        let big = [2usize; 8];
+        let mut thunk = Thunk::defer(move || big.iter().sum::<usize>());
+        let closure = Thunk::take(&mut thunk).unwrap().ok().unwrap();
+        assert_eq!(closure(), 16);
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn defer_small_closures(b: &mut Bencher) {
+        b.iter(|| {
+                   let things: Vec<_> = (0..1000usize)
+                       .map(|i| Thunk::defer(move || black_box(i) + 1))
+                       .collect();
+                   black_box(things)
+               })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn defer_large_closures(b: &mut Bencher) {
+        b.iter(|| {
+                   let things: Vec<_> = (0..1000usize)
+                       .map(|i| Thunk::defer(move || black_box([i; 4]).len()))
+                       .collect();
+                   black_box(things)
+               })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn rc_thunk_large_closures(b: &mut Bencher) {
+        b.iter(|| {
+                   let things: Vec<_> = (0..1000usize)
+                       .map(|i| RcThunk::defer(move || black_box([i; 4]).len()))
+                       .collect();
+                   black_box(things.iter().map(|thing| **thing).sum::<usize>())
+               })
+    }
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn rc_of_thunk_large_closures(b: &mut Bencher) {
+        b.iter(|| {
+                   let things: Vec<_> = (0..1000usize)
+                       .map(|i| Rc::new(Thunk::defer(move || black_box([i; 4]).len())))
+                       .collect();
+                   black_box(things.iter().map(|thing| ***thing).sum::<usize>())
+               })
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn thunk_defer_in_allocator() {
+        use std::alloc::{AllocError, Allocator, Global, Layout};
+        use std::ptr::NonNull;
+
+        struct Counting<'a>(&'a Cell<usize>);
+
+        unsafe impl<'a> Allocator for Counting<'a> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.set(self.0.get() - 1);
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let live = Cell::new(0);
+        let big = [4usize; 8];
+
+        let thunk = Thunk::defer_in(move || big.iter().sum::<usize>(), Counting(&live));
+        assert_eq!(live.get(), 1);
+        assert_eq!(*thunk, 32);
+        assert_eq!(live.get(), 0);
+
+        let small = Thunk::defer_in(|| 1 + 1, Counting(&live));
+        assert_eq!(live.get(), 0);
+        assert_eq!(*small, 2);
+
+        mem::drop(Thunk::defer_in(move || big.len(), Counting(&live)));
+        assert_eq!(live.get(), 0);
+    }
+
+    #[test]
+    fn unsized_thunk_str() {
+        let thunk: UnsizedThunk<str> =
+            UnsizedThunk::defer(|| String::from("hello").into_boxed_str());
+        assert!(!thunk.is_evaluated());
+        assert_eq!(&*thunk, "hello");
+        assert_eq!(thunk.len(), 5);
+    }
+
+    #[test]
+    fn unsized_thunk_trait_object() {
+        use std::fmt::Display;
+
+        let mut slice: UnsizedThunk<[u8]> = UnsizedThunk::defer(|| vec![1, 2, 3].into_boxed_slice());
+        slice[0] = 4;
+        assert_eq!(&*slice, &[4, 2, 3]);
+
+        let display: UnsizedThunk<dyn Display> = UnsizedThunk::defer(|| Box::new(42) as Box<dyn Display>);
+        assert_eq!(display.to_string(), "42");
     }
 }
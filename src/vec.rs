@@ -0,0 +1,434 @@
+//! Dense vectors of thunks. A `ThunkVec` stores each element's deferred
+//! closure or computed value in place, in one contiguous buffer, and keeps the
+//! evaluation state of every element in packed bitmaps rather than in a flag
+//! per element.
+
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+
+use crate::erased::ErasedClosure;
+
+
+const BITS: usize = mem::size_of::<usize>() * 8;
+
+
+/// A non-thread-safe, growable vector of lazily computed values.
+pub struct ThunkVec<T> {
+    slots: Vec<UnsafeCell<MaybeUninit<Slot<T>>>>,
+
+    /// One bit per element, set once the element has been computed.
+    evaluated: Vec<Cell<usize>>,
+
+    /// One bit per element, set while the element is being computed, and left
+    /// set if its computation panics.
+    evaluating: Vec<Cell<usize>>,
+}
+
+
+/// The storage of a single element. The `deferred` field is initialized if
+/// neither of the element's bits is set, and the `evaluated` field if its
+/// `evaluated` bit is; otherwise the slot is uninitialized.
+union Slot<T> {
+    deferred: ManuallyDrop<ErasedClosure<T>>,
+    evaluated: ManuallyDrop<T>,
+}
+
+
+/// An iterator over the elements of a `ThunkVec` which does not force them,
+/// yielding `None` for elements which have not been computed yet.
+pub struct Iter<'a, T: 'a> {
+    vec: &'a ThunkVec<T>,
+    idx: usize,
+}
+
+
+#[inline]
+fn get_bit(map: &[Cell<usize>], idx: usize) -> bool {
+    map[idx / BITS].get() & (1 << (idx % BITS)) != 0
+}
+
+
+#[inline]
+fn set_bit(map: &[Cell<usize>], idx: usize, value: bool) {
+    let word = &map[idx / BITS];
+
+    if value {
+        word.set(word.get() | (1 << (idx % BITS)));
+    } else {
+        word.set(word.get() & !(1 << (idx % BITS)));
+    }
+}
+
+
+impl<T> Drop for ThunkVec<T> {
+    fn drop(&mut self) {
+        for idx in 0..self.slots.len() {
+            if get_bit(&self.evaluated, idx) {
+                mem::drop(unsafe { ManuallyDrop::into_inner(self.take_slot(idx).evaluated) });
+            } else if !get_bit(&self.evaluating, idx) {
+                mem::drop(unsafe { ManuallyDrop::into_inner(self.take_slot(idx).deferred) });
+            }
+        }
+    }
+}
+
+
+impl<T> Default for ThunkVec<T> {
+    #[inline]
+    fn default() -> ThunkVec<T> {
+        ThunkVec::new()
+    }
+}
+
+
+impl<T> ThunkVec<T> {
+    /// Create an empty `ThunkVec`.
+    #[inline]
+    pub fn new() -> ThunkVec<T> {
+        ThunkVec::with_capacity(0)
+    }
+
+
+    /// Create an empty `ThunkVec` with space for at least `capacity`
+    /// elements.
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> ThunkVec<T> {
+        let words = capacity.div_ceil(BITS);
+
+        ThunkVec {
+            slots: Vec::with_capacity(capacity),
+            evaluated: Vec::with_capacity(words),
+            evaluating: Vec::with_capacity(words),
+        }
+    }
+
+
+    /// Append an already computed value.
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        self.push_slot(Slot { evaluated: ManuallyDrop::new(value) }, true);
+    }
+
+
+    /// Append a deferred computation.
+    #[inline]
+    pub fn push_deferred<'a, F: FnOnce() -> T + 'a>(&mut self, f: F)
+        where T: 'a
+    {
+        self.push_slot(Slot { deferred: ManuallyDrop::new(ErasedClosure::new(f)) }, false);
+    }
+
+
+    /// Get a reference to the element at `idx`, computing it if necessary.
+    ///
+    /// Panics if `idx` is out of bounds.
+    #[inline]
+    pub fn force(&self, idx: usize) -> &T {
+        match self.get(idx) {
+            Some(value) => value,
+            None => {
+                panic!("index {} out of bounds for a `ThunkVec` of length {}", idx, self.len())
+            }
+        }
+    }
+
+
+    /// Get a reference to the element at `idx`, computing it if necessary.
+    /// Returns `None` if `idx` is out of bounds.
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx >= self.slots.len() {
+            return None;
+        }
+
+        if !get_bit(&self.evaluated, idx) {
+            assert!(!get_bit(&self.evaluating, idx),
+                    "element {} of a `ThunkVec` was forced while it was already being forced, \
+                     or after forcing it panicked",
+                    idx);
+
+            set_bit(&self.evaluating, idx, true);
+
+            unsafe {
+                let slot = self.slot(idx);
+                let closure = ManuallyDrop::take(&mut (*slot).deferred);
+                slot.write(Slot { evaluated: ManuallyDrop::new(closure.call()) });
+            }
+
+            set_bit(&self.evaluating, idx, false);
+            set_bit(&self.evaluated, idx, true);
+        }
+
+        Some(unsafe { &(*self.slot(idx)).evaluated })
+    }
+
+
+    /// Get a mutable reference to the element at `idx`, computing it if
+    /// necessary. Returns `None` if `idx` is out of bounds.
+    #[inline]
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        self.get(idx)?;
+        Some(unsafe { &mut (*self.slot(idx)).evaluated })
+    }
+
+
+    /// Get a reference to the element at `idx` only if it has already been
+    /// computed.
+    #[inline]
+    pub fn try_get(&self, idx: usize) -> Option<&T> {
+        if idx < self.slots.len() && get_bit(&self.evaluated, idx) {
+            Some(unsafe { &(*self.slot(idx)).evaluated })
+        } else {
+            None
+        }
+    }
+
+
+    /// Check whether the element at `idx` has been computed. Returns `false`
+    /// if `idx` is out of bounds.
+    #[inline]
+    pub fn is_evaluated(&self, idx: usize) -> bool {
+        idx < self.slots.len() && get_bit(&self.evaluated, idx)
+    }
+
+
+    /// Compute every element in `range`.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn force_range<R: RangeBounds<usize>>(&self, range: R) {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        };
+
+        assert!(start <= end && end <= self.len(),
+                "range {}..{} out of bounds for a `ThunkVec` of length {}",
+                start,
+                end,
+                self.len());
+
+        let mut idx = start;
+
+        while idx < end {
+            // Whole words of already computed elements are skipped at once.
+            if idx % BITS == 0 && end - idx >= BITS && self.evaluated[idx / BITS].get() == !0 {
+                idx += BITS;
+                continue;
+            }
+
+            self.get(idx);
+            idx += 1;
+        }
+    }
+
+
+    /// The number of elements, computed or not.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+
+    /// Check whether there are no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+
+    /// Iterate over the elements without forcing them.
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { vec: self, idx: 0 }
+    }
+
+
+    /// Compute every element, and collect them into a `Vec`.
+    pub fn into_vec(mut self) -> Vec<T> {
+        self.force_range(..);
+
+        let values = (0..self.len()).map(|idx| unsafe { ManuallyDrop::into_inner(self.take_slot(idx).evaluated) }).collect();
+
+        // Every slot has been moved out of, so there is nothing left to drop.
+        for word in &mut self.evaluated {
+            *word.get_mut() = 0;
+        }
+        for word in &mut self.evaluating {
+            *word.get_mut() = !0;
+        }
+
+        values
+    }
+
+
+    fn push_slot(&mut self, slot: Slot<T>, evaluated: bool) {
+        let idx = self.slots.len();
+
+        if idx.is_multiple_of(BITS) {
+            self.evaluated.push(Cell::new(0));
+            self.evaluating.push(Cell::new(0));
+        }
+
+        self.slots.push(UnsafeCell::new(MaybeUninit::new(slot)));
+        set_bit(&self.evaluated, idx, evaluated);
+    }
+
+
+    /// A pointer to the slot of the element at `idx`, which holds whatever the
+    /// element's bits say it does.
+    #[inline]
+    fn slot(&self, idx: usize) -> *mut Slot<T> {
+        self.slots[idx].get() as *mut Slot<T>
+    }
+
+
+    /// PRECONDITION: the slot must be moved out of exactly once, and never
+    /// accessed again.
+    #[inline]
+    unsafe fn take_slot(&mut self, idx: usize) -> Slot<T> {
+        ptr::read(self.slot(idx))
+    }
+}
+
+
+impl<T> Extend<T> for ThunkVec<T> {
+    #[inline]
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = Option<&'a T>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Option<&'a T>> {
+        if self.idx < self.vec.len() {
+            self.idx += 1;
+            Some(self.vec.try_get(self.idx - 1))
+        } else {
+            None
+        }
+    }
+
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.vec.len() - self.idx;
+        (remaining, Some(remaining))
+    }
+}
+
+
+impl<'a, T> IntoIterator for &'a ThunkVec<T> {
+    type Item = Option<&'a T>;
+    type IntoIter = Iter<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+
+impl<T: fmt::Debug> fmt::Debug for ThunkVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::rc::Rc;
+
+    #[cfg(feature = "nightly")]
+    use std::hint::black_box;
+    #[cfg(feature = "nightly")]
+    use test::Bencher;
+
+    #[test]
+    fn thunk_vec_force() {
+        let mut vec = ThunkVec::new();
+
+        for i in 0..100 {
+            vec.push_deferred(move || i * 2);
+        }
+        vec.push(1000);
+
+        assert!(!vec.is_evaluated(10));
+        assert_eq!(*vec.force(10), 20);
+        assert!(vec.is_evaluated(10));
+        assert!(vec.is_evaluated(100));
+        assert_eq!(vec.get(101), None);
+        assert_eq!(vec.iter().filter(Option::is_some).count(), 2);
+    }
+
+
+    #[test]
+    fn thunk_vec_force_range() {
+        let mut vec = ThunkVec::new();
+
+        for i in 0..200 {
+            vec.push_deferred(move || i);
+        }
+
+        vec.force_range(64..130);
+
+        assert!(!vec.is_evaluated(63));
+        assert!(vec.is_evaluated(64));
+        assert!(vec.is_evaluated(129));
+        assert!(!vec.is_evaluated(130));
+
+        *vec.get_mut(0).unwrap() += 1;
+        assert_eq!(vec.into_vec().iter().sum::<usize>(), 19901);
+    }
+
+
+    #[test]
+    fn thunk_vec_drops_everything() {
+        let counter = Rc::new(());
+        let mut vec = ThunkVec::new();
+
+        for i in 0..10 {
+            let captured = counter.clone();
+            vec.push_deferred(move || (captured, i));
+        }
+        vec.push((counter.clone(), 10));
+
+        vec.force(3);
+        assert_eq!(Rc::strong_count(&counter), 12);
+
+        drop(vec);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+
+    #[cfg(feature = "nightly")]
+    #[bench]
+    fn thunk_vec_force_ten_thousand(b: &mut Bencher) {
+        b.iter(|| {
+                   let mut vec = ThunkVec::with_capacity(10000);
+
+                   for i in 0..10000usize {
+                       vec.push_deferred(move || black_box(i) ^ 0xff);
+                   }
+
+                   vec.force_range(..);
+                   black_box(vec)
+               })
+    }
+}